@@ -1,9 +1,14 @@
 #![no_main]
 #![no_std]
 
+#[cfg(feature = "fast-boot")]
+use core::ops::Range;
+
 use embassy_stm32::gpio::{Level, Speed};
 use embassy_time::Timer;
-use flash_lib::{self, MEMORY_MAPPED_FLASH_ADDRESS, OpiFlashMemory, SpiFlashMemory};
+use flash_lib::nucleo_h7s3l8::{self, FlashMemoryResources};
+use flash_lib::partition::FIRMWARE;
+use flash_lib::{FlashError, MEMORY_MAPPED_FLASH_ADDRESS, SpiFlashMemory};
 
 #[cfg(feature = "defmt")]
 use defmt::*;
@@ -14,20 +19,111 @@ use panic_probe as _;
 
 #[cortex_m_rt::entry]
 fn main() -> ! {
-    let r = flash_lib::init();
+    let r = nucleo_h7s3l8::init();
     let mut cor = cortex_m::Peripherals::take().unwrap();
 
-    let mut flash = SpiFlashMemory::new(r.flash_memory);
-
-    let mut flash = flash.into_octo();
-    flash.enable_mm();
+    let app_address = match map_flash(r.flash_memory) {
+        Ok(app_address) => app_address,
+        Err(_e) => {
+            #[cfg(feature = "defmt")]
+            error!("Failed to map external flash: {}", _e);
+            // Retry from a clean state rather than jumping into unmapped memory.
+            cortex_m::peripheral::SCB::sys_reset();
+        }
+    };
 
     unsafe {
-        // Set's the vector table offset register to the start of the flash memory.
-        cor.SCB.vtor.write(MEMORY_MAPPED_FLASH_ADDRESS);
-        // Bootload the flash memory by jumping to the start of the flash memory.
-        cortex_m::asm::bootload(MEMORY_MAPPED_FLASH_ADDRESS as *const u32);
+        // Set's the vector table offset register to the start of the application.
+        cor.SCB.vtor.write(app_address);
+        // Bootload the flash memory by jumping to the start of the application.
+        cortex_m::asm::bootload(app_address as *const u32);
+    }
+}
+
+/// Switch the external flash to OPI and map it at [`MEMORY_MAPPED_FLASH_ADDRESS`], falling back
+/// to SPI if the switch fails.
+///
+/// Returns the mapped address of the application.
+fn map_flash(r: FlashMemoryResources) -> Result<u32, FlashError> {
+    let mut flash = nucleo_h7s3l8::new_flash(r)?;
+    let app_offset = app_offset(&mut flash)?;
+    // The application runs from the mapped flash, so it must stay mapped and the driver, which
+    // owns the XSPI peripheral, must never be dropped.
+    match flash.into_octo() {
+        Ok(mut flash) => {
+            // SAFETY: the slice is dropped right away.
+            unsafe { flash.enable_mm()?.leak() };
+            core::mem::forget(flash);
+        }
+        Err(e) => {
+            #[cfg(feature = "defmt")]
+            warn!("Failed to switch to OPI, booting in SPI: {}", e);
+            let (mut flash, _) = e.flash.recover()?;
+            // SAFETY: the slice is dropped right away.
+            unsafe { flash.enable_mm()?.leak() };
+            core::mem::forget(flash);
+        }
+    }
+    Ok(MEMORY_MAPPED_FLASH_ADDRESS + app_offset)
+}
+
+/// The AXI SRAM the application's initial stack pointer must point into, the `RAM` region of the
+/// firmware's `memory.x`.
+#[cfg(feature = "fast-boot")]
+const APP_RAM: Range<u32> = 0x2400_0000..0x2400_0000 + 456 * 1024;
+
+/// Alignment of a vector table the VTOR accepts, the size of the STM32H7S's table rounded up to
+/// a power of two.
+#[cfg(feature = "fast-boot")]
+const VECTOR_TABLE_ALIGN: u32 = 1024;
+
+/// Offset of the application in the flash, taken from the fast boot register if it's enabled
+/// and points to a valid application, see [`is_app`].
+#[cfg(feature = "fast-boot")]
+fn app_offset(flash: &mut SpiFlashMemory) -> Result<u32, FlashError> {
+    let config = flash.read_fast_boot()?;
+    if !config.enabled {
+        return Ok(FIRMWARE.offset);
+    }
+    if !is_app(flash, config.start_address)? {
+        #[cfg(feature = "defmt")]
+        warn!(
+            "No application at fast boot address {:#x}, booting from the firmware partition",
+            config.start_address
+        );
+        return Ok(FIRMWARE.offset);
+    }
+    #[cfg(feature = "defmt")]
+    info!("Booting from fast boot address {:#x}", config.start_address);
+    Ok(config.start_address)
+}
+
+/// Whether the application's vector table could be at `offset`: in the firmware partition and
+/// aligned for the VTOR, with the initial stack pointer in [`APP_RAM`] and the reset vector in
+/// the firmware partition.
+#[cfg(feature = "fast-boot")]
+fn is_app(flash: &mut SpiFlashMemory, offset: u32) -> Result<bool, FlashError> {
+    let in_firmware = |offset: u32| (FIRMWARE.offset..FIRMWARE.end()).contains(&offset);
+    if !in_firmware(offset) || offset % VECTOR_TABLE_ALIGN != 0 {
+        return Ok(false);
     }
+    let mut vectors = [0; 8];
+    flash.read_memory(offset, &mut vectors)?;
+    let [sp, reset] = [0, 4].map(|i| u32::from_le_bytes(vectors[i..i + 4].try_into().unwrap()));
+    // The stack grows down from the initial stack pointer, so it may be the end of the RAM.
+    let sp_in_ram = APP_RAM.start < sp && sp <= APP_RAM.end;
+    // The reset vector is a Thumb function, with the lowest bit set.
+    let reset_in_firmware = reset & 1 == 1
+        && (reset & !1)
+            .checked_sub(MEMORY_MAPPED_FLASH_ADDRESS)
+            .is_some_and(in_firmware);
+    Ok(sp_in_ram && reset_in_firmware)
+}
+
+/// The application is the firmware partition.
+#[cfg(not(feature = "fast-boot"))]
+fn app_offset(_flash: &mut SpiFlashMemory) -> Result<u32, FlashError> {
+    Ok(FIRMWARE.offset)
 }
 
 #[unsafe(no_mangle)]
@@ -1,25 +1,34 @@
-//! This build script copies the `memory.x` file from the crate root into
-//! a directory where the linker can always find it at build time.
-//! For many projects this is optional, as the linker always searches the
-//! project root directory -- wherever `Cargo.toml` is. However, if you
-//! are using a workspace or have a more complicated build setup, this
-//! build script becomes required. Additionally, by requesting that
-//! Cargo re-run the build script whenever `memory.x` is changed,
-//! updating `memory.x` ensures a rebuild of the application with the
-//! new memory settings.
+//! This build script generates the `memory.x` file from the flash partition
+//! table in `flash-lib` and puts it into a directory where the linker can
+//! always find it at build time. The table is compiled into this script, so
+//! Cargo re-runs it whenever the table changes, and the application is
+//! relinked with the new layout.
 
 use std::{env, fs::File, io::Write, path::PathBuf};
 
+#[allow(dead_code)]
+#[path = "../flash-lib/src/partition/table.rs"]
+mod partition_table;
+
+use partition_table::{FIRMWARE, MODEL, Partition};
+
 fn main() {
     // Put `memory.x` in our output directory and ensure it's
     // on the linker search path.
     let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
     File::create(out.join("memory.x"))
         .unwrap()
-        .write_all(include_bytes!("memory.x"))
+        .write_all(memory_x().as_bytes())
         .unwrap();
     println!("cargo:rustc-link-search={}", out.display());
 
+    if xip() {
+        // `xip.x`, which `memory.x` includes, is in the crate root of `flash-lib`.
+        let flash_lib =
+            PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap()).join("../flash-lib");
+        println!("cargo:rustc-link-search={}", flash_lib.display());
+    }
+
     println!("cargo:rustc-link-arg-bins=-Tlink.x");
     println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
 
@@ -29,3 +38,56 @@ fn main() {
     println!("cargo:rustc-link-search=../model-lib/build");
     println!("cargo:rustc-link-search=../model-lib/build/third_party/CMSIS-NN");
 }
+
+/// Whether the `xip` feature is enabled.
+fn xip() -> bool {
+    env::var_os("CARGO_FEATURE_XIP").is_some()
+}
+
+/// The linker script, with the firmware executing from its partition of the memory mapped
+/// flash and the model data in the model partition. With the `xip` feature, the ITCM holds the
+/// code of `flash_lib::xip`.
+fn memory_x() -> String {
+    let (itcm, include_xip) = if xip() {
+        (
+            "
+    /* Address 0 is left out, as no function may be at the null address. */
+    ITCM  : ORIGIN = 0x00000004, LENGTH = 64K - 4",
+            "\nINCLUDE xip.x\n",
+        )
+    } else {
+        ("", "")
+    };
+    format!(
+        "\
+/* Generated by build.rs from flash-lib/src/partition/table.rs, edit the table instead. */
+MEMORY
+{{
+    /* To do quick tests of small programs running from the internal flash, which holds the
+       bootloader, use `FLASH : ORIGIN = 0x08000000, LENGTH = 64K` instead. */
+    {}
+    {}
+    RAM   : ORIGIN = 0x24000000, LENGTH =  456K{itcm}
+}}
+
+SECTIONS
+{{
+    .model_data : {{
+        . = ALIGN(32);
+    }} > MODEL_DATA
+}}
+{include_xip}",
+        region("FLASH", &FIRMWARE),
+        region("MODEL_DATA", &MODEL),
+    )
+}
+
+/// A memory region covering `partition` in the memory mapped flash.
+fn region(name: &str, partition: &Partition) -> String {
+    format!(
+        "{name} : ORIGIN = {:#010x}, LENGTH = {:#x} /* {} partition, XSPI2 */",
+        partition.mapped_address(),
+        partition.size,
+        partition.name,
+    )
+}
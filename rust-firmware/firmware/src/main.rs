@@ -39,6 +39,13 @@ unsafe extern "C" {
 
 #[embassy_executor::main]
 async fn main(_spawner: Spawner) {
+    // SAFETY: nothing in the ITCM has run yet, the embassy executor doesn't enter a critical
+    // section before the first task.
+    #[cfg(feature = "xip")]
+    unsafe {
+        flash_lib::xip::load_ram_code()
+    };
+
     let mut config = embassy_stm32::Config::default();
     {
         use embassy_stm32::rcc::*;
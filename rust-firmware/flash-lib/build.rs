@@ -1,5 +1,14 @@
+use std::env;
+
 fn main() {
+    // `flash_test.x` and the `xip.x` it includes are in the crate root.
+    println!(
+        "cargo:rustc-link-search={}",
+        env::var("CARGO_MANIFEST_DIR").unwrap()
+    );
     println!("cargo:rustc-link-arg-bins=--nmagic");
+    // Before `link.x`, whose `.text` would take the code for the ITCM otherwise.
+    println!("cargo:rustc-link-arg-bins=-Tflash_test.x");
     println!("cargo:rustc-link-arg-bins=-Tlink.x");
     println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
 }
@@ -0,0 +1,188 @@
+#!/usr/bin/env python3
+"""Check that the execute in place service of `flash_lib::xip` only runs from the ITCM.
+
+Usage: check_xip.py [COMMAND...] ELF
+
+Disassembles ELF and follows every direct call and branch from the code running while the XSPI
+flash is unmapped, the `XipFlash` functions in the `.itcm_text.flash_lib_xip_*` sections. It
+fails if any of them reaches code outside the ITCM, makes an indirect call, which can't be
+followed, or loads the address of a section in the flash, e.g. an anonymous constant. Then it
+runs COMMAND with ELF appended, so it can be used as the cargo runner. Images without an ITCM
+section are passed on unchecked.
+
+Calls to the panic machinery are allowed, with the addresses of the messages and locations they
+are passed: a panic faults while the flash is unmapped, but so does the `unwrap` it replaces.
+
+The disassembler is llvm-objdump, the one of the toolchain's `llvm-tools` component if
+installed, or the one in `OBJDUMP`.
+"""
+
+import bisect
+import os
+import re
+import shutil
+import subprocess
+import sys
+
+ROOT = re.compile(r"XipFlash.*(unmapped_in_ram|take_over_in_ram)")
+PANIC = re.compile(
+    r"^(core::panicking::|core::panic::|core::slice::index::|core::option::unwrap_failed"
+    r"|core::result::unwrap_failed|core::cell::panic_|_defmt_panic|__defmt_default_panic"
+    r"|defmt::export::panic)"
+)
+BRANCH = re.compile(
+    r"^(bl|blx|b|cbn?z|b(eq|ne|cs|hs|cc|lo|mi|pl|vs|vc|hi|ls|ge|lt|gt|le|al))(\.[nw])?$"
+)
+TARGET = re.compile(r"\b0x([0-9a-f]+) <")
+FUNCTION = re.compile(r"^([0-9a-f]+) <(.*)>:$")
+INSTRUCTION = re.compile(r"^\s*([0-9a-f]+):\s+(\S+)\s*(.*)$")
+HALF_WORD = re.compile(r"^(r\d+|r12|lr), #(\S+)$")
+
+
+def objdump():
+    if "OBJDUMP" in os.environ:
+        return os.environ["OBJDUMP"]
+    sysroot = subprocess.run(
+        ["rustc", "--print", "sysroot"], capture_output=True, text=True
+    ).stdout.strip()
+    host = re.search(
+        r"host: (\S+)",
+        subprocess.run(["rustc", "-vV"], capture_output=True, text=True).stdout,
+    )
+    if sysroot and host:
+        tool = os.path.join(sysroot, "lib", "rustlib", host[1], "bin", "llvm-objdump")
+        if os.path.exists(tool):
+            return tool
+    return shutil.which("llvm-objdump") or sys.exit(
+        "check_xip.py: llvm-objdump not found, install the llvm-tools component or set OBJDUMP"
+    )
+
+
+def run(tool, *args):
+    return subprocess.run([tool, *args], capture_output=True, text=True, check=True).stdout
+
+
+def sections(tool, elf):
+    """Name, start and end of every allocated section, and whether it's resident in the flash."""
+    result = []
+    for line in run(tool, "-h", "--show-lma", elf).splitlines():
+        fields = line.split()
+        if len(fields) == 6 and fields[5] in ("TEXT", "DATA") and fields[0].isdigit():
+            _, name, size, vma, lma, _ = fields
+            start, size = int(vma, 16), int(size, 16)
+            # The vector table is left out, as its start is also the address of the flash itself,
+            # e.g. the MPU region of `flash_test`.
+            resident = vma == lma and name != ".vector_table"
+            result.append((name, start, start + size, resident))
+    return result
+
+
+def functions(tool, elf):
+    """Start, name and instructions of every function, sorted by address."""
+    names = {}
+    for line in run(tool, "-t", "-C", elf).splitlines():
+        fields = line.split(None, 5)
+        if len(fields) == 6 and fields[2] == "F":
+            names[int(fields[0], 16)] = fields[5].removeprefix(".hidden ")
+    result = []
+    for line in run(tool, "-d", "-C", "--no-show-raw-insn", elf).splitlines():
+        if m := FUNCTION.match(line):
+            start = int(m[1], 16)
+            result.append((start, names.get(start, m[2]), []))
+        elif result and (m := INSTRUCTION.match(line)):
+            result[-1][2].append((int(m[1], 16), m[2], m[3].split("@")[0].strip()))
+    result.sort(key=lambda function: function[0])
+    return result
+
+
+def thunk_target(function):
+    """Target of a long branch thunk of the linker, which builds its address in r12."""
+    instructions = [(op, operands) for _, op, operands in function[2]]
+    if len(instructions) != 3 or instructions[2] != ("bx", "r12"):
+        return None
+    words = {op: int(m[2], 0) for op, operands in instructions[:2]
+             if (m := HALF_WORD.match(operands)) and m[1] == "r12"}
+    if set(words) != {"movw", "movt"}:
+        return None
+    return (words["movw"] | words["movt"] << 16) & ~1
+
+
+def check(tool, elf):
+    all_sections = sections(tool, elf)
+    itcm = [s for s in all_sections if s[0] == ".itcm_text"]
+    if not itcm:
+        return []
+    _, itcm_start, itcm_end, _ = itcm[0]
+    flash = [s for s in all_sections if s[3]]
+    code = functions(tool, elf)
+    starts = [f[0] for f in code]
+
+    def in_flash(address):
+        return next((name for name, start, end, _ in flash if start <= address < end), None)
+
+    def function_at(address):
+        i = bisect.bisect_right(starts, address) - 1
+        return code[i] if i >= 0 else (address, hex(address), [])
+
+    def callee(address):
+        """The function a branch to `address` ends up in, following thunks."""
+        function = function_at(address)
+        target = thunk_target(function)
+        return function_at(target) if target is not None else function
+
+    roots = [f for f in code if ROOT.search(f[1])]
+    if not roots:
+        return ["no `XipFlash` functions in `.itcm_text`, link with `xip.x`"]
+    errors, queue, seen = [], roots, set()
+    while queue:
+        start, name, instructions = queue.pop()
+        if start in seen:
+            continue
+        seen.add(start)
+        if not itcm_start <= start < itcm_end:
+            errors.append(f"`{name}` is in {in_flash(start) or 'no section'}")
+            continue
+        # Addresses loaded since the last call, which are the arguments of a panic if that is
+        # the next call.
+        pending, low_halves = [], {}
+        for address, op, operands in instructions:
+            if BRANCH.match(op) and (m := TARGET.search(operands)):
+                target = callee(int(m[1], 16))
+                if target[0] == start:
+                    continue
+                if PANIC.match(target[1]):
+                    pending = []
+                    continue
+                errors += pending
+                pending = []
+                queue.append(target)
+            elif op in ("blx", "bx") and operands != "lr":
+                errors.append(f"`{name}` makes an indirect call at {address:#x}")
+            elif op in ("movw", "movt") and (m := HALF_WORD.match(operands)):
+                if op == "movw":
+                    low_halves[m[1]] = int(m[2], 0)
+                    continue
+                value = low_halves.pop(m[1], 0) | int(m[2], 0) << 16
+                if section := in_flash(value):
+                    pending.append(f"`{name}` loads {value:#x} in {section} at {address:#x}")
+            elif op == ".word" and (section := in_flash(int(operands, 0))):
+                errors.append(f"`{name}` loads {int(operands, 0):#x} in {section} at {address:#x}")
+        errors += pending
+    return errors
+
+
+def main():
+    if len(sys.argv) < 2:
+        sys.exit(__doc__.splitlines()[2])
+    *command, elf = sys.argv[1:]
+    errors = check(objdump(), elf)
+    for error in errors:
+        print(f"check_xip.py: {error}", file=sys.stderr)
+    if errors:
+        sys.exit(f"check_xip.py: {elf} runs code or reads data in the flash while it is unmapped")
+    if command:
+        os.execvp(command[0], [*command, elf])
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,10 @@
+/* Additions to the memory.x of embassy-stm32 for `flash_test`, which runs the execute in place
+ * service from the ITCM like an application executing from the XSPI flash, see `flash_lib::xip`.
+ */
+MEMORY
+{
+    /* Address 0 is left out, as no function may be at the null address. */
+    ITCM : ORIGIN = 0x00000004, LENGTH = 64K - 4
+}
+
+INCLUDE xip.x
@@ -0,0 +1,357 @@
+//! Async variant of the flash drivers.
+//!
+//! Array reads and page programs are transferred by the XSPI's DMA channel. While a program or
+//! erase operation is in progress the status register is polled with a timer delay in between,
+//! so other tasks keep running while the flash is busy. Register accesses are only a few bytes
+//! long and stay blocking.
+
+use core::cmp::min;
+
+use embassy_stm32::{
+    Peri,
+    mode::Async,
+    xspi::{
+        CLKPin, D0Pin, D1Pin, D2Pin, D3Pin, D4Pin, D5Pin, D6Pin, D7Pin, DQS0Pin, NCSPin, XDma, Xspi,
+    },
+};
+use embassy_time::{Duration, Instant, Timer};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+
+use crate::erase::{ErasePlan, EraseStep};
+use crate::instance::{self, hal_transfer};
+use crate::nor_flash::check_erase_range;
+use crate::polling::WriteOperation;
+use crate::transport::TransferConfig;
+use crate::{FlashError, FlashMemory, OpiFlashMemory, SECTOR_SIZE, SpiFlashMemory, XspiInstance};
+
+/// Delay between status polls while a page program is in progress (typ. 0.15 ms).
+const PROGRAM_POLL_INTERVAL: Duration = Duration::from_micros(20);
+
+/// Delay between status polls while an erase is in progress (typ. 25 ms for a 4KB sector).
+const ERASE_POLL_INTERVAL: Duration = Duration::from_micros(500);
+
+/// Delay between status polls while waiting for `operation`.
+fn poll_interval(operation: WriteOperation) -> Duration {
+    match operation {
+        WriteOperation::Register | WriteOperation::Program => PROGRAM_POLL_INTERVAL,
+        _ => ERASE_POLL_INTERVAL,
+    }
+}
+
+impl<T: XspiInstance> SpiFlashMemory<Xspi<'static, T, Async>> {
+    /// Create the async driver like [`SpiFlashMemory::new`], using `dma` for array reads and
+    /// page programs.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_async(
+        peri: Peri<'static, T>,
+        clk: Peri<'static, impl CLKPin<T>>,
+        d0: Peri<'static, impl D0Pin<T>>,
+        d1: Peri<'static, impl D1Pin<T>>,
+        d2: Peri<'static, impl D2Pin<T>>,
+        d3: Peri<'static, impl D3Pin<T>>,
+        d4: Peri<'static, impl D4Pin<T>>,
+        d5: Peri<'static, impl D5Pin<T>>,
+        d6: Peri<'static, impl D6Pin<T>>,
+        d7: Peri<'static, impl D7Pin<T>>,
+        ncs: Peri<'static, impl NCSPin<T>>,
+        dqs: Peri<'static, impl DQS0Pin<T>>,
+        dma: Peri<'static, impl XDma<T>>,
+    ) -> Result<Self, FlashError> {
+        let xspi = Xspi::new_xspi(
+            peri,
+            clk,
+            d0,
+            d1,
+            d2,
+            d3,
+            d4,
+            d5,
+            d6,
+            d7,
+            ncs,
+            dma,
+            instance::xspi_config(),
+        );
+        instance::configure_dqs(dqs);
+        Self::with_transport(xspi)
+    }
+
+    async fn read_async(
+        &mut self,
+        buffer: &mut [u8],
+        transaction: TransferConfig,
+    ) -> Result<(), FlashError> {
+        self.check_indirect()?;
+        self.transport
+            .read(buffer, hal_transfer(&transaction))
+            .await?;
+        Ok(())
+    }
+
+    async fn write_async(
+        &mut self,
+        buffer: &[u8],
+        transaction: TransferConfig,
+    ) -> Result<(), FlashError> {
+        self.check_indirect()?;
+        self.transport
+            .write(buffer, hal_transfer(&transaction))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn read_memory_async(
+        &mut self,
+        addr: u32,
+        buffer: &mut [u8],
+    ) -> Result<(), FlashError> {
+        self.geometry.check_bounds(addr, buffer.len())?;
+        let transaction = self.read_transaction(addr);
+        self.read_async(buffer, transaction).await
+    }
+
+    async fn write_page_async(&mut self, addr: u32, buffer: &[u8]) -> Result<(), FlashError> {
+        if buffer.len() + addr as usize % self.geometry.page_size > self.geometry.page_size {
+            return Err(FlashError::OutOfBounds);
+        }
+
+        let transaction = self.program_transaction(addr);
+        self.enable_write()?;
+        self.write_async(buffer, transaction).await?;
+        self.wait_write_finish_async(WriteOperation::Program).await
+    }
+
+    pub async fn write_memory_async(&mut self, addr: u32, buffer: &[u8]) -> Result<(), FlashError> {
+        self.geometry.check_bounds(addr, buffer.len())?;
+        self.write_pages_async(addr, buffer).await
+    }
+}
+
+impl<T: XspiInstance> OpiFlashMemory<Xspi<'static, T, Async>> {
+    async fn read_async(
+        &mut self,
+        buffer: &mut [u8],
+        transaction: TransferConfig,
+    ) -> Result<(), FlashError> {
+        self.check_indirect()?;
+        self.set_dqs(self.dtr);
+        self.transport
+            .read(buffer, hal_transfer(&transaction))
+            .await?;
+        Ok(())
+    }
+
+    async fn write_async(
+        &mut self,
+        buffer: &[u8],
+        transaction: TransferConfig,
+    ) -> Result<(), FlashError> {
+        self.check_indirect()?;
+        self.set_dqs(false);
+        self.transport
+            .write(buffer, hal_transfer(&transaction))
+            .await?;
+        Ok(())
+    }
+
+    /// In DTR mode an odd first or last byte is read with a short blocking 2-byte read.
+    pub async fn read_memory_async(
+        &mut self,
+        addr: u32,
+        buffer: &mut [u8],
+    ) -> Result<(), FlashError> {
+        self.geometry.check_bounds(addr, buffer.len())?;
+        let mut addr = addr;
+        let mut buffer = buffer;
+        if self.dtr {
+            if addr % 2 == 1 && !buffer.is_empty() {
+                let mut pair = [0; 2];
+                self.read_array(addr - 1, &mut pair)?;
+                buffer[0] = pair[1];
+                addr += 1;
+                buffer = &mut buffer[1..];
+            }
+            if buffer.len() % 2 == 1 {
+                let last = buffer.len() - 1;
+                let mut pair = [0; 2];
+                self.read_array(addr + last as u32, &mut pair)?;
+                buffer[last] = pair[0];
+                buffer = &mut buffer[..last];
+            }
+        }
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        let transaction = self.read_transaction(addr);
+        self.read_async(buffer, transaction).await
+    }
+
+    async fn write_page_async(&mut self, addr: u32, buffer: &[u8]) -> Result<(), FlashError> {
+        if buffer.len() + addr as usize % self.geometry.page_size > self.geometry.page_size {
+            return Err(FlashError::OutOfBounds);
+        }
+        if self.dtr && (addr % 2 == 1 || buffer.len() % 2 == 1) {
+            return Err(FlashError::NotAligned);
+        }
+
+        let transaction = self.program_transaction(addr);
+        self.enable_write()?;
+        self.write_async(buffer, transaction).await?;
+        self.wait_write_finish_async(WriteOperation::Program).await
+    }
+
+    /// In DTR mode an odd first or last byte is padded with 0xFF, see
+    /// [`OpiFlashMemory::write_memory`].
+    pub async fn write_memory_async(&mut self, addr: u32, buffer: &[u8]) -> Result<(), FlashError> {
+        self.geometry.check_bounds(addr, buffer.len())?;
+        let mut addr = addr;
+        let mut buffer = buffer;
+        if self.dtr {
+            if addr % 2 == 1 && !buffer.is_empty() {
+                self.write_page_async(addr - 1, &[0xFF, buffer[0]]).await?;
+                addr += 1;
+                buffer = &buffer[1..];
+            }
+            if buffer.len() % 2 == 1 {
+                let last = buffer.len() - 1;
+                self.write_page_async(addr + last as u32, &[buffer[last], 0xFF])
+                    .await?;
+                buffer = &buffer[..last];
+            }
+        }
+        self.write_pages_async(addr, buffer).await
+    }
+}
+
+/// Implements the async operations that don't depend on the bus mode, for both drivers.
+macro_rules! impl_async_operations {
+    ($t:ident) => {
+        impl<T: XspiInstance> $t<Xspi<'static, T, Async>> {
+            /// Wait for the Write In Progress bit to clear, at most the max duration of
+            /// `operation`.
+            async fn wait_write_finish_async(
+                &mut self,
+                operation: WriteOperation,
+            ) -> Result<(), FlashError> {
+                let deadline = Instant::now() + self.timeouts.get(operation);
+                while self.read_status()?.write_in_progress() {
+                    if Instant::now() > deadline {
+                        return Err(FlashError::Timeout);
+                    }
+                    Timer::after(poll_interval(operation)).await;
+                }
+                self.check_write_result()
+            }
+
+            async fn perform_erase_async(
+                &mut self,
+                addr: u32,
+                cmd: u8,
+                operation: WriteOperation,
+            ) -> Result<(), FlashError> {
+                self.start_erase(addr, cmd)?;
+                self.wait_write_finish_async(operation).await
+            }
+
+            pub async fn erase_sector_async(&mut self, addr: u32) -> Result<(), FlashError> {
+                self.perform_erase_async(
+                    addr,
+                    self.chip.commands.sector_erase,
+                    WriteOperation::SectorErase,
+                )
+                .await
+            }
+
+            pub async fn erase_block_64k_async(&mut self, addr: u32) -> Result<(), FlashError> {
+                self.perform_erase_async(
+                    addr,
+                    self.chip.commands.block_erase,
+                    WriteOperation::BlockErase,
+                )
+                .await
+            }
+
+            pub async fn erase_chip_async(&mut self) -> Result<(), FlashError> {
+                self.enable_write()?;
+                self.exec_command(self.chip.commands.chip_erase)?;
+                self.wait_write_finish_async(WriteOperation::ChipErase)
+                    .await
+            }
+
+            /// Erase `len` bytes starting at `start`, both must be sector aligned, with 64KB
+            /// block erases where possible, see [`ErasePlan`].
+            pub async fn erase_range_async(
+                &mut self,
+                start: u32,
+                len: usize,
+            ) -> Result<(), FlashError> {
+                for step in ErasePlan::new(&self.geometry, start, len)? {
+                    match step {
+                        EraseStep::Sector(addr) => self.erase_sector_async(addr).await?,
+                        EraseStep::Block(addr) => self.erase_block_64k_async(addr).await?,
+                    }
+                }
+                Ok(())
+            }
+
+            /// Program `buffer` starting at `addr` page by page.
+            async fn write_pages_async(
+                &mut self,
+                addr: u32,
+                buffer: &[u8],
+            ) -> Result<(), FlashError> {
+                let page_size = self.geometry.page_size;
+                let mut left = buffer.len();
+                let mut place = addr;
+                let mut chunk_start = 0;
+
+                while left > 0 {
+                    let max_chunk_size = page_size - place as usize % page_size;
+                    let chunk_size = min(max_chunk_size, left);
+                    let chunk = &buffer[chunk_start..(chunk_start + chunk_size)];
+                    self.write_page_async(place, chunk).await?;
+                    place += chunk_size as u32;
+                    left -= chunk_size;
+                    chunk_start += chunk_size;
+                }
+                Ok(())
+            }
+        }
+    };
+}
+
+impl_async_operations!(SpiFlashMemory);
+impl_async_operations!(OpiFlashMemory);
+
+macro_rules! impl_async_nor_flash {
+    ($t:ident) => {
+        impl<T: XspiInstance> ReadNorFlash for $t<Xspi<'static, T, Async>> {
+            const READ_SIZE: usize = 1;
+
+            async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+                self.read_memory_async(offset, bytes).await
+            }
+
+            fn capacity(&self) -> usize {
+                FlashMemory::geometry(self).size
+            }
+        }
+
+        impl<T: XspiInstance> NorFlash for $t<Xspi<'static, T, Async>> {
+            const WRITE_SIZE: usize = 1;
+            const ERASE_SIZE: usize = SECTOR_SIZE;
+
+            async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+                check_erase_range(&FlashMemory::geometry(self), from, to)?;
+                self.erase_range_async(from, (to - from) as usize).await
+            }
+
+            async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+                self.write_memory_async(offset, bytes).await
+            }
+        }
+    };
+}
+
+impl_async_nor_flash!(SpiFlashMemory);
+impl_async_nor_flash!(OpiFlashMemory);
@@ -2,29 +2,66 @@
 #![no_std]
 
 //! This example tests the flash memory driver by writing and reading back data from the flash memory.
-//! It also has some throughput tests to measure the performance of the driver.
+//! It also has some throughput tests to compare the performance of the driver in SPI, octal STR
+//! and octal DTR mode, and checks that the execute in place service only uses code and data in
+//! the ITCM while the flash is unmapped.
 
-use defmt::{error, info};
+use defmt::{error, info, unwrap};
 use embassy_executor::Spawner;
 use embassy_time::Instant;
 
 use defmt_rtt as _;
-use flash_lib::{MEMORY_MAPPED_FLASH_ADDRESS, SpiFlashMemory};
+use flash_lib::FlashMemory;
+use flash_lib::nucleo_h7s3l8;
+use flash_lib::xip::{self, XipFlash};
 use panic_probe as _;
 
 #[embassy_executor::main]
 async fn main(_spawner: Spawner) {
-    let r = flash_lib::init();
+    // SAFETY: nothing in the ITCM has run yet.
+    unsafe { xip::load_ram_code() };
+    let r = nucleo_h7s3l8::init();
 
-    let mut flash = SpiFlashMemory::new(r.flash_memory);
+    let mut flash = unwrap!(nucleo_h7s3l8::new_flash(r.flash_memory));
 
-    let flash_id = flash.read_id();
+    let flash_id = unwrap!(flash.read_id());
     info!("FLASH ID: {=[u8]:x}", flash_id);
+    info!("FLASH chip: {}", flash.chip().name);
+    info!("FLASH geometry: {}", flash.geometry());
 
-    // let mut flash = flash.into_octo();
+    // Each mode uses its own sector so data left over from a previous mode can't hide a broken
+    // write.
+    test_flash(&mut flash, "SPI", 0x0000);
 
-    // Erase the first sector.
-    flash.erase_sector(0);
+    let mut flash = unwrap!(flash.into_octo());
+    test_flash(&mut flash, "OPI-STR", 0x1000);
+
+    let flash = unwrap!(flash.into_spi());
+    let mut flash = unwrap!(flash.into_octo_dtr());
+    test_flash(&mut flash, "OPI-DTR", 0x2000);
+
+    let mut flash = unwrap!(flash.into_spi());
+    info!(
+        "Back in SPI mode, FLASH ID: {=[u8]:x}",
+        unwrap!(flash.read_id())
+    );
+
+    let flash = unwrap!(flash.into_octo());
+    test_xip(unwrap!(XipFlash::new(flash)), 0x3000);
+
+    info!("DONE");
+
+    let future = core::future::pending();
+    let () = future.await;
+    defmt::unreachable!();
+}
+
+/// Writes a sector at `addr`, reads it back indirectly and memory mapped and verifies it, logging
+/// the time taken by each step.
+fn test_flash(flash: &mut impl FlashMemory, mode: &str, addr: u32) {
+    info!("---- {} ----", mode);
+
+    unwrap!(flash.erase_sector(addr));
 
     // Write a full sector
     let mut wr_buf = [0u8; 0x1000];
@@ -33,29 +70,36 @@ async fn main(_spawner: Spawner) {
     }
 
     let start = Instant::now();
-    flash.write_memory(0, &wr_buf);
+    unwrap!(flash.write_memory(addr, &wr_buf));
     let elapsed = start.elapsed();
-    info!("Wrote 4k bytes in {} us", elapsed.as_micros());
+    info!("{}: Wrote 4k bytes in {} us", mode, elapsed.as_micros());
 
-    // Read back the first 8 bytes with the fast read command and verify them.
-    let mut rd_buf = [0u8; 8];
-    flash.read_memory(0, &mut rd_buf);
+    // Read back the sector with indirect reads and verify it.
+    let mut rd_buf = [0u8; 0x1000];
+    let start = Instant::now();
+    unwrap!(flash.read_memory(addr, &mut rd_buf));
+    let elapsed = start.elapsed();
+    info!("{}: Read 4k bytes in {} us", mode, elapsed.as_micros());
     info!("WRITE BUF: {=[u8]:#X}", wr_buf[..8]);
-    info!("READ BUF: {=[u8]:#X}", rd_buf);
+    info!("READ BUF: {=[u8]:#X}", rd_buf[..8]);
+    if rd_buf != wr_buf {
+        error!("{}: Indirect read back doesn't match", mode);
+        panic!();
+    }
 
     // Enable memory mapped mode
-    flash.enable_mm();
+    let mapped = unwrap!(flash.enable_mm());
     info!("Enabled memory mapped mode");
 
-    let flash_beginning = MEMORY_MAPPED_FLASH_ADDRESS as *const u32;
+    if unwrap!(mapped.read(addr, wr_buf.len())) != wr_buf {
+        error!("{}: Memory mapped read back doesn't match", mode);
+        panic!();
+    }
+
+    let flash_beginning = unsafe { mapped.as_ptr().add(addr as usize) } as *const u32;
 
     let first_u32 = unsafe { *(flash_beginning) };
     info!("first_u32 {:08x}", first_u32);
-    // assert_eq!(first_u32, 0x03020100);
-
-    let second_u32 = unsafe { *(flash_beginning.offset(1)) };
-    // assert_eq!(second_u32, 0x07060504);
-    info!("second_u32 {:08x}", second_u32);
 
     // Speed test, read back 1024 u32 (4k bytes) from the memory mapped area.
     let mut rd_buf = [0u32; 1024];
@@ -65,7 +109,7 @@ async fn main(_spawner: Spawner) {
         *val = unsafe { *(flash_beginning.offset(pos as isize)) };
     }
     let elapsed = start.elapsed();
-    info!("Read 1024 u32 in {} us", elapsed.as_micros());
+    info!("{}: Read 1024 u32 in {} us", mode, elapsed.as_micros());
 
     // Verify the read data.
     for (pos, val) in rd_buf.iter().enumerate() {
@@ -74,10 +118,6 @@ async fn main(_spawner: Spawner) {
         let byte2 = (byte0 + 2) << 16;
         let byte3 = (byte0 + 3) << 24;
         let expected = byte0 + byte1 + byte2 + byte3;
-        // info!(
-        //     "pos: {}, val: {:08x}, expected: {:08x}",
-        //     pos, *val, expected
-        // );
         if *val != expected {
             error!(
                 "Mismatch at pos {}: expected {:08x}, got {:08x}",
@@ -87,12 +127,38 @@ async fn main(_spawner: Spawner) {
         }
     }
 
-    flash.disable_mm();
+    drop(mapped);
     info!("Disabled memory mapped mode");
+}
 
-    info!("DONE");
+/// Writes a sector at `addr` through the execute in place service and verifies it in the mapped
+/// flash. The service makes the internal flash this program runs from inaccessible while the
+/// XSPI flash is unmapped, so this faults if it uses anything `xip.x` doesn't place in the ITCM.
+fn test_xip(mut flash: XipFlash, addr: u32) {
+    info!("---- XIP ----");
 
-    let future = core::future::pending();
-    let () = future.await;
-    defmt::unreachable!();
+    // The data is on the stack, as the internal flash can't be read during the writes.
+    let mut wr_buf = [0u8; 0x1000];
+    for (i, byte) in wr_buf.iter_mut().enumerate() {
+        *byte = (i & 0xFF) as u8 ^ 0x5A;
+    }
+    let start = Instant::now();
+    unwrap!(flash.erase_sector(addr));
+    unwrap!(flash.write_memory(addr, &wr_buf));
+    info!("XIP: Wrote 4k bytes in {} us", start.elapsed().as_micros());
+    if unwrap!(flash.read(addr, wr_buf.len())) != wr_buf {
+        error!("XIP: Mapped read back doesn't match");
+        panic!();
+    }
+
+    let update = *b"updated";
+    let mut scratch = [0u8; 0x1000];
+    unwrap!(flash.update(addr + 16, &update, &mut scratch));
+    if unwrap!(flash.read(addr + 16, update.len())) != update
+        || unwrap!(flash.read(addr, 16)) != &wr_buf[..16]
+    {
+        error!("XIP: Update doesn't match");
+        panic!();
+    }
+    info!("XIP: Writes with the flash unmapped succeeded");
 }
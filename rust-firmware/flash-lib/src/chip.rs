@@ -0,0 +1,601 @@
+//! Database of supported octal NOR flash chips.
+//!
+//! The chip is selected at runtime from its JEDEC ID. Each entry describes the opcodes, how the
+//! chip is switched to OPI and where its registers live, everything that SFDP doesn't tell us.
+
+use core::cmp::min;
+
+use crate::FlashError;
+use crate::registers::{BusMode, Cr2DummyCycles, Cr2Mode, Cr2Register, SecurityRegister};
+use crate::transport::{AddressSize, MemoryType};
+
+/// How an opcode is encoded in octal mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CommandExtension {
+    /// 2-byte command, the second byte is the inverted opcode (Macronix).
+    Inverted,
+    /// 2-byte command, the opcode is sent twice.
+    Repeat,
+    /// The opcode is sent as a single byte.
+    None,
+}
+
+impl CommandExtension {
+    /// The instruction and instruction size for `opcode`.
+    pub(crate) fn encode(self, opcode: u8) -> (u32, AddressSize) {
+        let opcode = opcode as u32;
+        match self {
+            CommandExtension::Inverted => ((opcode << 8) | (!opcode & 0xFF), AddressSize::_16bit),
+            CommandExtension::Repeat => ((opcode << 8) | opcode, AddressSize::_16bit),
+            CommandExtension::None => (opcode, AddressSize::_8bit),
+        }
+    }
+}
+
+/// Opcodes of the array and device operation commands. All addressed commands use 4-byte
+/// addresses. Unless noted otherwise, the same opcode is used in SPI and octal mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Commands {
+    /// Fast read in SPI mode.
+    pub read: u8,
+    /// Read in octal STR mode.
+    pub octal_read: u8,
+    /// Read in octal DTR mode.
+    pub octal_dtr_read: u8,
+    pub page_program: u8,
+    pub sector_erase: u8,
+    pub block_erase: u8,
+    pub chip_erase: u8,
+    pub write_enable: u8,
+    pub write_disable: u8,
+    pub reset_enable: u8,
+    pub reset_memory: u8,
+    pub read_id: u8,
+    pub read_sfdp: u8,
+    pub read_status: u8,
+    pub write_status: u8,
+}
+
+impl Commands {
+    /// Opcodes shared by all supported chips.
+    const JEDEC: Self = Self {
+        read: 0x0C,
+        octal_read: 0x0C,
+        octal_dtr_read: 0xFD,
+        page_program: 0x12,
+        sector_erase: 0x21,
+        block_erase: 0xDC,
+        chip_erase: 0x60,
+        write_enable: 0x06,
+        write_disable: 0x04,
+        reset_enable: 0x66,
+        reset_memory: 0x99,
+        read_id: 0x9F,
+        read_sfdp: 0x5A,
+        read_status: 0x05,
+        write_status: 0x01,
+    };
+}
+
+/// How the chip is switched between SPI and octal mode.
+///
+/// The mode is selected by writing `(value & !mask) | mode` to the address based configuration
+/// register (see [`Registers::read_cr2`]) at `address`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct OpiEnable {
+    pub address: u32,
+    pub mask: u8,
+    pub spi: u8,
+    /// `None` if the chip doesn't support octal STR.
+    pub octal_str: Option<u8>,
+    pub octal_dtr: u8,
+}
+
+impl OpiEnable {
+    /// Register value (within `mask`) selecting `mode`, `None` if the chip doesn't support it.
+    pub fn value(&self, mode: BusMode) -> Option<u8> {
+        match mode {
+            BusMode::Spi => Some(self.spi),
+            BusMode::OctalStr => self.octal_str,
+            BusMode::OctalDtr => Some(self.octal_dtr),
+        }
+    }
+
+    /// The mode selected by the register value `value`, if any.
+    pub fn decode(&self, value: u8) -> Option<BusMode> {
+        let bits = value & self.mask;
+        [BusMode::Spi, BusMode::OctalStr, BusMode::OctalDtr]
+            .into_iter()
+            .find(|&mode| self.value(mode) == Some(bits))
+    }
+}
+
+/// Where the dummy cycles of octal reads are configured, in the address based configuration
+/// register (see [`Registers::read_cr2`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DummyCycleConfig {
+    pub address: u32,
+    pub mask: u8,
+    /// Dummy cycles selected by each register value, `None` if the register holds the number
+    /// of cycles itself.
+    pub table: Option<[u8; 8]>,
+}
+
+impl DummyCycleConfig {
+    /// Register value and the resulting number of dummy cycles for at least `cycles` dummy
+    /// cycles, or the max if the chip doesn't support that many.
+    pub(crate) fn encode(self, cycles: u8) -> (u8, u8) {
+        match self.table {
+            Some(table) => {
+                // The smallest entry that is large enough, otherwise the largest one.
+                let mut best = 0;
+                for (value, &entry) in table.iter().enumerate() {
+                    let current = table[best];
+                    let better = if current < cycles {
+                        entry > current
+                    } else {
+                        entry >= cycles && entry < current
+                    };
+                    if better {
+                        best = value;
+                    }
+                }
+                (best as u8, table[best])
+            }
+            None => {
+                let cycles = min(cycles, self.mask);
+                (cycles, cycles)
+            }
+        }
+    }
+}
+
+/// Where the chip reports failed program and erase operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FailStatus {
+    /// P_FAIL/E_FAIL bits of the security register, updated by every program/erase.
+    SecurityRegister {
+        read: u8,
+        program_fail: u8,
+        erase_fail: u8,
+    },
+    /// Error bits of the flag status register, sticky until cleared with `clear`.
+    FlagStatusRegister {
+        read: u8,
+        clear: u8,
+        program_fail: u8,
+        erase_fail: u8,
+    },
+}
+
+impl FailStatus {
+    /// Opcode reading the register.
+    pub(crate) fn read_opcode(self) -> u8 {
+        match self {
+            FailStatus::SecurityRegister { read, .. } => read,
+            FailStatus::FlagStatusRegister { read, .. } => read,
+        }
+    }
+
+    /// Opcode clearing the error bits, if they are sticky.
+    pub(crate) fn clear_opcode(self) -> Option<u8> {
+        match self {
+            FailStatus::SecurityRegister { .. } => None,
+            FailStatus::FlagStatusRegister { clear, .. } => Some(clear),
+        }
+    }
+
+    /// The error reported by the register `value`, if any.
+    pub(crate) fn error(self, value: u8) -> Option<FlashError> {
+        let (program_fail, erase_fail) = match self {
+            FailStatus::SecurityRegister {
+                program_fail,
+                erase_fail,
+                ..
+            } => (program_fail, erase_fail),
+            FailStatus::FlagStatusRegister {
+                program_fail,
+                erase_fail,
+                ..
+            } => (program_fail, erase_fail),
+        };
+        if value & program_fail != 0 {
+            Some(FlashError::ProgramFailed)
+        } else if value & erase_fail != 0 {
+            Some(FlashError::EraseFailed)
+        } else {
+            None
+        }
+    }
+}
+
+/// Program/erase suspend commands. The suspend state is reported in the same register as
+/// failed operations (see [`FailStatus`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Suspend {
+    pub suspend: u8,
+    pub resume: u8,
+    /// Bit set while a program operation is suspended.
+    pub program_suspended: u8,
+    /// Bit set while an erase operation is suspended.
+    pub erase_suspended: u8,
+}
+
+/// Write-to-buffer commands (WRBI/WRCT/WRCF).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct WriteBuffer {
+    /// Clear the page buffer and write the initial data (WRBI).
+    pub initial: u8,
+    /// Write more data to the page buffer (WRCT).
+    pub append: u8,
+    /// Program the page buffer to the array (WRCF).
+    pub confirm: u8,
+}
+
+/// Advanced sector protection commands (Macronix).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Protection {
+    /// Permanently switch from BP mode to advanced sector protection (WPSEL).
+    pub select_advanced: u8,
+    pub read_lock: u8,
+    pub write_lock: u8,
+    pub read_dpb: u8,
+    pub write_dpb: u8,
+    pub read_spb: u8,
+    pub write_spb: u8,
+    pub erase_spb: u8,
+    /// Set all DPBs.
+    pub gang_lock: u8,
+    /// Clear all DPBs.
+    pub gang_unlock: u8,
+    pub read_password: u8,
+    pub write_password: u8,
+    pub password_unlock: u8,
+}
+
+/// Secured OTP region commands (Macronix).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Otp {
+    /// Size of the OTP region in bytes.
+    pub size: usize,
+    /// Enter secured OTP mode (ENSO), array commands then access the OTP region.
+    pub enter: u8,
+    /// Exit secured OTP mode (EXSO).
+    pub exit: u8,
+    /// Write the security register (WRSCUR), which sets the lock-down bit.
+    pub lock: u8,
+    /// Security register bit set once the OTP region is locked down (LDSO).
+    pub locked: u8,
+}
+
+/// Fast boot register commands (Macronix).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FastBoot {
+    /// Read the fast boot register (RDFBR).
+    pub read: u8,
+    /// Program the fast boot register (WRFBR).
+    pub write: u8,
+    /// Erase the fast boot register to all ones (ESFBR), which disables fast boot.
+    pub erase: u8,
+}
+
+/// Max duration (ms) of program and erase operations from the datasheet, used as timeouts
+/// while waiting for them to finish.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Timeouts {
+    /// Status/configuration register writes and other short operations (tW).
+    pub register_write_ms: u32,
+    pub page_program_ms: u32,
+    pub sector_erase_ms: u32,
+    pub block_erase_ms: u32,
+    pub chip_erase_ms: u32,
+}
+
+/// Deep power-down commands and timings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PowerDown {
+    pub enter: u8,
+    pub release: u8,
+    /// Time (us) after entering until the chip has reached deep power-down (tDP).
+    pub enter_time_us: u32,
+    /// Time (us) after the release command until the chip accepts commands again (tRES1).
+    pub release_time_us: u32,
+}
+
+/// Register opcodes and layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Registers {
+    /// Read the configuration register, `None` if the chip has no such register.
+    pub read_cr: Option<u8>,
+    /// Read the address based configuration register (CR2 on Macronix, volatile configuration
+    /// register on Micron compatible parts).
+    pub read_cr2: u8,
+    /// Write the address based configuration register.
+    pub write_cr2: u8,
+    /// Whether CR2 accesses in SPI mode use a 4-byte (instead of 3-byte) address.
+    pub cr2_address_4b: bool,
+    /// Dummy cycles of a CR2 read in SPI mode.
+    pub cr2_read_dummy_cycles: u8,
+    /// Whether register reads in octal mode are sent with a (dummy) 4-byte address.
+    pub octal_read_address: bool,
+    /// Whether the chip outputs every byte of the ID twice in octal DTR mode.
+    pub dtr_repeated_bytes: bool,
+    pub dummy_cycles: DummyCycleConfig,
+    pub fail_status: FailStatus,
+    /// Whether the status, configuration, security, lock and CR2 registers are laid out as in
+    /// [`registers`](crate::registers).
+    pub macronix_layout: bool,
+}
+
+/// Description of a supported flash chip.
+#[derive(Clone, Copy)]
+pub struct Chip {
+    pub name: &'static str,
+    pub jedec_id: [u8; 3],
+    /// XSPI memory type, which selects the byte order in DTR mode.
+    pub memory_type: MemoryType,
+    /// Command encoding in octal STR mode.
+    pub command_extension: CommandExtension,
+    /// Command encoding in octal DTR mode.
+    pub dtr_command_extension: CommandExtension,
+    pub commands: Commands,
+    pub opi_enable: OpiEnable,
+    pub registers: Registers,
+    /// `None` if the chip can't suspend program and erase operations.
+    pub suspend: Option<Suspend>,
+    /// `None` if the chip has no write-to-buffer commands.
+    pub write_buffer: Option<WriteBuffer>,
+    /// `None` if the chip's protection scheme isn't supported.
+    pub protection: Option<Protection>,
+    /// `None` if the chip's OTP region isn't supported.
+    pub otp: Option<Otp>,
+    /// `None` if the chip has no fast boot register.
+    pub fast_boot: Option<FastBoot>,
+    pub power_down: PowerDown,
+    pub timeouts: Timeouts,
+}
+
+const MACRONIX_OPI_ENABLE: OpiEnable = OpiEnable {
+    address: Cr2Mode::ADDRESS,
+    mask: Cr2Mode::MODE_MASK,
+    spi: 0x00,
+    octal_str: Some(Cr2Mode::SOPI),
+    octal_dtr: Cr2Mode::DOPI,
+};
+
+const MACRONIX_REGISTERS: Registers = Registers {
+    read_cr: Some(0x15),
+    read_cr2: 0x71,
+    write_cr2: 0x72,
+    cr2_address_4b: true,
+    cr2_read_dummy_cycles: 0,
+    octal_read_address: true,
+    dtr_repeated_bytes: true,
+    dummy_cycles: DummyCycleConfig {
+        address: Cr2DummyCycles::ADDRESS,
+        mask: Cr2DummyCycles::MASK,
+        table: Some(Cr2DummyCycles::TABLE),
+    },
+    fail_status: FailStatus::SecurityRegister {
+        read: 0x2B,
+        program_fail: SecurityRegister::P_FAIL,
+        erase_fail: SecurityRegister::E_FAIL,
+    },
+    macronix_layout: true,
+};
+
+/// Registers of the Micron compatible xSPI parts (Winbond W35T, ISSI IS25WX).
+const XSPI_REGISTERS: Registers = Registers {
+    read_cr: None,
+    read_cr2: 0x85,
+    write_cr2: 0x81,
+    cr2_address_4b: false,
+    cr2_read_dummy_cycles: 8,
+    octal_read_address: false,
+    dtr_repeated_bytes: false,
+    dummy_cycles: DummyCycleConfig {
+        address: 0x01,
+        mask: 0x1F,
+        table: None,
+    },
+    fail_status: FailStatus::FlagStatusRegister {
+        read: 0x70,
+        clear: 0x50,
+        program_fail: 1 << 4,
+        erase_fail: 1 << 5,
+    },
+    macronix_layout: false,
+};
+
+/// Suspend commands of Macronix chips, tracked by the PSB/ESB bits of the security register.
+const MACRONIX_SUSPEND: Suspend = Suspend {
+    suspend: 0xB0,
+    resume: 0x30,
+    program_suspended: SecurityRegister::PSB,
+    erase_suspended: SecurityRegister::ESB,
+};
+
+/// Suspend commands of the Micron compatible xSPI parts, tracked by the flag status register.
+const XSPI_SUSPEND: Suspend = Suspend {
+    suspend: 0x75,
+    resume: 0x7A,
+    program_suspended: 1 << 2,
+    erase_suspended: 1 << 6,
+};
+
+const MACRONIX_TIMEOUTS: Timeouts = Timeouts {
+    register_write_ms: 40,
+    page_program_ms: 1,
+    sector_erase_ms: 400,
+    block_erase_ms: 2_000,
+    chip_erase_ms: 150_000,
+};
+
+/// Timeouts of the Micron compatible xSPI parts, using the slowest times of both.
+const XSPI_TIMEOUTS: Timeouts = Timeouts {
+    register_write_ms: 40,
+    page_program_ms: 3,
+    sector_erase_ms: 400,
+    block_erase_ms: 2_000,
+    chip_erase_ms: 600_000,
+};
+
+/// Deep power-down of the Micron compatible xSPI parts, using the slowest timings of both.
+const XSPI_POWER_DOWN: PowerDown = PowerDown {
+    enter: 0xB9,
+    release: 0xAB,
+    enter_time_us: 3,
+    release_time_us: 50,
+};
+
+/// Macronix MX25UW25645G, 256 Mbit, 1.8 V. On the Nucleo-H7S3L8.
+pub const MX25UW25645G: Chip = Chip {
+    name: "MX25UW25645G",
+    jedec_id: [0xC2, 0x81, 0x39],
+    memory_type: MemoryType::Macronix,
+    command_extension: CommandExtension::Inverted,
+    dtr_command_extension: CommandExtension::Inverted,
+    commands: Commands {
+        octal_read: 0xEC,
+        octal_dtr_read: 0xEE,
+        ..Commands::JEDEC
+    },
+    opi_enable: MACRONIX_OPI_ENABLE,
+    registers: MACRONIX_REGISTERS,
+    suspend: Some(MACRONIX_SUSPEND),
+    write_buffer: Some(WriteBuffer {
+        initial: 0x22,
+        append: 0x24,
+        confirm: 0x31,
+    }),
+    protection: Some(Protection {
+        select_advanced: 0x68,
+        read_lock: 0x2D,
+        write_lock: 0x2C,
+        read_dpb: 0xE0,
+        write_dpb: 0xE1,
+        read_spb: 0xE2,
+        write_spb: 0xE3,
+        erase_spb: 0xE4,
+        gang_lock: 0x7E,
+        gang_unlock: 0x98,
+        read_password: 0x27,
+        write_password: 0x28,
+        password_unlock: 0x29,
+    }),
+    otp: Some(Otp {
+        size: 1024,
+        enter: 0xB1,
+        exit: 0xC1,
+        lock: 0x2F,
+        locked: SecurityRegister::LDSO,
+    }),
+    fast_boot: Some(FastBoot {
+        read: 0x16,
+        write: 0x17,
+        erase: 0x18,
+    }),
+    power_down: PowerDown {
+        enter: 0xB9,
+        release: 0xAB,
+        enter_time_us: 10,
+        release_time_us: 30,
+    },
+    timeouts: MACRONIX_TIMEOUTS,
+};
+
+/// Macronix MX25UW51245G, 512 Mbit, 1.8 V.
+pub const MX25UW51245G: Chip = Chip {
+    name: "MX25UW51245G",
+    jedec_id: [0xC2, 0x81, 0x3A],
+    timeouts: Timeouts {
+        chip_erase_ms: 300_000,
+        ..MACRONIX_TIMEOUTS
+    },
+    ..MX25UW25645G
+};
+
+/// Winbond W35T51NW, 512 Mbit, 1.8 V. Octal mode is DTR only.
+pub const W35T51NW: Chip = Chip {
+    name: "W35T51NW",
+    jedec_id: [0xEF, 0x5B, 0x1A],
+    memory_type: MemoryType::Micron,
+    command_extension: CommandExtension::None,
+    dtr_command_extension: CommandExtension::Repeat,
+    commands: Commands {
+        octal_read: 0xCC,
+        ..Commands::JEDEC
+    },
+    opi_enable: OpiEnable {
+        address: 0x0000_0000,
+        mask: 0xFF,
+        spi: 0xFF,
+        octal_str: None,
+        octal_dtr: 0xE7,
+    },
+    registers: XSPI_REGISTERS,
+    suspend: Some(XSPI_SUSPEND),
+    write_buffer: None,
+    protection: None,
+    otp: None,
+    fast_boot: None,
+    power_down: XSPI_POWER_DOWN,
+    timeouts: XSPI_TIMEOUTS,
+};
+
+/// ISSI IS25WX256, 256 Mbit, 1.8 V.
+pub const IS25WX256: Chip = Chip {
+    name: "IS25WX256",
+    jedec_id: [0x9D, 0x5B, 0x19],
+    memory_type: MemoryType::Micron,
+    command_extension: CommandExtension::None,
+    dtr_command_extension: CommandExtension::Repeat,
+    commands: Commands {
+        octal_read: 0xCC,
+        ..Commands::JEDEC
+    },
+    opi_enable: OpiEnable {
+        address: 0x0000_0000,
+        mask: 0xFF,
+        spi: 0xFF,
+        octal_str: Some(0xB7),
+        octal_dtr: 0xE7,
+    },
+    registers: XSPI_REGISTERS,
+    suspend: Some(XSPI_SUSPEND),
+    write_buffer: None,
+    protection: None,
+    otp: None,
+    fast_boot: None,
+    power_down: XSPI_POWER_DOWN,
+    timeouts: XSPI_TIMEOUTS,
+};
+
+/// All supported chips.
+pub static CHIPS: &[Chip] = &DATABASE;
+
+/// The entries of [`CHIPS`], in a named section so `xip.x` places them in RAM, where
+/// [`XipFlash::take_over`](crate::xip::XipFlash::take_over) looks the chip up.
+#[cfg_attr(feature = "stm32", unsafe(link_section = ".itcm_data.flash_lib_chips"))]
+static DATABASE: [Chip; 4] = [MX25UW25645G, MX25UW51245G, W35T51NW, IS25WX256];
+
+/// Look up the chip with the given JEDEC ID.
+pub fn lookup(jedec_id: [u8; 3]) -> Result<&'static Chip, FlashError> {
+    CHIPS
+        .iter()
+        .find(|chip| chip.jedec_id == jedec_id)
+        .ok_or(FlashError::UnknownChip(jedec_id))
+}
@@ -0,0 +1,160 @@
+//! Erasing address ranges.
+//!
+//! [`ErasePlan`] splits a sector aligned range into 64KB block erases for the aligned interior
+//! and 4KB sector erases at the edges. `erase_range` executes the plan, optionally skipping
+//! units that are already blank, and reports progress after every unit.
+
+use crate::nor_flash::check_erase_range;
+use crate::{FlashError, FlashGeometry, OpiFlashMemory, SpiFlashMemory, Transport};
+
+/// Number of bytes read at once when checking whether a unit is blank.
+const BLANK_CHECK_CHUNK: usize = 256;
+
+/// A single erase operation of a plan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EraseStep {
+    /// Erase the sector at the address.
+    Sector(u32),
+    /// Erase the 64KB block at the address.
+    Block(u32),
+}
+
+impl EraseStep {
+    /// Address of the first erased byte.
+    pub fn address(&self) -> u32 {
+        match *self {
+            EraseStep::Sector(addr) | EraseStep::Block(addr) => addr,
+        }
+    }
+}
+
+/// Iterator over the erase operations covering a range, see the [module docs](self).
+#[derive(Debug, Clone)]
+pub struct ErasePlan {
+    next: u32,
+    end: u32,
+    sector_size: u32,
+    block_size: u32,
+}
+
+impl ErasePlan {
+    /// Plan erasing `len` bytes starting at `start`, both must be sector aligned.
+    pub fn new(geometry: &FlashGeometry, start: u32, len: usize) -> Result<Self, FlashError> {
+        let end = u32::try_from(len)
+            .ok()
+            .and_then(|len| start.checked_add(len))
+            .ok_or(FlashError::OutOfBounds)?;
+        check_erase_range(geometry, start, end)?;
+        Ok(Self {
+            next: start,
+            end,
+            sector_size: geometry.sector_size as u32,
+            block_size: geometry.block_size as u32,
+        })
+    }
+
+    /// Size in bytes of the erase unit of `step`.
+    pub fn step_size(&self, step: EraseStep) -> u32 {
+        match step {
+            EraseStep::Sector(_) => self.sector_size,
+            EraseStep::Block(_) => self.block_size,
+        }
+    }
+}
+
+impl Iterator for ErasePlan {
+    type Item = EraseStep;
+
+    fn next(&mut self) -> Option<EraseStep> {
+        if self.next >= self.end {
+            return None;
+        }
+        let addr = self.next;
+        let step = if addr.is_multiple_of(self.block_size) && self.end - addr >= self.block_size {
+            EraseStep::Block(addr)
+        } else {
+            EraseStep::Sector(addr)
+        };
+        self.next += self.step_size(step);
+        Some(step)
+    }
+}
+
+/// Progress of `erase_range`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EraseProgress {
+    /// Bytes erased (or found blank) so far.
+    pub done: usize,
+    /// Bytes of the whole range.
+    pub total: usize,
+}
+
+impl EraseProgress {
+    /// Progress in percent, 100 for an empty range.
+    pub fn percent(&self) -> u8 {
+        if self.total == 0 {
+            return 100;
+        }
+        (self.done as u64 * 100 / self.total as u64) as u8
+    }
+}
+
+macro_rules! impl_erase_range {
+    ($t:ident) => {
+        impl<X: Transport> $t<X> {
+            /// Erase `len` bytes starting at `start`, both must be sector aligned.
+            pub fn erase_range(&mut self, start: u32, len: usize) -> Result<(), FlashError> {
+                self.erase_range_with(start, len, false, |_| {})
+            }
+
+            /// Like `erase_range`, but skips units that are already blank if `skip_blank` is
+            /// set and calls `progress` after every unit.
+            pub fn erase_range_with(
+                &mut self,
+                start: u32,
+                len: usize,
+                skip_blank: bool,
+                mut progress: impl FnMut(EraseProgress),
+            ) -> Result<(), FlashError> {
+                let plan = ErasePlan::new(&self.geometry, start, len)?;
+                let mut status = EraseProgress {
+                    done: 0,
+                    total: len,
+                };
+                for step in plan.clone() {
+                    let size = plan.step_size(step);
+                    if !(skip_blank && self.is_blank(step.address(), size)?) {
+                        match step {
+                            EraseStep::Sector(addr) => self.erase_sector(addr)?,
+                            EraseStep::Block(addr) => self.erase_block_64k(addr)?,
+                        }
+                    }
+                    status.done += size as usize;
+                    progress(status);
+                }
+                Ok(())
+            }
+
+            /// Whether all `len` bytes starting at `addr` are erased.
+            fn is_blank(&mut self, addr: u32, len: u32) -> Result<bool, FlashError> {
+                let mut buffer = [0; BLANK_CHECK_CHUNK];
+                let mut offset = 0;
+                while offset < len {
+                    let chunk = (len - offset).min(BLANK_CHECK_CHUNK as u32);
+                    let buffer = &mut buffer[..chunk as usize];
+                    self.read_memory(addr + offset, buffer)?;
+                    if buffer.iter().any(|&byte| byte != 0xFF) {
+                        return Ok(false);
+                    }
+                    offset += chunk;
+                }
+                Ok(true)
+            }
+        }
+    };
+}
+
+impl_erase_range!(SpiFlashMemory);
+impl_erase_range!(OpiFlashMemory);
@@ -0,0 +1,89 @@
+#[cfg(feature = "stm32")]
+use embassy_stm32::xspi::XspiError;
+
+/// Transfer error of the transport, the simulated chip never fails a transfer.
+#[cfg(not(feature = "stm32"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum XspiError {}
+
+/// Errors returned by the flash drivers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FlashError {
+    /// The XSPI peripheral failed to perform a transfer.
+    Bus(XspiError),
+    /// The address range lies outside of the flash memory, or a page program would cross a
+    /// page boundary.
+    OutOfBounds,
+    /// The address or length is not aligned to the erase size.
+    NotAligned,
+    /// The chip did not clear its Write In Progress bit in time.
+    Timeout,
+    /// The chip reported a failed program operation (P_FAIL in the security register or
+    /// the flag status register).
+    ProgramFailed,
+    /// The chip reported a failed erase operation (E_FAIL in the security register or
+    /// the flag status register).
+    EraseFailed,
+    /// The password given to unlock the solid protection bits doesn't match.
+    WrongPassword,
+    /// The region has been permanently locked and can't be programmed anymore.
+    Locked,
+    /// The data read back after programming doesn't match what was written.
+    VerifyFailed,
+    /// A caller supplied buffer is smaller than required.
+    BufferTooSmall,
+    /// The chip's SFDP tables are missing or malformed, or don't match the chip's database
+    /// entry.
+    InvalidSfdp,
+    /// The chip's JEDEC ID isn't in the chip database.
+    UnknownChip([u8; 3]),
+    /// The chip doesn't support the operation.
+    Unsupported,
+    /// The operation is not possible in the current access mode, e.g. an indirect command
+    /// while the flash is memory mapped.
+    WrongMode,
+    /// The partition table doesn't allow writing the range, see [`policy`](crate::policy).
+    PolicyViolation,
+}
+
+impl From<XspiError> for FlashError {
+    fn from(e: XspiError) -> Self {
+        FlashError::Bus(e)
+    }
+}
+
+/// Error of an operation that consumes a driver or handle, returned together with it so the
+/// XSPI peripheral and its pins aren't lost. Which state `flash` is in is documented by the
+/// operation.
+pub struct HandleError<F> {
+    pub error: FlashError,
+    pub flash: F,
+}
+
+impl<F> HandleError<F> {
+    pub(crate) fn new(error: FlashError, flash: F) -> Self {
+        Self { error, flash }
+    }
+}
+
+impl<F> From<HandleError<F>> for FlashError {
+    fn from(e: HandleError<F>) -> Self {
+        e.error
+    }
+}
+
+// Not derived, as the drivers implement neither trait.
+impl<F> core::fmt::Debug for HandleError<F> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<F> defmt::Format for HandleError<F> {
+    fn format(&self, f: defmt::Formatter) {
+        self.error.format(f)
+    }
+}
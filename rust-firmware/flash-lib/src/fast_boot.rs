@@ -0,0 +1,175 @@
+//! Fast boot register.
+//!
+//! With fast boot enabled the chip starts streaming data from a configured address as soon as
+//! chip select is asserted after power-up, without receiving a read command first. The
+//! register is non-volatile: programming can only clear bits, erasing sets all of them which
+//! disables fast boot.
+//!
+//! The bootloader can also use the configured start address to find the application, see the
+//! `fast-boot` feature of the bootloader.
+
+use crate::chip::FastBoot;
+use crate::polling::WriteOperation;
+use crate::{FlashError, OpiFlashMemory, SpiFlashMemory, Transport};
+
+/// Fast boot start address, bits 31:4 of the register.
+const FBR_FBSA_MASK: u32 = 0xFFFF_FFF0;
+/// Reserved bit, always one.
+const FBR_RESERVED: u32 = 1 << 3;
+/// Fast boot start delay cycles.
+const FBR_FBSD_MASK: u32 = 0b110;
+const FBR_FBSD_SHIFT: u32 = 1;
+/// Fast boot enable, active low.
+const FBR_FBE: u32 = 1 << 0;
+/// Value of the erased register.
+const FBR_ERASED: u32 = 0xFFFF_FFFF;
+
+/// Dummy cycles between chip select and the first data of a fast boot read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FastBootDelay {
+    Cycles7 = 0b00,
+    Cycles9 = 0b01,
+    Cycles11 = 0b10,
+    /// Default of the erased register.
+    Cycles13 = 0b11,
+}
+
+impl FastBootDelay {
+    /// Number of dummy cycles.
+    pub const fn cycles(&self) -> u8 {
+        match self {
+            Self::Cycles7 => 7,
+            Self::Cycles9 => 9,
+            Self::Cycles11 => 11,
+            Self::Cycles13 => 13,
+        }
+    }
+
+    fn from_bits(bits: u32) -> Self {
+        match bits & 0b11 {
+            0b00 => Self::Cycles7,
+            0b01 => Self::Cycles9,
+            0b10 => Self::Cycles11,
+            _ => Self::Cycles13,
+        }
+    }
+}
+
+/// Contents of the fast boot register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FastBootConfig {
+    /// Flash address the chip starts reading from, 16 byte aligned.
+    pub start_address: u32,
+    pub delay: FastBootDelay,
+    pub enabled: bool,
+}
+
+impl FastBootConfig {
+    /// Enabled fast boot from `start_address`.
+    pub const fn new(start_address: u32, delay: FastBootDelay) -> Self {
+        Self {
+            start_address,
+            delay,
+            enabled: true,
+        }
+    }
+
+    /// Configuration of the erased register, fast boot disabled.
+    pub const DISABLED: Self = Self {
+        start_address: FBR_FBSA_MASK,
+        delay: FastBootDelay::Cycles13,
+        enabled: false,
+    };
+
+    /// Decode a raw register value.
+    pub fn from_register(value: u32) -> Self {
+        Self {
+            start_address: value & FBR_FBSA_MASK,
+            delay: FastBootDelay::from_bits((value & FBR_FBSD_MASK) >> FBR_FBSD_SHIFT),
+            enabled: value & FBR_FBE == 0,
+        }
+    }
+
+    /// Encode as raw register value. Fails if the start address isn't 16 byte aligned.
+    pub fn to_register(&self) -> Result<u32, FlashError> {
+        if self.start_address & !FBR_FBSA_MASK != 0 {
+            return Err(FlashError::NotAligned);
+        }
+        let enable = if self.enabled { 0 } else { FBR_FBE };
+        Ok(self.start_address
+            | FBR_RESERVED
+            | ((self.delay as u32) << FBR_FBSD_SHIFT) & FBR_FBSD_MASK
+            | enable)
+    }
+}
+
+macro_rules! impl_fast_boot {
+    ($t:ident) => {
+        impl<X: Transport> $t<X> {
+            fn fast_boot_commands(&self) -> Result<FastBoot, FlashError> {
+                self.chip.fast_boot.ok_or(FlashError::Unsupported)
+            }
+
+            /// Read the raw fast boot register.
+            pub fn read_fast_boot_register(&mut self) -> Result<u32, FlashError> {
+                let commands = self.fast_boot_commands()?;
+                let mut value = [0; 4];
+                self.asp_read(commands.read, None, false, &mut value)?;
+                Ok(u32::from_le_bytes(value))
+            }
+
+            /// Read the fast boot configuration.
+            pub fn read_fast_boot(&mut self) -> Result<FastBootConfig, FlashError> {
+                Ok(FastBootConfig::from_register(
+                    self.read_fast_boot_register()?,
+                ))
+            }
+
+            /// Program the fast boot configuration and verify it by reading it back.
+            ///
+            /// The register is only erased if the new value needs bits to be set again.
+            pub fn write_fast_boot(&mut self, config: &FastBootConfig) -> Result<(), FlashError> {
+                let commands = self.fast_boot_commands()?;
+                let value = config.to_register()?;
+                if config.enabled {
+                    self.geometry().check_bounds(config.start_address, 1)?;
+                }
+
+                let current = self.read_fast_boot_register()?;
+                if current == value {
+                    return Ok(());
+                }
+                if current & value != value {
+                    self.erase_fast_boot()?;
+                }
+                if value != FBR_ERASED {
+                    self.enable_write()?;
+                    self.asp_write(commands.write, None, &value.to_le_bytes())?;
+                    self.finish_write(WriteOperation::Program)?;
+                }
+
+                if self.read_fast_boot_register()? != value {
+                    return Err(FlashError::ProgramFailed);
+                }
+                Ok(())
+            }
+
+            /// Erase the fast boot register, which disables fast boot.
+            pub fn erase_fast_boot(&mut self) -> Result<(), FlashError> {
+                let commands = self.fast_boot_commands()?;
+                self.enable_write()?;
+                self.exec_command(commands.erase)?;
+                self.finish_write(WriteOperation::SectorErase)?;
+                if self.read_fast_boot_register()? != FBR_ERASED {
+                    return Err(FlashError::EraseFailed);
+                }
+                Ok(())
+            }
+        }
+    };
+}
+
+impl_fast_boot!(SpiFlashMemory);
+impl_fast_boot!(OpiFlashMemory);
@@ -0,0 +1,380 @@
+//! XSPI peripherals the drivers can run on.
+//!
+//! Embassy's XSPI driver is the drivers' [`Transport`] on the STM32. It doesn't cover a few
+//! registers the drivers need (DQS, automatic status polling), and doesn't know where the flash
+//! is mapped. [`XspiInstance`] provides that for each peripheral.
+
+use cortex_m::peripheral::{DCB, DWT};
+use embassy_stm32::{
+    Peri,
+    mode::{Blocking, Mode},
+    pac::{self, xspi::vals},
+    peripherals,
+    rcc::mux::{ClockMux, Xspisel},
+    xspi::{
+        self, CLKPin, D0Pin, D1Pin, D2Pin, D3Pin, D4Pin, D5Pin, D6Pin, D7Pin, DQS0Pin, NCSPin, Xspi,
+    },
+};
+use embassy_time::Duration;
+
+use crate::transport::{
+    AddressSize, DummyCycles, MemoryType, TransferConfig, Transport, XspiWidth,
+};
+use crate::{FlashError, SpiFlashMemory};
+
+/// Clock cycles between two automatic status reads.
+const POLL_INTERVAL_CYCLES: u16 = 64;
+
+/// Fastest core clock of the STM32H7S in MHz, which turns the polling timeouts into cycles.
+const MAX_CORE_CLOCK_MHZ: u64 = 600;
+
+/// FMODE values of the control register.
+const FMODE_INDIRECT_WRITE: u8 = 0b00;
+const FMODE_AUTO_POLLING: u8 = 0b10;
+
+/// An XSPI peripheral with everything the drivers need to know about it.
+pub trait XspiInstance: xspi::Instance + 'static {
+    /// Address the flash is mapped at in memory mapped mode.
+    const MAPPED_ADDRESS: u32;
+
+    /// The peripheral's register block.
+    fn regs() -> pac::xspi::Xspi;
+
+    /// Select the kernel clock of the peripheral.
+    fn select_kernel_clock(mux: &mut ClockMux, source: Xspisel);
+}
+
+impl XspiInstance for peripherals::XSPI1 {
+    const MAPPED_ADDRESS: u32 = 0x9000_0000;
+
+    fn regs() -> pac::xspi::Xspi {
+        pac::XSPI1
+    }
+
+    fn select_kernel_clock(mux: &mut ClockMux, source: Xspisel) {
+        mux.xspi1sel = source;
+    }
+}
+
+impl XspiInstance for peripherals::XSPI2 {
+    const MAPPED_ADDRESS: u32 = crate::MEMORY_MAPPED_FLASH_ADDRESS;
+
+    fn regs() -> pac::xspi::Xspi {
+        pac::XSPI2
+    }
+
+    fn select_kernel_clock(mux: &mut ClockMux, source: Xspisel) {
+        mux.xspi2sel = source;
+    }
+}
+
+/// Configures the DQS pin, which embassy's XSPI driver doesn't handle.
+pub(crate) fn configure_dqs<T: XspiInstance>(pin: Peri<'static, impl DQS0Pin<T>>) {
+    use pac::gpio::vals::{Moder, Ospeedr, Pupdr};
+
+    /// Distance between the register blocks of two GPIO ports.
+    const PORT_STRIDE: usize = 0x400;
+
+    let n = pin.pin() as usize;
+    // SAFETY: the GPIO ports are laid out contiguously starting at GPIOA, and the pin is owned.
+    let port = unsafe {
+        pac::gpio::Gpio::from_ptr(
+            pac::GPIOA
+                .as_ptr()
+                .cast::<u8>()
+                .add(pin.port() as usize * PORT_STRIDE)
+                .cast(),
+        )
+    };
+    port.afr(n / 8).modify(|w| w.set_afr(n % 8, pin.af_num()));
+    port.ospeedr()
+        .modify(|w| w.set_ospeedr(n, Ospeedr::VERY_HIGH_SPEED));
+    port.pupdr().modify(|w| w.set_pupdr(n, Pupdr::FLOATING));
+    port.moder().modify(|w| w.set_moder(n, Moder::ALTERNATE));
+}
+
+/// XSPI configuration used until the chip has been identified, matches
+/// [`TimingProfile::DEFAULT`](crate::TimingProfile::DEFAULT).
+pub(crate) fn xspi_config() -> xspi::Config {
+    use xspi::{ChipSelectHighTime, FIFOThresholdLevel, MemorySize, MemoryType, WrapSize};
+
+    xspi::Config {
+        fifo_threshold: FIFOThresholdLevel::_4Bytes,
+        memory_type: MemoryType::Macronix,
+        delay_hold_quarter_cycle: true,
+        device_size: MemorySize::_32MiB,
+        chip_select_high_time: ChipSelectHighTime::_2Cycle,
+        free_running_clock: false,
+        clock_mode: false,
+        wrap_size: WrapSize::None,
+        // 300 MHz clock / (3 + 1) = 75 MHz. This is above the max for READ instructions so the
+        // FAST READ must be used. The nucleo board's flash  can run at up to 133 MHz in SPI mode
+        // and 200 MHz in OPI mode. This clock prescaler must be even otherwise the clock will not
+        // have symmetric high and low times.
+        // The clock can also be fed by one of the PLLs to allow for more flexible clock rates.
+        clock_prescaler: 3,
+        sample_shifting: false,
+        chip_select_boundary: 0,
+        max_transfer: 0,
+        refresh: 0,
+    }
+}
+
+impl<T: XspiInstance> SpiFlashMemory<Xspi<'static, T, Blocking>> {
+    /// Set up the XSPI peripheral `peri` with an octal flash on the given pins, and identify
+    /// the chip.
+    ///
+    /// The kernel clock of the peripheral must run as in
+    /// [`TimingProfile::DEFAULT`](crate::TimingProfile::DEFAULT), see
+    /// [`TimingProfile::configure_rcc`](crate::TimingProfile::configure_rcc).
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        peri: Peri<'static, T>,
+        clk: Peri<'static, impl CLKPin<T>>,
+        d0: Peri<'static, impl D0Pin<T>>,
+        d1: Peri<'static, impl D1Pin<T>>,
+        d2: Peri<'static, impl D2Pin<T>>,
+        d3: Peri<'static, impl D3Pin<T>>,
+        d4: Peri<'static, impl D4Pin<T>>,
+        d5: Peri<'static, impl D5Pin<T>>,
+        d6: Peri<'static, impl D6Pin<T>>,
+        d7: Peri<'static, impl D7Pin<T>>,
+        ncs: Peri<'static, impl NCSPin<T>>,
+        dqs: Peri<'static, impl DQS0Pin<T>>,
+    ) -> Result<Self, FlashError> {
+        let xspi = Xspi::new_blocking_xspi(
+            peri,
+            clk,
+            d0,
+            d1,
+            d2,
+            d3,
+            d4,
+            d5,
+            d6,
+            d7,
+            ncs,
+            xspi_config(),
+        );
+        configure_dqs(dqs);
+        Self::with_transport(xspi)
+    }
+}
+
+/// Converts a transaction to embassy's transfer configuration.
+pub(crate) fn hal_transfer(transaction: &TransferConfig) -> xspi::TransferConfig {
+    xspi::TransferConfig {
+        iwidth: hal_width(transaction.iwidth),
+        instruction: transaction.instruction,
+        isize: hal_size(transaction.isize),
+        idtr: transaction.idtr,
+        adwidth: hal_width(transaction.adwidth),
+        address: transaction.address,
+        adsize: hal_size(transaction.adsize),
+        addtr: transaction.addtr,
+        abwidth: hal_width(transaction.abwidth),
+        alternate_bytes: transaction.alternate_bytes,
+        absize: hal_size(transaction.absize),
+        abdtr: transaction.abdtr,
+        dwidth: hal_width(transaction.dwidth),
+        ddtr: transaction.ddtr,
+        dummy: hal_dummy(transaction.dummy),
+    }
+}
+
+fn hal_width(width: XspiWidth) -> xspi::XspiWidth {
+    match width {
+        XspiWidth::NONE => xspi::XspiWidth::NONE,
+        XspiWidth::SING => xspi::XspiWidth::SING,
+        XspiWidth::DUAL => xspi::XspiWidth::DUAL,
+        XspiWidth::QUAD => xspi::XspiWidth::QUAD,
+        XspiWidth::OCTO => xspi::XspiWidth::OCTO,
+    }
+}
+
+fn hal_size(size: AddressSize) -> xspi::AddressSize {
+    match size {
+        AddressSize::_8bit => xspi::AddressSize::_8bit,
+        AddressSize::_16bit => xspi::AddressSize::_16bit,
+        AddressSize::_24bit => xspi::AddressSize::_24bit,
+        AddressSize::_32bit => xspi::AddressSize::_32bit,
+    }
+}
+
+fn hal_dummy(dummy: DummyCycles) -> xspi::DummyCycles {
+    use xspi::DummyCycles::*;
+    // A static in a named section rather than a constant, so `xip.x` places it in RAM.
+    #[unsafe(link_section = ".itcm_data.flash_lib_hal_dummy_cycles")]
+    static CYCLES: [xspi::DummyCycles; 32] = [
+        _0, _1, _2, _3, _4, _5, _6, _7, _8, _9, _10, _11, _12, _13, _14, _15, _16, _17, _18, _19,
+        _20, _21, _22, _23, _24, _25, _26, _27, _28, _29, _30, _31,
+    ];
+    CYCLES[dummy.cycles() as usize]
+}
+
+/// Converts a flash size in bytes to the XSPI device size setting.
+fn device_size(size: usize) -> xspi::MemorySize {
+    use xspi::MemorySize::*;
+    match size.next_power_of_two().trailing_zeros() {
+        ..=10 => _1KiB,
+        11 => _2KiB,
+        12 => _4KiB,
+        13 => _8KiB,
+        14 => _16KiB,
+        15 => _32KiB,
+        16 => _64KiB,
+        17 => _128KiB,
+        18 => _256KiB,
+        19 => _512KiB,
+        20 => _1MiB,
+        21 => _2MiB,
+        22 => _4MiB,
+        23 => _8MiB,
+        24 => _16MiB,
+        25 => _32MiB,
+        26 => _64MiB,
+        27 => _128MiB,
+        28 => _256MiB,
+        29 => _512MiB,
+        30 => _1GiB,
+        31 => _2GiB,
+        _ => _4GiB,
+    }
+}
+
+/// Enables the core's cycle counter if it isn't running yet, and returns its current value.
+fn start_cycle_counter() -> u32 {
+    /// TRCENA of DEMCR, enables the DWT.
+    const DEMCR_TRCENA: u32 = 1 << 24;
+    /// CYCCNTENA of the DWT's control register.
+    const CTRL_CYCCNTENA: u32 = 1;
+    /// Key unlocking the DWT's registers, which the Cortex-M7 locks after reset.
+    const LAR_KEY: u32 = 0xC5AC_CE55;
+
+    // SAFETY: only sets enable bits, which a debugger using the DWT sets as well. The counter
+    // itself is never reset, so other users of it aren't disturbed.
+    unsafe {
+        let (dcb, dwt) = (&*DCB::PTR, &*DWT::PTR);
+        dcb.demcr.modify(|demcr| demcr | DEMCR_TRCENA);
+        dwt.lar.write(LAR_KEY);
+        dwt.ctrl.modify(|ctrl| ctrl | CTRL_CYCCNTENA);
+        dwt.cyccnt.read()
+    }
+}
+
+impl<T: XspiInstance, M: Mode> Transport for Xspi<'static, T, M> {
+    fn command(&mut self, transaction: &TransferConfig) -> Result<(), FlashError> {
+        self.blocking_command(&hal_transfer(transaction))?;
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut [u8], transaction: TransferConfig) -> Result<(), FlashError> {
+        self.blocking_read(buffer, hal_transfer(&transaction))?;
+        Ok(())
+    }
+
+    fn write(&mut self, buffer: &[u8], transaction: TransferConfig) -> Result<(), FlashError> {
+        self.blocking_write(buffer, hal_transfer(&transaction))?;
+        Ok(())
+    }
+
+    /// Polls with the XSPI's automatic status-polling mode: the peripheral repeats the read
+    /// until the bits clear, without the CPU issuing every read.
+    fn poll_until_clear(
+        &mut self,
+        mask: u8,
+        addressed: bool,
+        timeout: Duration,
+    ) -> Result<(), FlashError> {
+        let regs = T::regs();
+        while regs.sr().read().busy() {}
+
+        regs.psmkr().write(|w| w.set_mask(mask as u32));
+        regs.psmar().write(|w| w.set_match_(0));
+        regs.pir().write(|w| w.set_interval(POLL_INTERVAL_CYCLES));
+        regs.cr().modify(|w| {
+            w.set_pmm(vals::MatchMode::AND);
+            // Stop polling on the first match.
+            w.set_apms(true);
+            w.set_fmode(vals::FunctionalMode::from_bits(FMODE_AUTO_POLLING));
+        });
+        // Rewriting the register of the last phase before data starts the polling.
+        if addressed {
+            regs.ar().write_value(regs.ar().read());
+        } else {
+            regs.ir().write_value(regs.ir().read());
+        }
+
+        // The timeout is counted in core cycles, as the time driver doesn't advance in the
+        // critical section `XipFlash` polls in. Assuming the fastest core clock, it is never
+        // shorter than requested.
+        let budget = timeout.as_micros().saturating_mul(MAX_CORE_CLOCK_MHZ);
+        let mut elapsed = 0u64;
+        let mut last = start_cycle_counter();
+        let result = loop {
+            if regs.sr().read().smf() {
+                regs.fcr().write(|w| w.set_csmf(true));
+                break Ok(());
+            }
+            // SAFETY: reading the cycle counter has no side effects.
+            let now = unsafe { (*DWT::PTR).cyccnt.read() };
+            elapsed += now.wrapping_sub(last) as u64;
+            last = now;
+            if elapsed > budget {
+                regs.cr().modify(|w| w.set_abort(true));
+                while regs.cr().read().abort() {}
+                break Err(FlashError::Timeout);
+            }
+        };
+
+        regs.cr()
+            .modify(|w| w.set_fmode(vals::FunctionalMode::from_bits(FMODE_INDIRECT_WRITE)));
+        result
+    }
+
+    fn set_memory_type(&mut self, memory_type: MemoryType) {
+        let mut config = self.get_config();
+        config.memory_type = match memory_type {
+            MemoryType::Micron => xspi::MemoryType::Micron,
+            MemoryType::Macronix => xspi::MemoryType::Macronix,
+            MemoryType::Standard => xspi::MemoryType::Standard,
+        };
+        self.set_config(&config);
+    }
+
+    fn set_device_size(&mut self, size: usize) {
+        let mut config = self.get_config();
+        config.device_size = device_size(size);
+        self.set_config(&config);
+    }
+
+    fn set_prescaler(&mut self, prescaler: u8) {
+        let mut config = self.get_config();
+        config.clock_prescaler = prescaler;
+        self.set_config(&config);
+    }
+
+    /// Embassy's XSPI driver has no notion of DQS, so the bit is set directly in the
+    /// peripheral. It survives the driver's command setup, which only modifies the other CCR
+    /// fields.
+    fn set_dqs(&mut self, enabled: bool) {
+        T::regs().ccr().modify(|w| w.set_dqse(enabled));
+    }
+
+    fn enable_memory_mapped(
+        &mut self,
+        read: TransferConfig,
+        write: TransferConfig,
+    ) -> Result<(), FlashError> {
+        self.enable_memory_mapped_mode(hal_transfer(&read), hal_transfer(&write))?;
+        Ok(())
+    }
+
+    fn disable_memory_mapped(&mut self) {
+        self.disable_memory_mapped_mode();
+    }
+
+    fn mapped_ptr(&self) -> *const u8 {
+        T::MAPPED_ADDRESS as *const u8
+    }
+}
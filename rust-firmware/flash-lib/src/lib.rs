@@ -1,382 +1,334 @@
 #![no_std]
 
-//! For Nucleo STM32H7S3L8 MB1737, has MX25UW25645GXDI00
-
-use assign_resources::assign_resources;
-use embassy_stm32::peripherals;
-use embassy_stm32::{
-    Config, Peri,
-    mode::Blocking,
-    rcc,
-    time::Hertz,
-    xspi::{self, AddressSize, DummyCycles, TransferConfig, Xspi, XspiWidth},
-};
+//! Drivers for Macronix and other octal SPI NOR flash chips on the STM32H7S XSPI peripherals.
+//!
+//! The defaults of the Nucleo STM32H7S3L8 (MB1737) are in [`nucleo_h7s3l8`].
 
 use core::cmp::min;
 
-/// Gives the underlying type for a `Peri` peripheral reference.
-#[macro_export]
-macro_rules! peri_type {
-    ($t:ty) => {
-        <$t as core::ops::Deref>::Target
-    };
-}
-
-assign_resources! {
-    flash_memory: FlashMemoryResources {
-        spi: XSPI2 = FlashMemorySpi,
-        clk: PN6 = FlashMemoryClk,
-        d0: PN2 = FlashMemoryD0,
-        d1: PN3 = FlashMemoryD1,
-        d2: PN4 = FlashMemoryD2,
-        d3: PN5 = FlashMemoryD3,
-        d4: PN8 = FlashMemoryD4,
-        d5: PN9 = FlashMemoryD5,
-        d6: PN10 = FlashMemoryD6,
-        d7: PN11 = FlashMemoryD7,
-        ncs: PN1 = FlashMemoryNcs,
-
-    },
-    debug: LedResources {
-        led: PD10 = LedPin,
-    }
-}
-
-fn configure_rcc(rcc: &mut rcc::Config) {
-    use embassy_stm32::rcc::{
-        AHBPrescaler, APBPrescaler, Hse, HseMode, Pll, PllDiv, PllMul, PllPreDiv, PllSource,
-        Sysclk, VoltageScale,
-    };
-
-    rcc.hse = Some(Hse {
-        freq: Hertz(24_000_000),
-        mode: HseMode::Oscillator,
-    });
-    rcc.pll1 = Some(Pll {
-        source: PllSource::HSE,
-        prediv: PllPreDiv::DIV3,
-        mul: PllMul::MUL150,
-        divp: Some(PllDiv::DIV2),
-        divq: None,
-        divr: None,
-        divs: None,
-        divt: None,
-    });
-    rcc.sys = Sysclk::PLL1_P; // 600 Mhz
-    rcc.ahb_pre = AHBPrescaler::DIV2; // 300 Mhz
-    rcc.apb1_pre = APBPrescaler::DIV2; // 150 Mhz
-    rcc.apb2_pre = APBPrescaler::DIV2; // 150 Mhz
-    rcc.apb4_pre = APBPrescaler::DIV2; // 150 Mhz
-    rcc.apb5_pre = APBPrescaler::DIV2; // 150 Mhz
-    rcc.voltage_scale = VoltageScale::HIGH;
-}
-
-pub fn init() -> AssignedResources {
-    let mut config = Config::default();
-    configure_rcc(&mut config.rcc);
-
-    let p = embassy_stm32::init(config);
-
-    split_resources!(p)
-}
+use chip::{FailStatus, Timeouts};
+use polling::WriteOperation;
+use registers::{BusMode, StatusRegister};
+use transport::{AddressSize, DummyCycles, TransferConfig, XspiWidth};
+
+#[cfg(not(feature = "stm32"))]
+extern crate std;
+
+#[cfg(feature = "stm32")]
+mod asynch;
+pub mod chip;
+pub mod erase;
+mod error;
+pub mod fast_boot;
+#[cfg(feature = "stm32")]
+pub mod instance;
+pub mod mapped;
+mod nor_flash;
+#[cfg(feature = "stm32")]
+pub mod nucleo_h7s3l8;
+pub mod otp;
+pub mod partition;
+pub mod policy;
+mod polling;
+pub mod power;
+pub mod protection;
+pub mod recovery;
+pub mod registers;
+pub mod sfdp;
+#[cfg(not(feature = "stm32"))]
+pub mod sim;
+pub mod suspend;
+pub mod timing;
+#[cfg(feature = "trace")]
+pub mod trace;
+pub mod transport;
+pub mod update;
+pub mod write_buffer;
+pub mod xip;
+
+pub use chip::Chip;
+pub use error::{FlashError, HandleError};
+#[cfg(feature = "stm32")]
+pub use instance::XspiInstance;
+pub use mapped::MappedFlash;
+pub use partition::MEMORY_MAPPED_FLASH_ADDRESS;
+pub use sfdp::FlashGeometry;
+pub use timing::TimingProfile;
+pub use transport::{DefaultTransport, Transport};
 
 /// Max size (in bytes) that can be written in a single page program operation.
 const MEMORY_PAGE_SIZE: usize = 256;
 
-/// The address in memory where the flash chip is mapped when in memory mapped mode.
-/// This is the address for the XSPI2 peripheral
-pub const MEMORY_MAPPED_FLASH_ADDRESS: u32 = 0x7000_0000;
-
-/// ID for the Macronix MX25UW25645GXDI00 flash chip.
-pub const MACRONIX_ID: u8 = 0xC2;
+/// Total size (in bytes) of the flash chip.
+pub const MEMORY_SIZE: usize = 32 * 1024 * 1024;
+
+/// Size (in bytes) of the smallest erasable unit, erased by `erase_sector`.
+pub const SECTOR_SIZE: usize = 4 * 1024;
+
+/// Size (in bytes) of a block erased by `erase_block_64k`.
+pub const BLOCK_64K_SIZE: usize = 64 * 1024;
+
+/// Chip a driver assumes until it has identified the chip, the Nucleo's MX25UW25645G. Like the
+/// geometry below, a static in a named section rather than a constant, so `xip.x` places it in
+/// RAM.
+#[cfg_attr(
+    feature = "stm32",
+    unsafe(link_section = ".itcm_data.flash_lib_initial_chip")
+)]
+static INITIAL_CHIP: Chip = chip::MX25UW25645G;
+
+/// Geometry a driver assumes until it has read the chip's SFDP tables.
+#[cfg_attr(
+    feature = "stm32",
+    unsafe(link_section = ".itcm_data.flash_lib_initial_geometry")
+)]
+static INITIAL_GEOMETRY: FlashGeometry = FlashGeometry::MX25UW25645G;
+
+/// Converts a number of dummy cycles to the XSPI setting, saturating at the max of 31.
+fn dummy_cycles(cycles: u8) -> DummyCycles {
+    use DummyCycles::*;
+    // A static in a named section rather than a constant, so `xip.x` places it in RAM.
+    #[cfg_attr(
+        feature = "stm32",
+        unsafe(link_section = ".itcm_data.flash_lib_dummy_cycles")
+    )]
+    static CYCLES: [DummyCycles; 32] = [
+        _0, _1, _2, _3, _4, _5, _6, _7, _8, _9, _10, _11, _12, _13, _14, _15, _16, _17, _18, _19,
+        _20, _21, _22, _23, _24, _25, _26, _27, _28, _29, _30, _31,
+    ];
+    CYCLES[min(cycles as usize, CYCLES.len() - 1)]
+}
 
 /// Implementation of access to flash chip using SPI.
 ///
-/// Chip commands are hardcoded as it depends on used chip.
-/// This targets a MX25UW25645GXDI00.
-pub struct SpiFlashMemory {
-    xspi: Xspi<'static, peri_type!(FlashMemorySpi), Blocking>,
+/// Chip commands are taken from the [`chip`] database entry matching the chip's JEDEC ID,
+/// sizes and dummy cycles are read from the chip's SFDP tables.
+pub struct SpiFlashMemory<X: Transport = DefaultTransport> {
+    transport: X,
+    memory_mapped: bool,
+    /// A copy of the database entry, so operations don't read the database, which may be in the
+    /// flash being written, see [`xip`].
+    chip: Chip,
+    geometry: FlashGeometry,
+    timing: TimingProfile,
+    timeouts: Timeouts,
 }
 
 /// Implementation of access to flash chip using Octo SPI.
 ///
-/// Chip commands are hardcoded as it depends on used chip.
-/// This targets a MX25UW25645GXDI00.
-pub struct OpiFlashMemory {
-    xspi: Xspi<'static, peri_type!(FlashMemorySpi), Blocking>,
+/// Chip commands are taken from the [`chip`] database entry matching the chip's JEDEC ID,
+/// sizes and dummy cycles are read from the chip's SFDP tables.
+pub struct OpiFlashMemory<X: Transport = DefaultTransport> {
+    transport: X,
+    memory_mapped: bool,
+    /// A copy of the database entry, like [`SpiFlashMemory`]'s.
+    chip: Chip,
+    geometry: FlashGeometry,
+    timing: TimingProfile,
+    timeouts: Timeouts,
+    /// Octal DTR instead of octal STR.
+    dtr: bool,
 }
 
-/// SPI mode commands for MX25UW25645G flash memory
-#[allow(dead_code)]
-#[repr(u8)]
-enum SpiCommand {
-    // Array access commands
-    /// Read data bytes using 3-byte address (up to 50 MHz)
-    Read3B = 0x03,
-    /// Fast read data bytes using 3-byte address with 8 dummy cycles (up to 133 MHz)
-    FastRead3B = 0x0B,
-    /// Program 1-256 bytes of data using 3-byte address
-    PageProgram3B = 0x02,
-    /// Erase 4KB sector using 3-byte address
-    SectorErase3B = 0x20,
-    /// Erase 64KB block using 3-byte address
-    BlockErase3B = 0xD8,
-    /// Read data bytes using 4-byte address (up to 50 MHz)
-    Read4B = 0x13,
-    /// Fast read data bytes using 4-byte address with 8 dummy cycles (up to 133 MHz)
-    FastRead4B = 0x0C,
-    /// Program 1-256 bytes of data using 4-byte address
-    PageProgram4B = 0x12,
-    /// Erase 4KB sector using 4-byte address
-    SectorErase4B = 0x21,
-    /// Erase 64KB block using 4-byte address
-    BlockErase4B = 0xDC,
-    /// Erase entire chip (only if no blocks are protected)
-    ChipErase = 0x60,
-
-    // Write Buffer Access commands
-    /// Read data from the 256-byte page buffer
-    ReadBuffer = 0x25,
-    /// Initialize write-to-buffer sequence, clears buffer and writes initial data
-    WriteBufferInitial = 0x22,
-    /// Continue writing data to buffer (used between WRBI and WRCF)
-    WriteBufferContinue = 0x24,
-    /// Confirm write operation, programs buffer contents to flash array
-    WriteBufferConfirm = 0x31,
-
-    // Device operation commands
-    /// Set Write Enable Latch (WEL) bit, required before write/program/erase operations
-    WriteEnable = 0x06,
-    /// Clear Write Enable Latch (WEL) bit
-    WriteDisable = 0x04,
-    /// Select write protection mode (BP mode or Advanced Sector Protection)
-    WriteProtectSelection = 0x68,
-    /// Suspend ongoing program or erase operation to allow read access
-    ProgramEraseSuspend = 0xB0,
-    /// Resume suspended program or erase operation
-    ProgramEraseResume = 0x30,
-    /// Enter deep power-down mode for minimum power consumption
-    DeepPowerDown = 0xB9,
-    /// Exit deep power-down mode and return to standby
-    ReleaseFromDeepPowerDown = 0xAB,
-    /// No operation, can terminate Reset Enable command
-    NoOperation = 0x00,
-    /// Enable reset operation (must precede Reset Memory command)
-    ResetEnable = 0x66,
-    /// Reset device to power-on state (requires prior Reset Enable)
-    ResetMemory = 0x99,
-    /// Protect all sectors using Dynamic Protection Bits (DPB)
-    GangBlockLock = 0x7E,
-    /// Unprotect all sectors by clearing Dynamic Protection Bits (DPB)
-    GangBlockUnlock = 0x98,
-
-    // Register Access commands
-    /// Read 3-byte device identification (manufacturer ID + device ID)
-    ReadIdentification = 0x9F,
-    /// Read Serial Flash Discoverable Parameters (SFDP) table
-    ReadSFDP = 0x5A,
-    /// Read 8-bit Status Register (WIP, WEL, BP bits, etc.)
-    ReadStatusRegister = 0x05,
-    /// Read 8-bit Configuration Register (ODS, TB, PBE bits)
-    ReadConfigurationRegister = 0x15,
-    /// Write Status and/or Configuration Register (1-2 bytes)
-    WriteStatusConfigurationRegister = 0x01,
-    /// Read Configuration Register 2 from specified 4-byte address
-    ReadConfigurationRegister2 = 0x71,
-    /// Write Configuration Register 2 to specified 4-byte address
-    WriteConfigurationRegister2 = 0x72,
-    /// Read 8-bit Security Register (protection status, suspend bits)
-    ReadSecurityRegister = 0x2B,
-    /// Write Security Register to set customer lock-down bit
-    WriteSecurityRegister = 0x2F,
-    /// Read 32-bit Fast Boot Register (boot address and configuration)
-    ReadFastBootRegister = 0x16,
-    /// Write 32-bit Fast Boot Register
-    WriteFastBootRegister = 0x17,
-    /// Erase Fast Boot Register (disable fast boot feature)
-    EraseFastBootRegister = 0x18,
-    /// Set burst/wrap length for read operations (16/32/64 bytes)
-    SetBurstLength = 0xC0,
-    /// Enter 8K-bit secured OTP mode for programming unique identifiers
-    EnterSecuredOTP = 0xB1,
-    /// Exit secured OTP mode and return to main array access
-    ExitSecuredOTP = 0xC1,
-    /// Write Lock Register to control SPB protection mode
-    WriteLockRegister = 0x2C,
-    /// Read Lock Register status
-    ReadLockRegister = 0x2D,
-    /// Program Solid Protection Bit (SPB) for specified sector/block
-    WriteSPB = 0xE3,
-    /// Erase all Solid Protection Bits (SPB)
-    EraseSPB = 0xE4,
-    /// Read Solid Protection Bit (SPB) status for specified sector/block
-    ReadSPB = 0xE2,
-    /// Write Dynamic Protection Bit (DPB) for specified sector
-    WriteDPB = 0xE1,
-    /// Read Dynamic Protection Bit (DPB) status for specified sector
-    ReadDPB = 0xE0,
-    /// Read 64-bit password register (only in Solid Protection mode)
-    ReadPassword = 0x27,
-    /// Write 64-bit password register
-    WritePassword = 0x28,
-    /// Unlock SPB operations using 64-bit password
-    PasswordUnlock = 0x29,
+/// Operations supported by the flash chip regardless of the bus mode it is accessed in.
+///
+/// Implemented by both [`SpiFlashMemory`] and [`OpiFlashMemory`] so that storage code can be
+/// written once and used with either. Both types also implement the `embedded-storage`
+/// `ReadNorFlash` and `NorFlash` traits on top of this, which the [`partition`] handles use.
+///
+/// While the flash is memory mapped it is borrowed by the [`MappedFlash`] guard, so no other
+/// operation can be issued.
+pub trait FlashMemory {
+    /// Reset the chip to its power-on state.
+    fn reset_memory(&mut self) -> Result<(), FlashError>;
+    /// Set the Write Enable Latch, required before any program or erase operation.
+    fn enable_write(&mut self) -> Result<(), FlashError>;
+    /// Read the 3-byte JEDEC ID (manufacturer ID + device ID).
+    fn read_id(&mut self) -> Result<[u8; 3], FlashError>;
+    /// Read `buffer.len()` bytes starting at `addr`.
+    fn read_memory(&mut self, addr: u32, buffer: &mut [u8]) -> Result<(), FlashError>;
+    /// Program `buffer` starting at `addr`, splitting the write at page boundaries.
+    /// The target area must have been erased beforehand.
+    fn write_memory(&mut self, addr: u32, buffer: &[u8]) -> Result<(), FlashError>;
+    /// Erase the 4KB sector containing `addr`.
+    fn erase_sector(&mut self, addr: u32) -> Result<(), FlashError>;
+    /// Erase the 64KB block containing `addr`.
+    fn erase_block_64k(&mut self, addr: u32) -> Result<(), FlashError>;
+    /// Erase the entire chip.
+    fn erase_chip(&mut self) -> Result<(), FlashError>;
+    /// Read the Status Register.
+    fn read_sr(&mut self) -> Result<u8, FlashError>;
+    /// Read the Configuration Register, if the chip has one.
+    fn read_cr(&mut self) -> Result<u8, FlashError>;
+    /// Write the Status and (if the chip has one) Configuration Registers.
+    fn write_sr_cr(&mut self, sr: u8, cr: u8) -> Result<(), FlashError>;
+    /// Read Configuration Register 2 (or the volatile configuration register) at `address`.
+    fn read_cr2(&mut self, address: u32) -> Result<u8, FlashError>;
+    /// Write Configuration Register 2 (or the volatile configuration register) at `address`.
+    /// Sets the Write Enable Latch itself, in both modes.
+    fn write_cr2(&mut self, address: u32, value: u8) -> Result<(), FlashError>;
+    /// Read the Security Register of Macronix chips.
+    fn read_scur(&mut self) -> Result<u8, FlashError>;
+    /// Read `buffer.len()` bytes of the SFDP tables starting at `addr`.
+    fn read_sfdp(&mut self, addr: u32, buffer: &mut [u8]) -> Result<(), FlashError>;
+    /// Sizes and timings the driver currently uses.
+    fn geometry(&self) -> FlashGeometry;
+    /// The chip the driver is talking to.
+    fn chip(&self) -> &Chip;
+    /// Map the flash until the returned guard is dropped.
+    fn enable_mm(&mut self) -> Result<MappedFlash<'_>, FlashError>;
 }
 
-/// OPI mode commands for MX25UW25645G flash memory
-#[allow(dead_code)]
-#[repr(u16)]
-enum OpiCommand {
-    // Array access commands
-    /// Read data using 8 I/O lines in STR mode with configurable dummy cycles (up to 200 MHz)
-    OctaRead = 0xEC13,
-    /// Read data using 8 I/O lines in DTR mode with configurable dummy cycles (up to 200 MHz)
-    OctaDTRRead = 0xEE11,
-    /// Program 1-256 bytes using 4-byte address and 8 I/O lines
-    PageProgram4B = 0x12ED,
-    /// Erase 4KB sector using 4-byte address
-    SectorErase4B = 0x21DE,
-    /// Erase 64KB block using 4-byte address
-    BlockErase4B = 0xDC23,
-    /// Erase entire chip (only if no blocks are protected)
-    ChipErase = 0x609F,
-
-    // Write Buffer Access commands
-    /// Read data from the 256-byte page buffer using 4-byte address
-    ReadBuffer = 0x25DA,
-    /// Initialize interruptible write-to-buffer sequence with 4-byte address
-    WriteBufferInitial = 0x22DD,
-    /// Continue writing data to buffer during interruptible sequence
-    WriteBufferContinue = 0x24DB,
-    /// Confirm and execute write operation from buffer to flash array
-    WriteBufferConfirm = 0x31CE,
-
-    // Device operation commands
-    /// Set Write Enable Latch (WEL) bit, required before write/program/erase operations
-    WriteEnable = 0x06F9,
-    /// Clear Write Enable Latch (WEL) bit, aborts write-to-buffer sequence
-    WriteDisable = 0x04FB,
-    /// Select write protection mode (BP mode or Advanced Sector Protection) - OTP bit
-    WriteProtectSelection = 0x6897,
-    /// Suspend ongoing program or erase operation to allow read from other banks
-    ProgramEraseSuspend = 0xB04F,
-    /// Resume suspended program or erase operation
-    ProgramEraseResume = 0x30CF,
-    /// Enter deep power-down mode for minimum power consumption
-    DeepPowerDown = 0xB946,
-    /// Exit deep power-down mode and return to standby
-    ReleaseFromDeepPowerDown = 0xAB54,
-    /// No operation, can terminate Reset Enable command
-    NoOperation = 0x00FF,
-    /// Enable reset operation (must precede Reset Memory command)
-    ResetEnable = 0x6699,
-    /// Reset device to power-on state, clears volatile settings
-    ResetMemory = 0x9966,
-    /// Protect all sectors using Dynamic Protection Bits (DPB)
-    GangBlockLock = 0x7E81,
-    /// Unprotect all sectors by clearing Dynamic Protection Bits (DPB)
-    GangBlockUnlock = 0x9867,
-
-    // Register Access commands
-    /// Read 3-byte device identification with 4-byte dummy address
-    ReadIdentification = 0x9F60,
-    /// Read Serial Flash Discoverable Parameters (SFDP) table with 4-byte address
-    ReadSFDP = 0x5AA5,
-    /// Read 8-bit Status Register with 4-byte dummy address
-    ReadStatusRegister = 0x05FA,
-    /// Read 8-bit Configuration Register with specific address (00000001h)
-    ReadConfigurationRegister = 0x15EA,
-    /// Write 8-bit Status Register with specific address (00000000h) or Configuration Register with address (00000001h)
-    WriteStatusConfigurationRegister = 0x01FE,
-    /// Read Configuration Register 2 from specified 4-byte address
-    ReadConfigurationRegister2 = 0x718E,
-    /// Write Configuration Register 2 to specified 4-byte address
-    WriteConfigurationRegister2 = 0x728D,
-    /// Read 8-bit Security Register with 4-byte dummy address
-    ReadSecurityRegister = 0x2BD4,
-    /// Write Security Register to set customer lock-down bit
-    WriteSecurityRegister = 0x2FD0,
-    /// Set burst/wrap length for read operations with 4-byte dummy address
-    SetBurstLength = 0xC03F,
-    /// Read 32-bit Fast Boot Register with 4-byte dummy address
-    ReadFastBootRegister = 0x16E9,
-    /// Write 32-bit Fast Boot Register with 4-byte dummy address
-    WriteFastBootRegister = 0x17E8,
-    /// Erase Fast Boot Register (disable fast boot feature)
-    EraseFastBootRegister = 0x18E7,
-    /// Enter 8K-bit secured OTP mode for programming unique identifiers
-    EnterSecuredOTP = 0xB14E,
-    /// Exit secured OTP mode and return to main array access
-    ExitSecuredOTP = 0xC13E,
-    /// Write Lock Register to control SPB protection mode with 4-byte dummy address
-    WriteLockRegister = 0x2CD3,
-    /// Read Lock Register status with 4-byte dummy address
-    ReadLockRegister = 0x2DD2,
-    /// Program Solid Protection Bit (SPB) for specified 4-byte address
-    WriteSPB = 0xE31C,
-    /// Erase all Solid Protection Bits (SPB)
-    EraseSPB = 0xE41B,
-    /// Read Solid Protection Bit (SPB) status for specified 4-byte address
-    ReadSPB = 0xE21D,
-    /// Write Dynamic Protection Bit (DPB) for specified 4-byte address
-    WriteDPB = 0xE11E,
-    /// Read Dynamic Protection Bit (DPB) status for specified 4-byte address
-    ReadDPB = 0xE01F,
-    /// Read 64-bit password register with 4-byte dummy address and 20 dummy cycles
-    ReadPassword = 0x27D8,
-    /// Write 64-bit password register with 4-byte dummy address
-    WritePassword = 0x28D7,
-    /// Unlock SPB operations using 64-bit password with 4-byte dummy address
-    PasswordUnlock = 0x29D6,
+/// Implements [`FlashMemory`] by forwarding to the inherent methods of the same name.
+macro_rules! impl_flash_memory {
+    ($t:ident) => {
+        impl<X: Transport> FlashMemory for $t<X> {
+            fn reset_memory(&mut self) -> Result<(), FlashError> {
+                <$t<X>>::reset_memory(self)
+            }
+            fn enable_write(&mut self) -> Result<(), FlashError> {
+                <$t<X>>::enable_write(self)
+            }
+            fn read_id(&mut self) -> Result<[u8; 3], FlashError> {
+                <$t<X>>::read_id(self)
+            }
+            fn read_memory(&mut self, addr: u32, buffer: &mut [u8]) -> Result<(), FlashError> {
+                <$t<X>>::read_memory(self, addr, buffer)
+            }
+            fn write_memory(&mut self, addr: u32, buffer: &[u8]) -> Result<(), FlashError> {
+                <$t<X>>::write_memory(self, addr, buffer)
+            }
+            fn erase_sector(&mut self, addr: u32) -> Result<(), FlashError> {
+                <$t<X>>::erase_sector(self, addr)
+            }
+            fn erase_block_64k(&mut self, addr: u32) -> Result<(), FlashError> {
+                <$t<X>>::erase_block_64k(self, addr)
+            }
+            fn erase_chip(&mut self) -> Result<(), FlashError> {
+                <$t<X>>::erase_chip(self)
+            }
+            fn read_sr(&mut self) -> Result<u8, FlashError> {
+                <$t<X>>::read_sr(self)
+            }
+            fn read_cr(&mut self) -> Result<u8, FlashError> {
+                <$t<X>>::read_cr(self)
+            }
+            fn write_sr_cr(&mut self, sr: u8, cr: u8) -> Result<(), FlashError> {
+                <$t<X>>::write_sr_cr(self, sr, cr)
+            }
+            fn read_cr2(&mut self, address: u32) -> Result<u8, FlashError> {
+                <$t<X>>::read_cr2(self, address)
+            }
+            fn write_cr2(&mut self, address: u32, value: u8) -> Result<(), FlashError> {
+                <$t<X>>::write_cr2(self, address, value)
+            }
+            fn read_scur(&mut self) -> Result<u8, FlashError> {
+                <$t<X>>::read_scur(self)
+            }
+            fn read_sfdp(&mut self, addr: u32, buffer: &mut [u8]) -> Result<(), FlashError> {
+                <$t<X>>::read_sfdp(self, addr, buffer)
+            }
+            fn geometry(&self) -> FlashGeometry {
+                <$t<X>>::geometry(self)
+            }
+            fn chip(&self) -> &Chip {
+                <$t<X>>::chip(self)
+            }
+            fn enable_mm(&mut self) -> Result<MappedFlash<'_>, FlashError> {
+                <$t<X>>::enable_mm(self)
+            }
+        }
+    };
 }
 
-impl SpiFlashMemory {
-    pub fn new(r: FlashMemoryResources) -> Self {
-        use xspi::{ChipSelectHighTime, FIFOThresholdLevel, MemorySize, MemoryType, WrapSize};
-
-        let config = xspi::Config {
-            fifo_threshold: FIFOThresholdLevel::_4Bytes,
-            memory_type: MemoryType::Macronix,
-            delay_hold_quarter_cycle: true,
-            device_size: MemorySize::_32MiB,
-            chip_select_high_time: ChipSelectHighTime::_2Cycle,
-            free_running_clock: false,
-            clock_mode: false,
-            wrap_size: WrapSize::None,
-            // 300 MHz clock / (3 + 1) = 75 MHz. This is above the max for READ instructions so the
-            // FAST READ must be used. The nucleo board's flash  can run at up to 133 MHz in SPI mode
-            // and 200 MHz in OPI mode. This clock prescaler must be even otherwise the clock will not
-            // have symmetric high and low times.
-            // The clock can also be fed by one of the PLLs to allow for more flexible clock rates.
-            clock_prescaler: 3,
-            sample_shifting: false,
-            chip_select_boundary: 0,
-            max_transfer: 0,
-            refresh: 0,
+impl_flash_memory!(SpiFlashMemory);
+impl_flash_memory!(OpiFlashMemory);
+
+impl<X: Transport> SpiFlashMemory<X> {
+    /// Bring the chip on `transport` into a known state and configure the driver for it.
+    ///
+    /// The chip is reset from whatever bus mode it was left in, see [`recovery`].
+    pub fn with_transport(transport: X) -> Result<Self, FlashError> {
+        let memory = Self {
+            transport,
+            memory_mapped: false,
+            chip: INITIAL_CHIP,
+            geometry: INITIAL_GEOMETRY,
+            timing: TimingProfile::DEFAULT,
+            timeouts: INITIAL_CHIP.timeouts,
         };
 
-        let xspi = Xspi::new_blocking_xspi(
-            r.spi, r.clk, r.d0, r.d1, r.d2, r.d3, r.d4, r.d5, r.d6, r.d7, r.ncs, config,
-        );
+        let (mut memory, _) = memory.recover()?;
+        memory.identify()?;
+        memory.discover_geometry()?;
+        Ok(memory)
+    }
+
+    /// The chip the driver is talking to.
+    pub fn chip(&self) -> &Chip {
+        &self.chip
+    }
+
+    /// Read the JEDEC ID and select the matching entry of the [`chip`] database.
+    ///
+    /// Fails with [`FlashError::UnknownChip`] if the chip isn't supported. The timeouts become
+    /// the chip's, unless they were changed with `set_timeouts`.
+    pub fn identify(&mut self) -> Result<&'static Chip, FlashError> {
+        let chip = chip::lookup(self.read_id()?)?;
+        self.transport.set_memory_type(chip.memory_type);
+        if self.timeouts == self.chip.timeouts {
+            self.timeouts = chip.timeouts;
+        }
+        self.chip = *chip;
+        Ok(chip)
+    }
+
+    /// Sizes and timings the driver currently uses.
+    pub fn geometry(&self) -> FlashGeometry {
+        self.geometry
+    }
 
-        let mut memory = Self { xspi };
+    /// Read the chip's SFDP tables and configure the driver and XSPI peripheral from them.
+    pub fn discover_geometry(&mut self) -> Result<FlashGeometry, FlashError> {
+        let commands = self.chip.commands;
+        let geometry = sfdp::read_geometry(&commands, |addr, buffer| self.read_sfdp(addr, buffer))?;
+        self.transport.set_device_size(geometry.size);
+        self.geometry = geometry;
+        Ok(geometry)
+    }
 
-        memory.reset_memory();
-        memory
+    /// Read `buffer.len()` bytes of the SFDP tables starting at `addr`.
+    pub fn read_sfdp(&mut self, addr: u32, buffer: &mut [u8]) -> Result<(), FlashError> {
+        let transaction = TransferConfig {
+            iwidth: XspiWidth::SING,
+            adwidth: XspiWidth::SING,
+            adsize: AddressSize::_24bit,
+            dwidth: XspiWidth::SING,
+            instruction: Some(self.chip.commands.read_sfdp as u32),
+            dummy: DummyCycles::_8,
+            address: Some(addr),
+            ..Default::default()
+        };
+        self.read(buffer, transaction)
     }
 
-    pub fn disable_mm(&mut self) {
-        self.xspi.disable_memory_mapped_mode();
+    /// Leave memory mapped mode and return to indirect access.
+    fn unmap(&mut self) {
+        self.transport.disable_memory_mapped();
+        self.memory_mapped = false;
     }
 
-    pub fn enable_mm(&mut self) {
+    /// Enter memory mapped mode, see [`MappedFlash`] for the public interface.
+    fn map(&mut self) -> Result<(), FlashError> {
+        self.check_indirect()?;
+
         let read_config = TransferConfig {
             iwidth: XspiWidth::SING,
             isize: AddressSize::_8bit,
             adwidth: XspiWidth::SING,
             adsize: AddressSize::_32bit,
             dwidth: XspiWidth::SING,
-            instruction: Some(SpiCommand::FastRead4B as u32),
+            instruction: Some(self.chip.commands.read as u32),
             dummy: DummyCycles::_8,
             ..Default::default()
         };
@@ -387,27 +339,140 @@ impl SpiFlashMemory {
             adwidth: XspiWidth::SING,
             adsize: AddressSize::_32bit,
             dwidth: XspiWidth::SING,
-            instruction: Some(SpiCommand::PageProgram4B as u32),
+            instruction: Some(self.chip.commands.page_program as u32),
             dummy: DummyCycles::_0,
             ..Default::default()
         };
-        self.xspi
-            .enable_memory_mapped_mode(read_config, write_config)
-            .unwrap();
+        self.transport
+            .enable_memory_mapped(read_config, write_config)?;
+        self.memory_mapped = true;
+        Ok(())
+    }
+
+    /// Switch the chip to octal STR mode.
+    ///
+    /// Fails with [`FlashError::Unsupported`] if the chip only supports octal DTR. On failure
+    /// the SPI driver is returned with the error. The chip may have switched anyway,
+    /// [`recover`](Self::recover) brings it back to SPI mode.
+    #[allow(clippy::result_large_err)]
+    pub fn into_octo(self) -> Result<OpiFlashMemory<X>, HandleError<Self>> {
+        self.into_octal(BusMode::OctalStr)
+    }
+
+    /// Switch the chip to octal DTR mode, which transfers data on both clock edges and doubles
+    /// the throughput of octal STR mode. Reads are sampled with the chip's DQS strobe.
+    ///
+    /// Fails like [`into_octo`](Self::into_octo).
+    #[allow(clippy::result_large_err)]
+    pub fn into_octo_dtr(self) -> Result<OpiFlashMemory<X>, HandleError<Self>> {
+        self.into_octal(BusMode::OctalDtr)
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn into_octal(mut self, mode: BusMode) -> Result<OpiFlashMemory<X>, HandleError<Self>> {
+        if let Err(error) = self.enter_octal(mode) {
+            return Err(HandleError::new(error, self));
+        }
+        self.set_prescaler(self.timing.opi_prescaler);
+        let mut flash = self.octal_driver(mode == BusMode::OctalDtr);
+        match flash.check_bus_mode(mode) {
+            Ok(()) => Ok(flash),
+            Err(error) => {
+                let mut flash = flash.spi_driver();
+                flash.set_prescaler(flash.timing.spi_prescaler);
+                Err(HandleError::new(error, flash))
+            }
+        }
+    }
+
+    fn enter_octal(&mut self, mode: BusMode) -> Result<(), FlashError> {
+        self.chip
+            .opi_enable
+            .value(mode)
+            .ok_or(FlashError::Unsupported)?;
+        self.configure_dummy_cycles()?;
+        self.enable_opi_mode(mode)
+    }
+
+    /// The octal driver for the same chip, without switching the chip's mode.
+    fn octal_driver(self, dtr: bool) -> OpiFlashMemory<X> {
+        OpiFlashMemory {
+            transport: self.transport,
+            memory_mapped: false,
+            chip: self.chip,
+            geometry: self.geometry,
+            timing: self.timing,
+            timeouts: self.timeouts,
+            dtr,
+        }
+    }
+
+    /// The timing profile the driver uses.
+    pub fn timing(&self) -> TimingProfile {
+        self.timing
     }
 
-    pub fn into_octo(mut self) -> OpiFlashMemory {
-        self.enable_opi_mode();
-        OpiFlashMemory { xspi: self.xspi }
+    /// Use the prescalers and dummy cycles of `timing`. The kernel clock must have been set up
+    /// for the same profile, see [`TimingProfile::configure_rcc`].
+    pub fn set_timing(&mut self, timing: TimingProfile) -> Result<(), FlashError> {
+        self.check_indirect()?;
+        self.set_prescaler(timing.spi_prescaler);
+        self.timing = timing;
+        Ok(())
     }
 
-    fn enable_opi_mode(&mut self) {
-        let cr2_0 = self.read_cr2(0);
-        self.enable_write();
-        self.write_cr2(0, cr2_0 | 0x01); // Set bit 0 to enable octo SPI in STR
+    fn set_prescaler(&mut self, prescaler: u8) {
+        self.transport.set_prescaler(prescaler);
     }
 
-    fn exec_command(&mut self, cmd: u8) {
+    /// Program the octal read dummy cycles of the timing profile into the chip.
+    fn configure_dummy_cycles(&mut self) -> Result<(), FlashError> {
+        let config = self.chip.registers.dummy_cycles;
+        let (bits, cycles) = config.encode(self.timing.opi_read_dummy_cycles);
+        let value = if config.mask == 0xFF {
+            bits
+        } else {
+            (self.read_cr2(config.address)? & !config.mask) | bits
+        };
+        self.write_cr2(config.address, value)?;
+        self.geometry.opi_read_dummy_cycles = cycles;
+        Ok(())
+    }
+
+    fn enable_opi_mode(&mut self, mode: BusMode) -> Result<(), FlashError> {
+        let value = self.bus_mode_value(mode)?;
+        // The chip switches to OPI as soon as the write completes, so the status register can't
+        // be polled in SPI mode afterwards.
+        self.send_write_cr2(self.chip.opi_enable.address, value)
+    }
+
+    fn check_indirect(&self) -> Result<(), FlashError> {
+        if self.memory_mapped {
+            Err(FlashError::WrongMode)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn command(&mut self, transaction: &TransferConfig) -> Result<(), FlashError> {
+        self.check_indirect()?;
+        self.transport.command(transaction)?;
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut [u8], transaction: TransferConfig) -> Result<(), FlashError> {
+        self.check_indirect()?;
+        self.transport.read(buffer, transaction)?;
+        Ok(())
+    }
+
+    fn write(&mut self, buffer: &[u8], transaction: TransferConfig) -> Result<(), FlashError> {
+        self.check_indirect()?;
+        self.transport.write(buffer, transaction)?;
+        Ok(())
+    }
+
+    fn exec_command(&mut self, cmd: u8) -> Result<(), FlashError> {
         let transaction = TransferConfig {
             iwidth: XspiWidth::SING,
             adwidth: XspiWidth::NONE,
@@ -418,55 +483,111 @@ impl SpiFlashMemory {
             dummy: DummyCycles::_0,
             ..Default::default()
         };
-        // info!("Excuting command: {:x}", transaction.instruction);
-        self.xspi.blocking_command(&transaction).unwrap();
+        self.command(&transaction)
     }
 
-    pub fn reset_memory(&mut self) {
-        self.exec_command(SpiCommand::ResetEnable as u8);
-        self.exec_command(SpiCommand::ResetMemory as u8);
-        self.wait_write_finish();
+    pub fn reset_memory(&mut self) -> Result<(), FlashError> {
+        self.exec_command(self.chip.commands.reset_enable)?;
+        self.exec_command(self.chip.commands.reset_memory)?;
+        self.wait_write_finish(WriteOperation::Register)
     }
 
-    pub fn enable_write(&mut self) {
-        self.exec_command(SpiCommand::WriteEnable as u8);
+    pub fn enable_write(&mut self) -> Result<(), FlashError> {
+        self.exec_command(self.chip.commands.write_enable)
     }
 
-    pub fn read_id(&mut self) -> [u8; 3] {
+    pub fn read_id(&mut self) -> Result<[u8; 3], FlashError> {
         let mut buffer = [0; 3];
         let transaction: TransferConfig = TransferConfig {
             iwidth: XspiWidth::SING,
             isize: AddressSize::_8bit,
             adwidth: XspiWidth::NONE,
             dwidth: XspiWidth::SING,
-            instruction: Some(SpiCommand::ReadIdentification as u32),
+            instruction: Some(self.chip.commands.read_id as u32),
             ..Default::default()
         };
-        self.xspi.blocking_read(&mut buffer, transaction).unwrap();
-        buffer
+        self.read(&mut buffer, transaction)?;
+        Ok(buffer)
     }
 
-    pub fn read_memory(&mut self, addr: u32, buffer: &mut [u8]) {
-        let transaction = TransferConfig {
+    pub fn read_memory(&mut self, addr: u32, buffer: &mut [u8]) -> Result<(), FlashError> {
+        self.geometry.check_bounds(addr, buffer.len())?;
+        let transaction = self.read_transaction(addr);
+
+        self.read(buffer, transaction)
+    }
+
+    /// Array read starting at `addr`.
+    fn read_transaction(&self, addr: u32) -> TransferConfig {
+        TransferConfig {
             iwidth: XspiWidth::SING,
             adwidth: XspiWidth::SING,
             adsize: AddressSize::_32bit,
             dwidth: XspiWidth::SING,
-            instruction: Some(SpiCommand::FastRead4B as u32),
+            instruction: Some(self.chip.commands.read as u32),
             dummy: DummyCycles::_8,
             address: Some(addr),
             ..Default::default()
-        };
+        }
+    }
 
-        self.xspi.blocking_read(buffer, transaction).unwrap();
+    /// Wait for the Write In Progress bit to clear, at most the max duration of `operation`.
+    fn wait_write_finish(&mut self, operation: WriteOperation) -> Result<(), FlashError> {
+        // The status read also sets up the transfer repeated by the automatic polling.
+        if !self.read_status()?.write_in_progress() {
+            return Ok(());
+        }
+        self.transport
+            .poll_until_clear(StatusRegister::WIP, false, self.timeouts.get(operation))
     }
 
-    fn wait_write_finish(&mut self) {
-        while (self.read_sr() & 0x01) != 0 {}
+    /// Wait for a program or erase operation to finish and check whether it succeeded.
+    fn finish_write(&mut self, operation: WriteOperation) -> Result<(), FlashError> {
+        self.wait_write_finish(operation)?;
+        self.check_write_result()
     }
 
-    fn perform_erase(&mut self, addr: u32, cmd: u8) {
-        let transaction = TransferConfig {
+    /// Read the register reporting failed (and suspended) program and erase operations.
+    fn read_fail_status(&mut self) -> Result<u8, FlashError> {
+        self.read_register(self.chip.registers.fail_status.read_opcode())
+    }
+
+    /// Check whether the last program or erase operation succeeded.
+    fn check_write_result(&mut self) -> Result<(), FlashError> {
+        let status = self.chip.registers.fail_status;
+        let value = self.read_fail_status()?;
+        match status.error(value) {
+            None => Ok(()),
+            Some(e) => {
+                if let Some(clear) = status.clear_opcode() {
+                    self.exec_command(clear)?;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn perform_erase(
+        &mut self,
+        addr: u32,
+        cmd: u8,
+        operation: WriteOperation,
+    ) -> Result<(), FlashError> {
+        self.start_erase(addr, cmd)?;
+        self.finish_write(operation)
+    }
+
+    /// Issue erase command `cmd` for `addr` without waiting for the erase to finish.
+    fn start_erase(&mut self, addr: u32, cmd: u8) -> Result<(), FlashError> {
+        self.geometry.check_bounds(addr, 1)?;
+        let transaction = self.erase_transaction(addr, cmd);
+        self.enable_write()?;
+        self.command(&transaction)
+    }
+
+    /// Erase command `cmd` of the sector or block at `addr`.
+    fn erase_transaction(&self, addr: u32, cmd: u8) -> TransferConfig {
+        TransferConfig {
             iwidth: XspiWidth::SING,
             adwidth: XspiWidth::SING,
             adsize: AddressSize::_32bit,
@@ -475,68 +596,83 @@ impl SpiFlashMemory {
             address: Some(addr),
             dummy: DummyCycles::_0,
             ..Default::default()
-        };
-        self.enable_write();
-        self.xspi.blocking_command(&transaction).unwrap();
-        self.wait_write_finish();
+        }
     }
 
-    pub fn erase_sector(&mut self, addr: u32) {
-        self.perform_erase(addr, SpiCommand::SectorErase4B as u8);
+    pub fn erase_sector(&mut self, addr: u32) -> Result<(), FlashError> {
+        self.perform_erase(
+            addr,
+            self.chip.commands.sector_erase,
+            WriteOperation::SectorErase,
+        )
     }
 
-    pub fn erase_block_64k(&mut self, addr: u32) {
-        self.perform_erase(addr, SpiCommand::BlockErase4B as u8);
+    pub fn erase_block_64k(&mut self, addr: u32) -> Result<(), FlashError> {
+        self.perform_erase(
+            addr,
+            self.chip.commands.block_erase,
+            WriteOperation::BlockErase,
+        )
     }
 
-    pub fn erase_chip(&mut self) {
-        self.enable_write();
-        self.exec_command(SpiCommand::ChipErase as u8);
-        self.wait_write_finish();
+    pub fn erase_chip(&mut self) -> Result<(), FlashError> {
+        self.enable_write()?;
+        self.exec_command(self.chip.commands.chip_erase)?;
+        self.finish_write(WriteOperation::ChipErase)
     }
 
-    fn write_page(&mut self, addr: u32, buffer: &[u8], len: usize) {
-        assert!(
-            (len as u32 + (addr & 0x000000ff)) <= MEMORY_PAGE_SIZE as u32,
-            "write_page(): page write length exceeds page boundary (len = {}, addr = {:X}",
-            len,
-            addr
-        );
+    fn write_page(&mut self, addr: u32, buffer: &[u8], len: usize) -> Result<(), FlashError> {
+        if len + addr as usize % self.geometry.page_size > self.geometry.page_size {
+            return Err(FlashError::OutOfBounds);
+        }
 
-        let transaction = TransferConfig {
+        let transaction = self.program_transaction(addr);
+        self.enable_write()?;
+        self.write(buffer, transaction)?;
+        self.finish_write(WriteOperation::Program)
+    }
+
+    /// Page program of the data at `addr`.
+    fn program_transaction(&self, addr: u32) -> TransferConfig {
+        self.write_data_transaction(self.chip.commands.page_program, addr)
+    }
+
+    /// Command `cmd` followed by `addr` and the data to write.
+    fn write_data_transaction(&self, cmd: u8, addr: u32) -> TransferConfig {
+        TransferConfig {
             iwidth: XspiWidth::SING,
             adsize: AddressSize::_32bit,
             adwidth: XspiWidth::SING,
             dwidth: XspiWidth::SING,
-            instruction: Some(SpiCommand::PageProgram4B as u32),
+            instruction: Some(cmd as u32),
             address: Some(addr),
             dummy: DummyCycles::_0,
             ..Default::default()
-        };
-        self.enable_write();
-        self.xspi.blocking_write(buffer, transaction).unwrap();
-        self.wait_write_finish();
+        }
     }
 
-    pub fn write_memory(&mut self, addr: u32, buffer: &[u8]) {
+    pub fn write_memory(&mut self, addr: u32, buffer: &[u8]) -> Result<(), FlashError> {
+        self.geometry.check_bounds(addr, buffer.len())?;
+        let page_size = self.geometry.page_size;
         let mut left = buffer.len();
         let mut place = addr;
         let mut chunk_start = 0;
 
         while left > 0 {
-            let max_chunk_size = MEMORY_PAGE_SIZE - (place & 0x000000ff) as usize;
+            let max_chunk_size = page_size - place as usize % page_size;
             let chunk_size = min(max_chunk_size, left);
             let chunk = &buffer[chunk_start..(chunk_start + chunk_size)];
-            self.write_page(place, chunk, chunk_size);
+            self.write_page(place, chunk, chunk_size)?;
             place += chunk_size as u32;
             left -= chunk_size;
             chunk_start += chunk_size;
         }
+        Ok(())
     }
 
     // Note: read_register cannot be used to read the configuration register 2 since there is an
     // address required for that read.
-    fn read_register(&mut self, cmd: u8) -> u8 {
+    fn read_register(&mut self, cmd: u8) -> Result<u8, FlashError> {
         let mut buffer = [0; 1];
         let transaction: TransferConfig = TransferConfig {
             iwidth: XspiWidth::SING,
@@ -548,348 +684,711 @@ impl SpiFlashMemory {
             dummy: DummyCycles::_0,
             ..Default::default()
         };
-        self.xspi.blocking_read(&mut buffer, transaction).unwrap();
-        buffer[0]
+        self.read(&mut buffer, transaction)?;
+        Ok(buffer[0])
     }
 
-    pub fn read_sr(&mut self) -> u8 {
-        self.read_register(SpiCommand::ReadStatusRegister as u8)
+    pub fn read_sr(&mut self) -> Result<u8, FlashError> {
+        self.read_register(self.chip.commands.read_status)
     }
 
-    pub fn read_cr(&mut self) -> u8 {
-        self.read_register(SpiCommand::ReadConfigurationRegister as u8)
+    /// Fails with [`FlashError::Unsupported`] on chips without a configuration register.
+    pub fn read_cr(&mut self) -> Result<u8, FlashError> {
+        let read_cr = self.chip.registers.read_cr.ok_or(FlashError::Unsupported)?;
+        self.read_register(read_cr)
     }
 
-    pub fn write_sr_cr(&mut self, sr: u8, cr: u8) {
+    /// Fails with [`FlashError::Unsupported`] on chips that report failures elsewhere.
+    pub fn read_scur(&mut self) -> Result<u8, FlashError> {
+        let FailStatus::SecurityRegister { read, .. } = self.chip.registers.fail_status else {
+            return Err(FlashError::Unsupported);
+        };
+        self.read_register(read)
+    }
+
+    /// On chips without a configuration register only `sr` is written.
+    pub fn write_sr_cr(&mut self, sr: u8, cr: u8) -> Result<(), FlashError> {
         let buffer = [sr, cr];
+        let len = if self.chip.registers.read_cr.is_some() {
+            2
+        } else {
+            1
+        };
         let transaction: TransferConfig = TransferConfig {
             iwidth: XspiWidth::SING,
             isize: AddressSize::_8bit,
-            instruction: Some(SpiCommand::WriteStatusConfigurationRegister as u32),
+            instruction: Some(self.chip.commands.write_status as u32),
             adwidth: XspiWidth::NONE,
             dwidth: XspiWidth::SING,
             address: None,
             dummy: DummyCycles::_0,
             ..Default::default()
         };
-        self.enable_write();
-        self.xspi.blocking_write(&buffer, transaction).unwrap();
-        self.wait_write_finish();
+        self.enable_write()?;
+        self.write(&buffer[..len], transaction)?;
+        self.wait_write_finish(WriteOperation::Register)
+    }
+
+    fn cr2_address_size(&self) -> AddressSize {
+        if self.chip.registers.cr2_address_4b {
+            AddressSize::_32bit
+        } else {
+            AddressSize::_24bit
+        }
     }
 
-    pub fn read_cr2(&mut self, address: u32) -> u8 {
+    pub fn read_cr2(&mut self, address: u32) -> Result<u8, FlashError> {
         let mut buffer = [0; 1];
         let transaction: TransferConfig = TransferConfig {
             iwidth: XspiWidth::SING,
             isize: AddressSize::_8bit,
-            instruction: Some(SpiCommand::ReadConfigurationRegister2 as u32),
-            adsize: AddressSize::_32bit,
+            instruction: Some(self.chip.registers.read_cr2 as u32),
+            adsize: self.cr2_address_size(),
             adwidth: XspiWidth::SING,
             dwidth: XspiWidth::SING,
             address: Some(address),
-            dummy: DummyCycles::_0,
+            dummy: dummy_cycles(self.chip.registers.cr2_read_dummy_cycles),
             ..Default::default()
         };
-        self.xspi.blocking_read(&mut buffer, transaction).unwrap();
-        buffer[0]
+        self.read(&mut buffer, transaction)?;
+        Ok(buffer[0])
     }
 
-    pub fn write_cr2(&mut self, address: u32, value: u8) {
+    /// Write Configuration Register 2, setting the Write Enable Latch first like the OPI driver
+    /// always did, so [`FlashMemory::write_cr2`] behaves the same for both drivers.
+    pub fn write_cr2(&mut self, address: u32, value: u8) -> Result<(), FlashError> {
+        self.send_write_cr2(address, value)?;
+        self.wait_write_finish(WriteOperation::Register)
+    }
+
+    /// Write Configuration Register 2 without waiting for the write to finish.
+    fn send_write_cr2(&mut self, address: u32, value: u8) -> Result<(), FlashError> {
         let buffer = [value; 1];
         let transaction: TransferConfig = TransferConfig {
             iwidth: XspiWidth::SING,
             isize: AddressSize::_8bit,
-            instruction: Some(SpiCommand::WriteConfigurationRegister2 as u32),
-            adsize: AddressSize::_32bit,
+            instruction: Some(self.chip.registers.write_cr2 as u32),
+            adsize: self.cr2_address_size(),
             adwidth: XspiWidth::SING,
             dwidth: XspiWidth::SING,
             address: Some(address),
             dummy: DummyCycles::_0,
             ..Default::default()
         };
-        self.xspi.blocking_write(&buffer, transaction).unwrap();
-        self.wait_write_finish();
+        self.enable_write()?;
+        self.write(&buffer, transaction)
     }
 }
 
-impl OpiFlashMemory {
-    pub fn into_spi(mut self) -> SpiFlashMemory {
-        self.disable_opi_mode();
-        SpiFlashMemory { xspi: self.xspi }
+impl<X: Transport> OpiFlashMemory<X> {
+    /// Switch the chip back to SPI mode.
+    ///
+    /// On failure the octal driver is returned with the error. The chip may have switched
+    /// anyway, [`SpiFlashMemory::recover`] brings it back to SPI mode.
+    #[allow(clippy::result_large_err)]
+    pub fn into_spi(mut self) -> Result<SpiFlashMemory<X>, HandleError<Self>> {
+        if let Err(error) = self.disable_opi_mode() {
+            return Err(HandleError::new(error, self));
+        }
+        let dtr = self.dtr;
+        let mut flash = self.spi_driver();
+        flash.set_prescaler(flash.timing.spi_prescaler);
+        match flash.check_bus_mode(BusMode::Spi) {
+            Ok(()) => Ok(flash),
+            Err(error) => {
+                flash.set_prescaler(flash.timing.opi_prescaler);
+                Err(HandleError::new(error, flash.octal_driver(dtr)))
+            }
+        }
+    }
+
+    /// The SPI driver for the same chip, without switching the chip's mode.
+    fn spi_driver(self) -> SpiFlashMemory<X> {
+        SpiFlashMemory {
+            transport: self.transport,
+            memory_mapped: false,
+            chip: self.chip,
+            geometry: self.geometry,
+            timing: self.timing,
+            timeouts: self.timeouts,
+        }
+    }
+
+    /// The chip the driver is talking to.
+    pub fn chip(&self) -> &Chip {
+        &self.chip
+    }
+
+    /// Whether the chip runs in octal DTR (8D-8D-8D) instead of octal STR (8S-8S-8S) mode.
+    pub fn is_dtr(&self) -> bool {
+        self.dtr
     }
 
     /// Disable OPI mode and return to SPI
-    pub fn disable_opi_mode(&mut self) {
-        // Clear SOPI and DOPI bits in CR2 volatile register
-        let cr2_0 = self.read_cr2(0x00000000);
-        self.write_cr2(0x00000000, cr2_0 & 0xFC); // Clear bits 0 and 1
+    pub fn disable_opi_mode(&mut self) -> Result<(), FlashError> {
+        // The chip leaves OPI as soon as the write completes, so the status register can't be
+        // polled in OPI mode afterwards.
+        let value = self.bus_mode_value(BusMode::Spi)?;
+        self.send_write_cr2(self.chip.opi_enable.address, value)
     }
 
-    /// Enable memory-mapped mode for OPI
-    pub fn enable_mm(&mut self) {
+    /// Instruction and instruction size of `opcode` in the current octal mode.
+    fn instruction(&self, opcode: u8) -> (Option<u32>, AddressSize) {
+        let extension = if self.dtr {
+            self.chip.dtr_command_extension
+        } else {
+            self.chip.command_extension
+        };
+        let (instruction, isize) = extension.encode(opcode);
+        (Some(instruction), isize)
+    }
+
+    /// Opcode of the array read in the current octal mode.
+    fn read_opcode(&self) -> u8 {
+        if self.dtr {
+            self.chip.commands.octal_dtr_read
+        } else {
+            self.chip.commands.octal_read
+        }
+    }
+
+    /// Enable or disable sampling read data with the chip's DQS strobe.
+    fn set_dqs(&mut self, enabled: bool) {
+        self.transport.set_dqs(enabled);
+    }
+
+    /// Enter memory mapped mode, see [`MappedFlash`] for the public interface.
+    fn map(&mut self) -> Result<(), FlashError> {
+        self.check_indirect()?;
+
+        let (instruction, isize) = self.instruction(self.read_opcode());
         let read_config = TransferConfig {
             iwidth: XspiWidth::OCTO,
-            isize: AddressSize::_16bit, // 2-byte command for OPI
+            isize,
+            idtr: self.dtr,
             adwidth: XspiWidth::OCTO,
             adsize: AddressSize::_32bit,
+            addtr: self.dtr,
             dwidth: XspiWidth::OCTO,
-            instruction: Some(OpiCommand::OctaRead as u32),
-            dummy: DummyCycles::_20, // Default dummy cycles for OPI
+            ddtr: self.dtr,
+            instruction,
+            dummy: dummy_cycles(self.geometry.opi_read_dummy_cycles),
             ..Default::default()
         };
 
+        let (instruction, isize) = self.instruction(self.chip.commands.page_program);
         let write_config = TransferConfig {
             iwidth: XspiWidth::OCTO,
-            isize: AddressSize::_16bit,
+            isize,
+            idtr: self.dtr,
             adwidth: XspiWidth::OCTO,
             adsize: AddressSize::_32bit,
+            addtr: self.dtr,
             dwidth: XspiWidth::OCTO,
-            instruction: Some(OpiCommand::PageProgram4B as u32),
+            ddtr: self.dtr,
+            instruction,
             dummy: DummyCycles::_0,
             ..Default::default()
         };
 
-        self.xspi
-            .enable_memory_mapped_mode(read_config, write_config)
-            .unwrap();
+        self.transport
+            .enable_memory_mapped(read_config, write_config)?;
+        // Enabling memory mapped mode clears DQSE, and the write configuration must not use it.
+        self.set_dqs(self.dtr);
+        self.memory_mapped = true;
+        Ok(())
+    }
+
+    /// Leave memory mapped mode and return to indirect access.
+    fn unmap(&mut self) {
+        self.transport.disable_memory_mapped();
+        self.memory_mapped = false;
     }
 
-    pub fn disable_mm(&mut self) {
-        self.xspi.disable_memory_mapped_mode();
+    fn check_indirect(&self) -> Result<(), FlashError> {
+        if self.memory_mapped {
+            Err(FlashError::WrongMode)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn command(&mut self, transaction: &TransferConfig) -> Result<(), FlashError> {
+        self.check_indirect()?;
+        self.set_dqs(false);
+        self.transport.command(transaction)?;
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut [u8], transaction: TransferConfig) -> Result<(), FlashError> {
+        self.check_indirect()?;
+        self.set_dqs(self.dtr);
+        self.transport.read(buffer, transaction)?;
+        Ok(())
+    }
+
+    fn write(&mut self, buffer: &[u8], transaction: TransferConfig) -> Result<(), FlashError> {
+        self.check_indirect()?;
+        self.set_dqs(false);
+        self.transport.write(buffer, transaction)?;
+        Ok(())
     }
 
-    /// Execute OPI command (2-byte command)
-    fn exec_command(&mut self, cmd: OpiCommand) {
+    /// Execute OPI command
+    fn exec_command(&mut self, cmd: u8) -> Result<(), FlashError> {
+        let (instruction, isize) = self.instruction(cmd);
         let transaction = TransferConfig {
             iwidth: XspiWidth::OCTO,
-            isize: AddressSize::_16bit, // 2-byte command
+            isize,
+            idtr: self.dtr,
             adwidth: XspiWidth::NONE,
             dwidth: XspiWidth::NONE,
-            instruction: Some(cmd as u32),
+            instruction,
             address: None,
             dummy: DummyCycles::_0,
             ..Default::default()
         };
-        self.xspi.blocking_command(&transaction).unwrap();
+        self.command(&transaction)
     }
 
     /// Reset memory using OPI commands
-    pub fn reset_memory(&mut self) {
-        self.exec_command(OpiCommand::ResetEnable);
-        self.exec_command(OpiCommand::ResetMemory);
-        self.wait_write_finish();
+    pub fn reset_memory(&mut self) -> Result<(), FlashError> {
+        self.exec_command(self.chip.commands.reset_enable)?;
+        self.exec_command(self.chip.commands.reset_memory)?;
+        self.wait_write_finish(WriteOperation::Register)
     }
 
     /// Enable write using OPI command
-    pub fn enable_write(&mut self) {
-        self.exec_command(OpiCommand::WriteEnable);
+    pub fn enable_write(&mut self) -> Result<(), FlashError> {
+        self.exec_command(self.chip.commands.write_enable)
     }
 
     /// Read device ID in OPI mode
-    pub fn read_id(&mut self) -> [u8; 3] {
-        let mut buffer = [0; 3];
-        let transaction = TransferConfig {
+    pub fn read_id(&mut self) -> Result<[u8; 3], FlashError> {
+        let mut buffer = [0; 6];
+        if self.dtr && self.chip.registers.dtr_repeated_bytes {
+            self.read_register_into(self.chip.commands.read_id, 0x00000000, &mut buffer)?;
+            Ok([buffer[0], buffer[2], buffer[4]])
+        } else {
+            self.read_register_into(self.chip.commands.read_id, 0x00000000, &mut buffer[..3])?;
+            Ok([buffer[0], buffer[1], buffer[2]])
+        }
+    }
+
+    /// Read memory using OPI mode
+    ///
+    /// In DTR mode the chip only reads from even addresses, an odd first or last byte is read
+    /// as part of a 2-byte read.
+    pub fn read_memory(&mut self, addr: u32, buffer: &mut [u8]) -> Result<(), FlashError> {
+        self.geometry.check_bounds(addr, buffer.len())?;
+        if !self.dtr {
+            return self.read_array(addr, buffer);
+        }
+
+        let mut addr = addr;
+        let mut buffer = buffer;
+        if addr % 2 == 1 && !buffer.is_empty() {
+            let mut pair = [0; 2];
+            self.read_array(addr - 1, &mut pair)?;
+            buffer[0] = pair[1];
+            addr += 1;
+            buffer = &mut buffer[1..];
+        }
+        if buffer.len() % 2 == 1 {
+            let last = buffer.len() - 1;
+            let mut pair = [0; 2];
+            self.read_array(addr + last as u32, &mut pair)?;
+            buffer[last] = pair[0];
+            buffer = &mut buffer[..last];
+        }
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        self.read_array(addr, buffer)
+    }
+
+    fn read_array(&mut self, addr: u32, buffer: &mut [u8]) -> Result<(), FlashError> {
+        let transaction = self.read_transaction(addr);
+        self.read(buffer, transaction)
+    }
+
+    /// Array read starting at `addr`.
+    fn read_transaction(&self, addr: u32) -> TransferConfig {
+        let (instruction, isize) = self.instruction(self.read_opcode());
+        TransferConfig {
             iwidth: XspiWidth::OCTO,
-            isize: AddressSize::_16bit,
+            isize,
+            idtr: self.dtr,
             adwidth: XspiWidth::OCTO,
             adsize: AddressSize::_32bit,
+            addtr: self.dtr,
             dwidth: XspiWidth::OCTO,
-            instruction: Some(OpiCommand::ReadIdentification as u32),
-            address: Some(0x00000000), // Dummy address required
-            dummy: DummyCycles::_4,
+            ddtr: self.dtr,
+            instruction,
+            address: Some(addr),
+            dummy: dummy_cycles(self.geometry.opi_read_dummy_cycles),
             ..Default::default()
-        };
-        self.xspi.blocking_read(&mut buffer, transaction).unwrap();
-        buffer
+        }
     }
 
-    /// Read memory using OPI mode
-    pub fn read_memory(&mut self, addr: u32, buffer: &mut [u8]) {
+    /// Read SFDP tables using OPI mode
+    pub fn read_sfdp(&mut self, addr: u32, buffer: &mut [u8]) -> Result<(), FlashError> {
+        let (instruction, isize) = self.instruction(self.chip.commands.read_sfdp);
         let transaction = TransferConfig {
             iwidth: XspiWidth::OCTO,
-            isize: AddressSize::_16bit,
+            isize,
+            idtr: self.dtr,
             adwidth: XspiWidth::OCTO,
             adsize: AddressSize::_32bit,
+            addtr: self.dtr,
             dwidth: XspiWidth::OCTO,
-            instruction: Some(OpiCommand::OctaRead as u32),
+            ddtr: self.dtr,
+            instruction,
             address: Some(addr),
-            dummy: DummyCycles::_20, // Default for 200MHz operation
+            dummy: dummy_cycles(self.geometry.opi_read_dummy_cycles),
             ..Default::default()
         };
-        self.xspi.blocking_read(buffer, transaction).unwrap();
+        self.read(buffer, transaction)
+    }
+
+    /// Sizes and timings the driver currently uses.
+    pub fn geometry(&self) -> FlashGeometry {
+        self.geometry
+    }
+
+    /// Wait for the Write In Progress bit to clear, at most the max duration of `operation`.
+    fn wait_write_finish(&mut self, operation: WriteOperation) -> Result<(), FlashError> {
+        // The status read also sets up the transfer repeated by the automatic polling.
+        if !self.read_status()?.write_in_progress() {
+            return Ok(());
+        }
+        let addressed = self.chip.registers.octal_read_address;
+        self.transport.poll_until_clear(
+            StatusRegister::WIP,
+            addressed,
+            self.timeouts.get(operation),
+        )
     }
 
-    /// Wait for write completion using OPI status read
-    fn wait_write_finish(&mut self) {
-        while (self.read_sr() & 0x01) != 0 {}
+    /// Wait for a program or erase operation to finish and check whether it succeeded.
+    fn finish_write(&mut self, operation: WriteOperation) -> Result<(), FlashError> {
+        self.wait_write_finish(operation)?;
+        self.check_write_result()
+    }
+
+    /// Read the register reporting failed (and suspended) program and erase operations.
+    fn read_fail_status(&mut self) -> Result<u8, FlashError> {
+        self.read_register(self.chip.registers.fail_status.read_opcode(), 0x00000000)
+    }
+
+    /// Check whether the last program or erase operation succeeded.
+    fn check_write_result(&mut self) -> Result<(), FlashError> {
+        let status = self.chip.registers.fail_status;
+        let value = self.read_fail_status()?;
+        match status.error(value) {
+            None => Ok(()),
+            Some(e) => {
+                if let Some(clear) = status.clear_opcode() {
+                    self.exec_command(clear)?;
+                }
+                Err(e)
+            }
+        }
     }
 
     /// Perform erase operation using OPI command
-    fn perform_erase(&mut self, addr: u32, cmd: OpiCommand) {
-        let transaction = TransferConfig {
+    fn perform_erase(
+        &mut self,
+        addr: u32,
+        cmd: u8,
+        operation: WriteOperation,
+    ) -> Result<(), FlashError> {
+        self.start_erase(addr, cmd)?;
+        self.finish_write(operation)
+    }
+
+    /// Issue erase command `cmd` for `addr` without waiting for the erase to finish.
+    fn start_erase(&mut self, addr: u32, cmd: u8) -> Result<(), FlashError> {
+        self.geometry.check_bounds(addr, 1)?;
+        let transaction = self.erase_transaction(addr, cmd);
+        self.enable_write()?;
+        self.command(&transaction)
+    }
+
+    /// Erase command `cmd` of the sector or block at `addr`.
+    fn erase_transaction(&self, addr: u32, cmd: u8) -> TransferConfig {
+        let (instruction, isize) = self.instruction(cmd);
+        TransferConfig {
             iwidth: XspiWidth::OCTO,
-            isize: AddressSize::_16bit,
+            isize,
+            idtr: self.dtr,
             adwidth: XspiWidth::OCTO,
             adsize: AddressSize::_32bit,
+            addtr: self.dtr,
             dwidth: XspiWidth::NONE,
-            instruction: Some(cmd as u32),
+            instruction,
             address: Some(addr),
             dummy: DummyCycles::_0,
             ..Default::default()
-        };
-        self.enable_write();
-        self.xspi.blocking_command(&transaction).unwrap();
-        self.wait_write_finish();
+        }
     }
 
     /// Erase 4KB sector using OPI
-    pub fn erase_sector(&mut self, addr: u32) {
-        self.perform_erase(addr, OpiCommand::SectorErase4B);
+    pub fn erase_sector(&mut self, addr: u32) -> Result<(), FlashError> {
+        self.perform_erase(
+            addr,
+            self.chip.commands.sector_erase,
+            WriteOperation::SectorErase,
+        )
     }
 
     /// Erase 64KB block using OPI
-    pub fn erase_block_64k(&mut self, addr: u32) {
-        self.perform_erase(addr, OpiCommand::BlockErase4B);
+    pub fn erase_block_64k(&mut self, addr: u32) -> Result<(), FlashError> {
+        self.perform_erase(
+            addr,
+            self.chip.commands.block_erase,
+            WriteOperation::BlockErase,
+        )
     }
 
     /// Erase entire chip using OPI
-    pub fn erase_chip(&mut self) {
-        self.enable_write();
-        self.exec_command(OpiCommand::ChipErase);
-        self.wait_write_finish();
+    pub fn erase_chip(&mut self) -> Result<(), FlashError> {
+        self.enable_write()?;
+        self.exec_command(self.chip.commands.chip_erase)?;
+        self.finish_write(WriteOperation::ChipErase)
     }
 
     /// Write single page using OPI
-    fn write_page(&mut self, addr: u32, buffer: &[u8], len: usize) {
-        assert!(
-            (len as u32 + (addr & 0x000000ff)) <= MEMORY_PAGE_SIZE as u32,
-            "write_page(): page write length exceeds page boundary (len = {}, addr = {:X})",
-            len,
-            addr
-        );
+    fn write_page(&mut self, addr: u32, buffer: &[u8], len: usize) -> Result<(), FlashError> {
+        if len + addr as usize % self.geometry.page_size > self.geometry.page_size {
+            return Err(FlashError::OutOfBounds);
+        }
+        if self.dtr && (addr % 2 == 1 || len % 2 == 1) {
+            return Err(FlashError::NotAligned);
+        }
 
-        let transaction = TransferConfig {
+        let transaction = self.program_transaction(addr);
+        self.enable_write()?;
+        self.write(buffer, transaction)?;
+        self.finish_write(WriteOperation::Program)
+    }
+
+    /// Page program of the data at `addr`.
+    fn program_transaction(&self, addr: u32) -> TransferConfig {
+        self.write_data_transaction(self.chip.commands.page_program, addr)
+    }
+
+    /// Command `cmd` followed by `addr` and the data to write.
+    fn write_data_transaction(&self, cmd: u8, addr: u32) -> TransferConfig {
+        let (instruction, isize) = self.instruction(cmd);
+        TransferConfig {
             iwidth: XspiWidth::OCTO,
-            isize: AddressSize::_16bit,
+            isize,
+            idtr: self.dtr,
             adwidth: XspiWidth::OCTO,
             adsize: AddressSize::_32bit,
+            addtr: self.dtr,
             dwidth: XspiWidth::OCTO,
-            instruction: Some(OpiCommand::PageProgram4B as u32),
+            ddtr: self.dtr,
+            instruction,
             address: Some(addr),
             dummy: DummyCycles::_0,
             ..Default::default()
-        };
-        self.enable_write();
-        self.xspi.blocking_write(buffer, transaction).unwrap();
-        self.wait_write_finish();
+        }
     }
 
     /// Write memory using OPI (handles page boundaries)
-    pub fn write_memory(&mut self, addr: u32, buffer: &[u8]) {
+    ///
+    /// In DTR mode the chip only programs whole 2-byte words, an odd first or last byte is
+    /// padded with 0xFF which leaves its neighbour unchanged.
+    pub fn write_memory(&mut self, addr: u32, buffer: &[u8]) -> Result<(), FlashError> {
+        self.geometry.check_bounds(addr, buffer.len())?;
+        let mut addr = addr;
+        let mut buffer = buffer;
+        if self.dtr {
+            if addr % 2 == 1 && !buffer.is_empty() {
+                self.write_page(addr - 1, &[0xFF, buffer[0]], 2)?;
+                addr += 1;
+                buffer = &buffer[1..];
+            }
+            if buffer.len() % 2 == 1 {
+                let last = buffer.len() - 1;
+                self.write_page(addr + last as u32, &[buffer[last], 0xFF], 2)?;
+                buffer = &buffer[..last];
+            }
+        }
+
+        let page_size = self.geometry.page_size;
         let mut left = buffer.len();
         let mut place = addr;
         let mut chunk_start = 0;
 
         while left > 0 {
-            let max_chunk_size = MEMORY_PAGE_SIZE - (place & 0x000000ff) as usize;
+            let max_chunk_size = page_size - place as usize % page_size;
             let chunk_size = min(max_chunk_size, left);
             let chunk = &buffer[chunk_start..(chunk_start + chunk_size)];
-            self.write_page(place, chunk, chunk_size);
+            self.write_page(place, chunk, chunk_size)?;
             place += chunk_size as u32;
             left -= chunk_size;
             chunk_start += chunk_size;
         }
+        Ok(())
     }
 
-    /// Read register using OPI mode
-    fn read_register(&mut self, cmd: OpiCommand, dummy_addr: u32, dummy_cycles: DummyCycles) -> u8 {
-        let mut buffer = [0; 1];
+    /// Read register using OPI mode. `address` is only sent to chips that expect an address
+    /// with register reads.
+    fn read_register_into(
+        &mut self,
+        cmd: u8,
+        address: u32,
+        buffer: &mut [u8],
+    ) -> Result<(), FlashError> {
+        let (instruction, isize) = self.instruction(cmd);
+        let (adwidth, address) = if self.chip.registers.octal_read_address {
+            (XspiWidth::OCTO, Some(address))
+        } else {
+            (XspiWidth::NONE, None)
+        };
         let transaction = TransferConfig {
             iwidth: XspiWidth::OCTO,
-            isize: AddressSize::_16bit,
-            adwidth: XspiWidth::OCTO,
+            isize,
+            idtr: self.dtr,
+            adwidth,
             adsize: AddressSize::_32bit,
+            addtr: self.dtr,
             dwidth: XspiWidth::OCTO,
-            instruction: Some(cmd as u32),
-            address: Some(dummy_addr),
-            dummy: dummy_cycles,
+            ddtr: self.dtr,
+            instruction,
+            address,
+            dummy: dummy_cycles(self.geometry.opi_status_dummy_cycles),
             ..Default::default()
         };
-        self.xspi.blocking_read(&mut buffer, transaction).unwrap();
-        buffer[0]
+        self.read(buffer, transaction)
+    }
+
+    /// Read a single byte register. In DTR mode a whole 2-byte word is read, the register is
+    /// in the first byte.
+    fn read_register(&mut self, cmd: u8, address: u32) -> Result<u8, FlashError> {
+        let mut buffer = [0; 2];
+        let len = if self.dtr { 2 } else { 1 };
+        self.read_register_into(cmd, address, &mut buffer[..len])?;
+        Ok(buffer[0])
     }
 
     /// Read Status Register using OPI
-    pub fn read_sr(&mut self) -> u8 {
-        self.read_register(
-            OpiCommand::ReadStatusRegister,
-            0x00000000, // Dummy address
-            DummyCycles::_4,
-        )
+    pub fn read_sr(&mut self) -> Result<u8, FlashError> {
+        self.read_register(self.chip.commands.read_status, 0x00000000)
     }
 
     /// Read Configuration Register using OPI
-    pub fn read_cr(&mut self) -> u8 {
-        self.read_register(
-            OpiCommand::ReadConfigurationRegister,
-            0x00000001, // Address for CR
-            DummyCycles::_4,
-        )
+    ///
+    /// Fails with [`FlashError::Unsupported`] on chips without a configuration register.
+    pub fn read_cr(&mut self) -> Result<u8, FlashError> {
+        let read_cr = self.chip.registers.read_cr.ok_or(FlashError::Unsupported)?;
+        self.read_register(read_cr, 0x00000001) // Address for CR
+    }
+
+    /// Read Security Register using OPI
+    ///
+    /// Fails with [`FlashError::Unsupported`] on chips that report failures elsewhere.
+    pub fn read_scur(&mut self) -> Result<u8, FlashError> {
+        let FailStatus::SecurityRegister { read, .. } = self.chip.registers.fail_status else {
+            return Err(FlashError::Unsupported);
+        };
+        self.read_register(read, 0x00000000)
     }
 
     /// Write Status/Configuration Register using OPI
-    pub fn write_sr_cr(&mut self, sr: u8, cr: u8) {
+    ///
+    /// Macronix chips take the two registers at separate addresses, other chips only have the
+    /// status register and `cr` is ignored.
+    pub fn write_sr_cr(&mut self, sr: u8, cr: u8) -> Result<(), FlashError> {
+        let (instruction, isize) = self.instruction(self.chip.commands.write_status);
+        let (adwidth, address) = if self.chip.registers.octal_read_address {
+            (XspiWidth::OCTO, Some(0x00000000))
+        } else {
+            (XspiWidth::NONE, None)
+        };
         let transaction = TransferConfig {
             iwidth: XspiWidth::OCTO,
-            isize: AddressSize::_16bit,
-            adwidth: XspiWidth::OCTO,
+            isize,
+            idtr: self.dtr,
+            adwidth,
             adsize: AddressSize::_32bit,
+            addtr: self.dtr,
             dwidth: XspiWidth::OCTO,
-            instruction: Some(OpiCommand::WriteStatusConfigurationRegister as u32),
-            address: Some(0x00000000),
+            ddtr: self.dtr,
+            instruction,
+            address,
             dummy: DummyCycles::_0,
             ..Default::default()
         };
 
-        self.enable_write();
-        self.xspi.blocking_write(&[sr, cr], transaction).unwrap();
-        self.wait_write_finish();
+        // DTR transfers are always a whole 2-byte word.
+        let buffer = match self.chip.registers.read_cr {
+            Some(_) => [sr, cr],
+            None => [sr, sr],
+        };
+        let len = if self.dtr || self.chip.registers.read_cr.is_some() {
+            2
+        } else {
+            1
+        };
+        self.enable_write()?;
+        self.write(&buffer[..len], transaction)?;
+        self.wait_write_finish(WriteOperation::Register)
     }
 
     /// Read Configuration Register 2 using OPI
-    pub fn read_cr2(&mut self, address: u32) -> u8 {
-        let mut buffer = [0; 1];
+    pub fn read_cr2(&mut self, address: u32) -> Result<u8, FlashError> {
+        let mut buffer = [0; 2];
+        let len = if self.dtr { 2 } else { 1 };
+        let (instruction, isize) = self.instruction(self.chip.registers.read_cr2);
         let transaction = TransferConfig {
             iwidth: XspiWidth::OCTO,
-            isize: AddressSize::_16bit,
+            isize,
+            idtr: self.dtr,
             adwidth: XspiWidth::OCTO,
             adsize: AddressSize::_32bit,
+            addtr: self.dtr,
             dwidth: XspiWidth::OCTO,
-            instruction: Some(OpiCommand::ReadConfigurationRegister2 as u32),
+            ddtr: self.dtr,
+            instruction,
             address: Some(address),
-            dummy: DummyCycles::_4,
+            dummy: dummy_cycles(self.geometry.opi_status_dummy_cycles),
             ..Default::default()
         };
-        self.xspi.blocking_read(&mut buffer, transaction).unwrap();
-        buffer[0]
+        self.read(&mut buffer[..len], transaction)?;
+        Ok(buffer[0])
+    }
+
+    /// Write Configuration Register 2 using OPI, setting the Write Enable Latch first.
+    pub fn write_cr2(&mut self, address: u32, value: u8) -> Result<(), FlashError> {
+        self.send_write_cr2(address, value)?;
+        self.wait_write_finish(WriteOperation::Register)
     }
 
-    /// Write Configuration Register 2 using OPI
-    pub fn write_cr2(&mut self, address: u32, value: u8) {
+    /// Write Configuration Register 2 without waiting for the write to finish.
+    fn send_write_cr2(&mut self, address: u32, value: u8) -> Result<(), FlashError> {
+        let (instruction, isize) = self.instruction(self.chip.registers.write_cr2);
         let transaction = TransferConfig {
             iwidth: XspiWidth::OCTO,
-            isize: AddressSize::_16bit,
+            isize,
+            idtr: self.dtr,
             adwidth: XspiWidth::OCTO,
             adsize: AddressSize::_32bit,
+            addtr: self.dtr,
             dwidth: XspiWidth::OCTO,
-            instruction: Some(OpiCommand::WriteConfigurationRegister2 as u32),
+            ddtr: self.dtr,
+            instruction,
             address: Some(address),
             dummy: DummyCycles::_0,
             ..Default::default()
         };
 
-        self.enable_write();
-        self.xspi.blocking_write(&[value], transaction).unwrap();
-        self.wait_write_finish();
+        // DTR transfers are always a whole 2-byte word.
+        let len = if self.dtr { 2 } else { 1 };
+        self.enable_write()?;
+        self.write(&[value, value][..len], transaction)
     }
 }
@@ -0,0 +1,97 @@
+//! Memory mapped access.
+//!
+//! `enable_mm` maps the flash at the transport's [`mapped_ptr`](Transport::mapped_ptr) (e.g.
+//! [`MEMORY_MAPPED_FLASH_ADDRESS`](crate::MEMORY_MAPPED_FLASH_ADDRESS) for XSPI2) and returns a
+//! [`MappedFlash`] guard that borrows the driver, so no indirect command can be issued while
+//! the XSPI is memory mapped. The guard hands out slices of the mapped region, which can't outlive it,
+//! and switches back to indirect mode when dropped.
+
+use core::slice;
+
+use crate::{FlashError, OpiFlashMemory, SpiFlashMemory, Transport};
+
+mod sealed {
+    pub trait Unmap {
+        fn unmap(&mut self);
+    }
+}
+
+/// The mapped flash, see the [module docs](self).
+pub struct MappedFlash<'a> {
+    flash: &'a mut dyn sealed::Unmap,
+    ptr: *const u8,
+    size: usize,
+}
+
+impl MappedFlash<'_> {
+    /// Pointer to the start of the mapped flash.
+    pub fn as_ptr(&self) -> *const u8 {
+        self.ptr
+    }
+
+    /// The whole flash.
+    pub fn as_slice(&self) -> &[u8] {
+        // SAFETY: the region is mapped for as long as the guard exists and is only written by
+        // the flash itself, which can't be accessed indirectly while the guard borrows it.
+        unsafe { slice::from_raw_parts(self.as_ptr(), self.size) }
+    }
+
+    /// `len` bytes of the flash starting at `addr`.
+    pub fn read(&self, addr: u32, len: usize) -> Result<&[u8], FlashError> {
+        let start = addr as usize;
+        match start.checked_add(len) {
+            Some(end) if end <= self.size => Ok(&self.as_slice()[start..end]),
+            _ => Err(FlashError::OutOfBounds),
+        }
+    }
+
+    /// Keep the flash mapped for good, e.g. to execute code from it.
+    ///
+    /// The driver stays in memory mapped mode and all its indirect operations fail with
+    /// [`FlashError::WrongMode`].
+    ///
+    /// # Safety
+    ///
+    /// The returned slice isn't tied to the driver, it must not be used once the driver is
+    /// dropped, which disables the XSPI peripheral, or unmaps the flash to change it, like
+    /// [`XipFlash`](crate::xip::XipFlash) does.
+    pub unsafe fn leak(self) -> &'static [u8] {
+        let (ptr, size) = (self.ptr, self.size);
+        core::mem::forget(self);
+        // SAFETY: the region stays mapped while the caller uses the slice, see `as_slice`.
+        unsafe { slice::from_raw_parts(ptr, size) }
+    }
+}
+
+impl Drop for MappedFlash<'_> {
+    fn drop(&mut self) {
+        self.flash.unmap();
+    }
+}
+
+macro_rules! impl_mapped {
+    ($t:ident) => {
+        impl<X: Transport> sealed::Unmap for $t<X> {
+            fn unmap(&mut self) {
+                <$t<X>>::unmap(self)
+            }
+        }
+
+        impl<X: Transport> $t<X> {
+            /// Map the flash until the returned guard is dropped.
+            pub fn enable_mm(&mut self) -> Result<MappedFlash<'_>, FlashError> {
+                self.map()?;
+                let ptr = self.transport.mapped_ptr();
+                let size = self.geometry.size;
+                Ok(MappedFlash {
+                    flash: self,
+                    ptr,
+                    size,
+                })
+            }
+        }
+    };
+}
+
+impl_mapped!(SpiFlashMemory);
+impl_mapped!(OpiFlashMemory);
@@ -0,0 +1,106 @@
+//! `embedded-storage` implementations for the flash drivers.
+
+use embedded_storage::nor_flash::{
+    ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+
+use crate::xip::XipFlash;
+use crate::{
+    FlashError, FlashGeometry, FlashMemory, OpiFlashMemory, SECTOR_SIZE, SpiFlashMemory, Transport,
+};
+
+impl NorFlashError for FlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            FlashError::NotAligned => NorFlashErrorKind::NotAligned,
+            FlashError::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            _ => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+/// Checks that `from..to` lies within the flash and is aligned to whole sectors.
+pub(crate) fn check_erase_range(
+    geometry: &FlashGeometry,
+    from: u32,
+    to: u32,
+) -> Result<(), FlashError> {
+    if from > to {
+        return Err(FlashError::OutOfBounds);
+    }
+    geometry.check_bounds(from, (to - from) as usize)?;
+    if !(from as usize).is_multiple_of(geometry.sector_size)
+        || !(to as usize).is_multiple_of(geometry.sector_size)
+    {
+        return Err(FlashError::NotAligned);
+    }
+    Ok(())
+}
+
+macro_rules! impl_nor_flash {
+    ($t:ident) => {
+        impl<X: Transport> ErrorType for $t<X> {
+            type Error = FlashError;
+        }
+
+        impl<X: Transport> ReadNorFlash for $t<X> {
+            const READ_SIZE: usize = 1;
+
+            fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+                FlashMemory::read_memory(self, offset, bytes)
+            }
+
+            fn capacity(&self) -> usize {
+                FlashMemory::geometry(self).size
+            }
+        }
+
+        impl<X: Transport> NorFlash for $t<X> {
+            const WRITE_SIZE: usize = 1;
+            const ERASE_SIZE: usize = SECTOR_SIZE;
+
+            fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+                check_erase_range(&FlashMemory::geometry(self), from, to)?;
+                <$t<X>>::erase_range(self, from, (to - from) as usize)
+            }
+
+            fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+                FlashMemory::write_memory(self, offset, bytes)
+            }
+        }
+    };
+}
+
+impl_nor_flash!(SpiFlashMemory);
+impl_nor_flash!(OpiFlashMemory);
+
+impl<X: Transport> ErrorType for XipFlash<X> {
+    type Error = FlashError;
+}
+
+impl<X: Transport> ReadNorFlash for XipFlash<X> {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        bytes.copy_from_slice(XipFlash::read(self, offset, bytes.len())?);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.geometry().size
+    }
+}
+
+impl<X: Transport> NorFlash for XipFlash<X> {
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = SECTOR_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_erase_range(&self.geometry(), from, to)?;
+        self.erase_range(from, (to - from) as usize)
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.write_memory(offset, bytes)
+    }
+}
@@ -0,0 +1,138 @@
+//! Board defaults of the Nucleo STM32H7S3L8 (MB1737), which has an MX25UW25645GXDI00 on XSPI2.
+
+use assign_resources::assign_resources;
+#[cfg(feature = "trace")]
+use embassy_stm32::mode::Blocking;
+use embassy_stm32::{
+    Config, Peri, Peripherals,
+    mode::Async,
+    peripherals, rcc,
+    time::Hertz,
+    xspi::{XDma, Xspi},
+};
+
+#[cfg(feature = "trace")]
+use crate::trace::Traced;
+use crate::xip::XipFlash;
+use crate::{FlashError, SpiFlashMemory, TimingProfile};
+
+assign_resources! {
+    flash_memory: FlashMemoryResources {
+        spi: XSPI2 = FlashMemorySpi,
+        clk: PN6 = FlashMemoryClk,
+        d0: PN2 = FlashMemoryD0,
+        d1: PN3 = FlashMemoryD1,
+        d2: PN4 = FlashMemoryD2,
+        d3: PN5 = FlashMemoryD3,
+        d4: PN8 = FlashMemoryD4,
+        d5: PN9 = FlashMemoryD5,
+        d6: PN10 = FlashMemoryD6,
+        d7: PN11 = FlashMemoryD7,
+        ncs: PN1 = FlashMemoryNcs,
+        dqs: PN0 = FlashMemoryDqs,
+
+    },
+    debug: LedResources {
+        led: PD10 = LedPin,
+    }
+}
+
+fn configure_rcc(rcc: &mut rcc::Config) {
+    use embassy_stm32::rcc::{
+        AHBPrescaler, APBPrescaler, Hse, HseMode, Pll, PllDiv, PllMul, PllPreDiv, PllSource,
+        Sysclk, VoltageScale,
+    };
+
+    rcc.hse = Some(Hse {
+        freq: Hertz(24_000_000),
+        mode: HseMode::Oscillator,
+    });
+    rcc.pll1 = Some(Pll {
+        source: PllSource::HSE,
+        prediv: PllPreDiv::DIV3,
+        mul: PllMul::MUL150,
+        divp: Some(PllDiv::DIV2),
+        divq: None,
+        divr: None,
+        divs: None,
+        divt: None,
+    });
+    rcc.sys = Sysclk::PLL1_P; // 600 Mhz
+    rcc.ahb_pre = AHBPrescaler::DIV2; // 300 Mhz
+    rcc.apb1_pre = APBPrescaler::DIV2; // 150 Mhz
+    rcc.apb2_pre = APBPrescaler::DIV2; // 150 Mhz
+    rcc.apb4_pre = APBPrescaler::DIV2; // 150 Mhz
+    rcc.apb5_pre = APBPrescaler::DIV2; // 150 Mhz
+    rcc.voltage_scale = VoltageScale::HIGH;
+}
+
+/// Initialize embassy with the board's clocks and [`TimingProfile::DEFAULT`], and split the
+/// peripherals into the board's resources.
+pub fn init() -> AssignedResources {
+    init_with_timing(&TimingProfile::DEFAULT)
+}
+
+/// Like [`init`], but clocks the XSPI peripheral as required by `timing`. The flash driver
+/// must be given the same profile with `set_timing`.
+pub fn init_with_timing(timing: &TimingProfile) -> AssignedResources {
+    let mut config = Config::default();
+    configure_rcc(&mut config.rcc);
+    timing.configure_rcc::<peripherals::XSPI2>(&mut config.rcc);
+
+    split(embassy_stm32::init(config))
+}
+
+/// Split peripherals initialized with a custom configuration into the board's resources.
+pub fn split(p: Peripherals) -> AssignedResources {
+    split_resources!(p)
+}
+
+/// Create the blocking driver for the board's flash.
+pub fn new_flash(r: FlashMemoryResources) -> Result<SpiFlashMemory, FlashError> {
+    SpiFlashMemory::new(
+        r.spi, r.clk, r.d0, r.d1, r.d2, r.d3, r.d4, r.d5, r.d6, r.d7, r.ncs, r.dqs,
+    )
+}
+
+/// Take over the board's flash from an application executing in place, see
+/// [`xip`](crate::xip).
+///
+/// The flash is set up again in octal STR mode, as left by the bootloader, without leaving the
+/// critical section.
+pub fn new_xip_flash(r: FlashMemoryResources) -> Result<XipFlash, FlashError> {
+    XipFlash::take_over(|| Ok(new_flash(r)?.into_octo()?))
+}
+
+/// Like [`new_flash`], but records the driver's transactions, see [`trace`](crate::trace).
+#[cfg(feature = "trace")]
+pub fn new_flash_traced(
+    r: FlashMemoryResources,
+) -> Result<SpiFlashMemory<Traced<Xspi<'static, peripherals::XSPI2, Blocking>>>, FlashError> {
+    let xspi = Xspi::new_blocking_xspi(
+        r.spi,
+        r.clk,
+        r.d0,
+        r.d1,
+        r.d2,
+        r.d3,
+        r.d4,
+        r.d5,
+        r.d6,
+        r.d7,
+        r.ncs,
+        crate::instance::xspi_config(),
+    );
+    crate::instance::configure_dqs(r.dqs);
+    SpiFlashMemory::with_transport(Traced::new(xspi))
+}
+
+/// Create the async driver for the board's flash, using `dma` for array reads and page
+/// programs.
+pub fn new_flash_async(
+    r: FlashMemoryResources,
+    dma: Peri<'static, impl XDma<peripherals::XSPI2>>,
+) -> Result<SpiFlashMemory<Xspi<'static, peripherals::XSPI2, Async>>, FlashError> {
+    SpiFlashMemory::new_async(
+        r.spi, r.clk, r.d0, r.d1, r.d2, r.d3, r.d4, r.d5, r.d6, r.d7, r.ncs, r.dqs, dma,
+    )
+}
@@ -0,0 +1,133 @@
+//! Secured OTP region.
+//!
+//! The chip has a small one-time programmable region, e.g. for a serial number, calibration
+//! data or key hashes. It is accessed by switching the array commands over to it, which is
+//! done by the [`OtpMode`] guard: while it exists only the OTP region can be accessed, and
+//! dropping it switches back to the main array, also when an operation failed.
+//!
+//! Once the region is locked with `lock_otp` it can't be programmed anymore.
+
+use crate::chip::Otp;
+use crate::polling::WriteOperation;
+use crate::{FlashError, FlashMemory, OpiFlashMemory, SpiFlashMemory, Transport};
+
+mod sealed {
+    use crate::FlashError;
+
+    pub trait ExitOtp {
+        fn exit_otp(&mut self) -> Result<(), FlashError>;
+    }
+}
+
+/// Access to the OTP region, see the [module docs](self). Addresses are relative to the start
+/// of the OTP region.
+pub struct OtpMode<'a, F: sealed::ExitOtp> {
+    flash: &'a mut F,
+    size: usize,
+    exited: bool,
+}
+
+impl<F: FlashMemory + sealed::ExitOtp> OtpMode<'_, F> {
+    /// Size of the OTP region in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    fn check_bounds(&self, addr: u32, len: usize) -> Result<(), FlashError> {
+        match (addr as usize).checked_add(len) {
+            Some(end) if end <= self.size => Ok(()),
+            _ => Err(FlashError::OutOfBounds),
+        }
+    }
+
+    /// Read `buffer.len()` bytes starting at `addr`.
+    pub fn read(&mut self, addr: u32, buffer: &mut [u8]) -> Result<(), FlashError> {
+        self.check_bounds(addr, buffer.len())?;
+        self.flash.read_memory(addr, buffer)
+    }
+
+    /// Program `data` starting at `addr`. Bits can only be cleared, never set again.
+    pub fn program(&mut self, addr: u32, data: &[u8]) -> Result<(), FlashError> {
+        self.check_bounds(addr, data.len())?;
+        self.flash.write_memory(addr, data)
+    }
+
+    /// Leave OTP mode and return to the main array.
+    pub fn exit(mut self) -> Result<(), FlashError> {
+        self.exited = true;
+        self.flash.exit_otp()
+    }
+}
+
+impl<F: sealed::ExitOtp> Drop for OtpMode<'_, F> {
+    fn drop(&mut self) {
+        if !self.exited {
+            let _ = self.flash.exit_otp();
+        }
+    }
+}
+
+macro_rules! impl_otp {
+    ($t:ident) => {
+        impl<X: Transport> sealed::ExitOtp for $t<X> {
+            fn exit_otp(&mut self) -> Result<(), FlashError> {
+                let otp = self.chip.otp.ok_or(FlashError::Unsupported)?;
+                self.exec_command(otp.exit)
+            }
+        }
+
+        impl<X: Transport> $t<X> {
+            fn otp_commands(&self) -> Result<Otp, FlashError> {
+                self.chip.otp.ok_or(FlashError::Unsupported)
+            }
+
+            /// Switch the array commands over to the OTP region until the returned guard is
+            /// dropped.
+            pub fn enter_otp(&mut self) -> Result<OtpMode<'_, Self>, FlashError> {
+                let otp = self.otp_commands()?;
+                self.exec_command(otp.enter)?;
+                Ok(OtpMode {
+                    flash: self,
+                    size: otp.size,
+                    exited: false,
+                })
+            }
+
+            /// Read `buffer.len()` bytes of the OTP region starting at `addr`.
+            pub fn read_otp(&mut self, addr: u32, buffer: &mut [u8]) -> Result<(), FlashError> {
+                let mut otp = self.enter_otp()?;
+                otp.read(addr, buffer)?;
+                otp.exit()
+            }
+
+            /// Program `data` into the OTP region starting at `addr`.
+            ///
+            /// Fails with [`FlashError::Locked`] if the region has been locked.
+            pub fn program_otp(&mut self, addr: u32, data: &[u8]) -> Result<(), FlashError> {
+                if self.otp_locked()? {
+                    return Err(FlashError::Locked);
+                }
+                let mut otp = self.enter_otp()?;
+                otp.program(addr, data)?;
+                otp.exit()
+            }
+
+            /// Whether the OTP region has been permanently locked.
+            pub fn otp_locked(&mut self) -> Result<bool, FlashError> {
+                let otp = self.otp_commands()?;
+                Ok(self.read_scur()? & otp.locked != 0)
+            }
+
+            /// Permanently lock the OTP region against programming.
+            pub fn lock_otp(&mut self) -> Result<(), FlashError> {
+                let otp = self.otp_commands()?;
+                self.enable_write()?;
+                self.exec_command(otp.lock)?;
+                self.finish_write(WriteOperation::Register)
+            }
+        }
+    };
+}
+
+impl_otp!(SpiFlashMemory);
+impl_otp!(OpiFlashMemory);
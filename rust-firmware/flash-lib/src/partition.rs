@@ -0,0 +1,73 @@
+//! Partition table of the board's external flash.
+//!
+//! The layout is defined in one place, `partition/table.rs`: the firmware's build script
+//! generates the flash regions of its `memory.x` from it, the bootloader starts the
+//! [`FIRMWARE`] partition, and the firmware reads and writes the others through the
+//! [`Partition`] handles. A handle takes offsets relative to the partition, fails with
+//! [`FlashError::OutOfBounds`] instead of touching a neighbour, and works with anything
+//! implementing the `embedded-storage` NOR flash traits: both drivers and
+//! [`XipFlash`](crate::xip::XipFlash).
+
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+
+use crate::FlashError;
+
+mod table;
+
+pub use table::*;
+
+impl Partition {
+    /// Whether the flash offset `addr` lies within the partition.
+    pub const fn contains(&self, addr: u32) -> bool {
+        addr >= self.offset && addr < self.end()
+    }
+
+    /// Flash offset of the `len` bytes at `offset` into the partition.
+    fn address(&self, offset: u32, len: usize) -> Result<u32, FlashError> {
+        match (offset as usize).checked_add(len) {
+            Some(end) if end <= self.size => Ok(self.offset + offset),
+            _ => Err(FlashError::OutOfBounds),
+        }
+    }
+
+    /// Read `buffer.len()` bytes starting at `offset` into the partition.
+    pub fn read<F>(&self, flash: &mut F, offset: u32, buffer: &mut [u8]) -> Result<(), FlashError>
+    where
+        F: ReadNorFlash<Error = FlashError>,
+    {
+        flash.read(self.address(offset, buffer.len())?, buffer)
+    }
+
+    /// Program `data` starting at `offset` into the partition.
+    pub fn write<F>(&self, flash: &mut F, offset: u32, data: &[u8]) -> Result<(), FlashError>
+    where
+        F: NorFlash<Error = FlashError>,
+    {
+        flash.write(self.address(offset, data.len())?, data)
+    }
+
+    /// Erase `len` bytes starting at `offset` into the partition, both must be sector aligned.
+    pub fn erase<F>(&self, flash: &mut F, offset: u32, len: usize) -> Result<(), FlashError>
+    where
+        F: NorFlash<Error = FlashError>,
+    {
+        let from = self.address(offset, len)?;
+        flash.erase(from, from + len as u32)
+    }
+
+    /// Erase the whole partition.
+    pub fn erase_all<F>(&self, flash: &mut F) -> Result<(), FlashError>
+    where
+        F: NorFlash<Error = FlashError>,
+    {
+        self.erase(flash, 0, self.size)
+    }
+}
+
+// Not derived, as the table is also compiled into build scripts without defmt.
+#[cfg(feature = "defmt")]
+impl defmt::Format for Partition {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{} ({:#x}..{:#x})", self.name, self.offset, self.end())
+    }
+}
@@ -0,0 +1,135 @@
+//! The partition table, see the [parent module](super).
+//!
+//! The build scripts generating linker scripts include this file as well, so it may only use
+//! `core`.
+
+/// The address in memory where the flash chip is mapped when in memory mapped mode.
+/// This is the address for the XSPI2 peripheral.
+pub const MEMORY_MAPPED_FLASH_ADDRESS: u32 = 0x7000_0000;
+
+/// Size of the flash divided by the table, the MX25UW25645G of the Nucleo board.
+pub const FLASH_SIZE: usize = 32 * 1024 * 1024;
+
+/// Alignment of the partitions' offsets and sizes, 64KB blocks so a partition can be erased
+/// blockwise.
+pub const PARTITION_ALIGN: usize = 64 * 1024;
+
+const MB: usize = 1024 * 1024;
+
+/// A region of the external flash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Partition {
+    pub name: &'static str,
+    /// Offset of the partition in the flash.
+    pub offset: u32,
+    /// Size of the partition in bytes.
+    pub size: usize,
+    /// Who may erase and program the partition.
+    pub access: Access,
+}
+
+/// Who may erase and program a partition at runtime, enforced by
+/// [`ProtectedFlash`](crate::policy::ProtectedFlash).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// Only written by the programmer, e.g. together with the firmware.
+    ReadOnly,
+    /// Written by the application.
+    Writable,
+    /// Written only with a [`BootloaderAccess`](crate::policy::BootloaderAccess) token, e.g. by
+    /// the bootloader or a firmware updater.
+    Bootloader,
+}
+
+impl Partition {
+    /// Offset of the first byte after the partition.
+    pub const fn end(&self) -> u32 {
+        self.offset + self.size as u32
+    }
+
+    /// Address of the partition while the flash is memory mapped.
+    pub const fn mapped_address(&self) -> u32 {
+        MEMORY_MAPPED_FLASH_ADDRESS + self.offset
+    }
+}
+
+/// The firmware the bootloader starts, executing in place. It's the `FLASH` region of the
+/// firmware's `memory.x`.
+pub const FIRMWARE: Partition = Partition {
+    name: "firmware",
+    offset: 0x0000_0000,
+    size: 4 * MB,
+    access: Access::Bootloader,
+};
+
+/// The model data, the `MODEL_DATA` region of the firmware's `memory.x`.
+pub const MODEL: Partition = Partition {
+    name: "model",
+    offset: 0x0040_0000,
+    size: 12 * MB,
+    access: Access::ReadOnly,
+};
+
+/// A new firmware image, received while the current one runs.
+pub const FIRMWARE_UPDATE: Partition = Partition {
+    name: "firmware-update",
+    offset: 0x0100_0000,
+    size: 4 * MB,
+    access: Access::Writable,
+};
+
+/// Settings shared by the bootloader and the firmware.
+pub const BOOTLOADER_CONFIG: Partition = Partition {
+    name: "bootloader-config",
+    offset: 0x0140_0000,
+    size: PARTITION_ALIGN,
+    access: Access::Bootloader,
+};
+
+/// Data stored by the firmware.
+pub const DATA: Partition = Partition {
+    name: "data",
+    offset: 0x0141_0000,
+    size: 4 * MB - PARTITION_ALIGN,
+    access: Access::Writable,
+};
+
+/// Logs written by the firmware.
+pub const LOGS: Partition = Partition {
+    name: "logs",
+    offset: 0x0180_0000,
+    size: 8 * MB,
+    access: Access::Writable,
+};
+
+/// All partitions, in flash order.
+pub const TABLE: [Partition; 6] = [
+    FIRMWARE,
+    MODEL,
+    FIRMWARE_UPDATE,
+    BOOTLOADER_CONFIG,
+    DATA,
+    LOGS,
+];
+
+const _: () = check(&TABLE);
+
+/// Fails to compile if the partitions are out of order, overlap, aren't aligned or don't fit
+/// into the flash.
+const fn check(table: &[Partition]) {
+    let mut end = 0;
+    let mut i = 0;
+    while i < table.len() {
+        let partition = &table[i];
+        let offset = partition.offset as usize;
+        assert!(offset >= end, "partitions overlap or are out of order");
+        assert!(
+            offset.is_multiple_of(PARTITION_ALIGN)
+                && partition.size.is_multiple_of(PARTITION_ALIGN),
+            "partition isn't block aligned"
+        );
+        end = offset + partition.size;
+        i += 1;
+    }
+    assert!(end <= FLASH_SIZE, "partitions don't fit into the flash");
+}
@@ -0,0 +1,200 @@
+//! Write protection by partition.
+//!
+//! The drivers erase and program wherever they are told to, including the firmware executing
+//! from the flash, and `erase_chip` wipes everything. [`ProtectedFlash`] wraps a driver or an
+//! [`XipFlash`](crate::xip::XipFlash) and checks every erase and program against the
+//! [partition table](crate::partition) before anything is sent to the chip:
+//!
+//! - [`Access::Writable`] partitions can be written.
+//! - [`Access::Bootloader`] partitions, the firmware and the bootloader configuration, only
+//!   through the [`BootloaderMode`] borrowed with [`ProtectedFlash::bootloader_access`], which
+//!   takes a [`BootloaderAccess`] token.
+//! - [`Access::ReadOnly`] partitions and addresses outside the table are never written.
+//!
+//! [`ProtectedFlash::erase_chip`] takes a [`ChipErase`] token. Violations fail with
+//! [`FlashError::PolicyViolation`].
+//!
+//! Each token can be taken once, like the peripherals, by the code meant to do such writes, e.g.
+//! a firmware updater, so a bug elsewhere can't brick the board. They don't stop code from using
+//! the driver directly, the chip's [protection](crate::protection) does that.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embedded_storage::nor_flash::{ErrorType, NorFlash, ReadNorFlash};
+
+use crate::partition::{Access, TABLE};
+use crate::{FlashError, FlashMemory};
+
+static BOOTLOADER_ACCESS_TAKEN: AtomicBool = AtomicBool::new(false);
+static CHIP_ERASE_TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Permission to write [`Access::Bootloader`] partitions.
+pub struct BootloaderAccess(());
+
+impl BootloaderAccess {
+    /// Take the token, in code meant to replace the firmware or change the bootloader
+    /// configuration. Returns `None` if it was taken before.
+    pub fn take() -> Option<Self> {
+        (!BOOTLOADER_ACCESS_TAKEN.swap(true, Ordering::Relaxed)).then_some(Self(()))
+    }
+}
+
+/// Permission to erase the whole chip.
+pub struct ChipErase(());
+
+impl ChipErase {
+    /// Take the token, in code meant to erase the firmware and everything else. Returns `None`
+    /// if it was taken before.
+    pub fn take() -> Option<Self> {
+        (!CHIP_ERASE_TAKEN.swap(true, Ordering::Relaxed)).then_some(Self(()))
+    }
+}
+
+/// A flash that is only written where the partition table allows it, see the
+/// [module docs](self).
+pub struct ProtectedFlash<F> {
+    flash: F,
+}
+
+impl<F: ReadNorFlash<Error = FlashError>> ProtectedFlash<F> {
+    pub fn new(flash: F) -> Self {
+        Self { flash }
+    }
+
+    /// The wrapped flash, e.g. to read from the mapped flash.
+    pub fn inner(&self) -> &F {
+        &self.flash
+    }
+
+    /// Borrow the flash with write access to the [`Access::Bootloader`] partitions, which ends
+    /// with the borrow.
+    pub fn bootloader_access(&mut self, _token: &BootloaderAccess) -> BootloaderMode<'_, F> {
+        BootloaderMode { flash: self }
+    }
+
+    /// Fail with [`FlashError::PolicyViolation`] unless all of the `len` bytes at `addr` may be
+    /// written.
+    pub fn check_writable(&self, addr: u32, len: usize) -> Result<(), FlashError> {
+        self.check(addr, len, false)
+    }
+
+    fn check(&self, addr: u32, len: usize, bootloader: bool) -> Result<(), FlashError> {
+        let end = match (addr as usize).checked_add(len) {
+            Some(end) if end <= self.flash.capacity() => end,
+            _ => return Err(FlashError::OutOfBounds),
+        };
+        let mut addr = addr;
+        while (addr as usize) < end {
+            let partition = TABLE
+                .iter()
+                .find(|partition| partition.contains(addr))
+                .ok_or(FlashError::PolicyViolation)?;
+            match partition.access {
+                Access::Writable => {}
+                Access::Bootloader if bootloader => {}
+                _ => return Err(FlashError::PolicyViolation),
+            }
+            addr = partition.end();
+        }
+        Ok(())
+    }
+}
+
+impl<F: FlashMemory> ProtectedFlash<F> {
+    /// Erase the whole chip, including the firmware and the bootloader configuration.
+    pub fn erase_chip(&mut self, _token: &ChipErase) -> Result<(), FlashError> {
+        self.flash.erase_chip()
+    }
+}
+
+impl<F: ErrorType> ErrorType for ProtectedFlash<F> {
+    type Error = F::Error;
+}
+
+impl<F: ReadNorFlash<Error = FlashError>> ReadNorFlash for ProtectedFlash<F> {
+    const READ_SIZE: usize = F::READ_SIZE;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.flash.read(offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        self.flash.capacity()
+    }
+}
+
+impl<F: NorFlash<Error = FlashError>> ProtectedFlash<F> {
+    fn checked_erase(&mut self, from: u32, to: u32, bootloader: bool) -> Result<(), FlashError> {
+        if from > to {
+            return Err(FlashError::OutOfBounds);
+        }
+        self.check(from, (to - from) as usize, bootloader)?;
+        self.flash.erase(from, to)
+    }
+
+    fn checked_write(
+        &mut self,
+        offset: u32,
+        bytes: &[u8],
+        bootloader: bool,
+    ) -> Result<(), FlashError> {
+        self.check(offset, bytes.len(), bootloader)?;
+        self.flash.write(offset, bytes)
+    }
+}
+
+impl<F: NorFlash<Error = FlashError>> NorFlash for ProtectedFlash<F> {
+    const WRITE_SIZE: usize = F::WRITE_SIZE;
+    const ERASE_SIZE: usize = F::ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        self.checked_erase(from, to, false)
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.checked_write(offset, bytes, false)
+    }
+}
+
+/// A [`ProtectedFlash`] that may also write the [`Access::Bootloader`] partitions, borrowed with
+/// [`ProtectedFlash::bootloader_access`].
+pub struct BootloaderMode<'a, F> {
+    flash: &'a mut ProtectedFlash<F>,
+}
+
+impl<F: ReadNorFlash<Error = FlashError>> BootloaderMode<'_, F> {
+    /// Like [`ProtectedFlash::check_writable`], with the [`Access::Bootloader`] partitions
+    /// writable.
+    pub fn check_writable(&self, addr: u32, len: usize) -> Result<(), FlashError> {
+        self.flash.check(addr, len, true)
+    }
+}
+
+impl<F: ErrorType> ErrorType for BootloaderMode<'_, F> {
+    type Error = F::Error;
+}
+
+impl<F: ReadNorFlash<Error = FlashError>> ReadNorFlash for BootloaderMode<'_, F> {
+    const READ_SIZE: usize = F::READ_SIZE;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.flash.read(offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        self.flash.capacity()
+    }
+}
+
+impl<F: NorFlash<Error = FlashError>> NorFlash for BootloaderMode<'_, F> {
+    const WRITE_SIZE: usize = F::WRITE_SIZE;
+    const ERASE_SIZE: usize = F::ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        self.flash.checked_erase(from, to, true)
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.flash.checked_write(offset, bytes, true)
+    }
+}
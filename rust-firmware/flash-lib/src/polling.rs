@@ -0,0 +1,56 @@
+//! Waiting for program and erase operations to finish.
+//!
+//! The Write In Progress bit is polled by the transport (see [`Transport::poll_until_clear`]),
+//! on the XSPI with its automatic status-polling mode: the peripheral repeats the status
+//! register read set up by a preceding indirect read until the bit clears, without the CPU
+//! issuing every read. Every wait is bounded by the chip's max operation time from its
+//! [`Timeouts`].
+
+use embassy_time::Duration;
+
+use crate::chip::Timeouts;
+use crate::{OpiFlashMemory, SpiFlashMemory, Transport};
+
+/// Kind of operation that is waited for, selecting the timeout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum WriteOperation {
+    Register,
+    Program,
+    SectorErase,
+    BlockErase,
+    ChipErase,
+}
+
+impl Timeouts {
+    /// Max duration of `operation`.
+    pub(crate) fn get(&self, operation: WriteOperation) -> Duration {
+        let ms = match operation {
+            WriteOperation::Register => self.register_write_ms,
+            WriteOperation::Program => self.page_program_ms,
+            WriteOperation::SectorErase => self.sector_erase_ms,
+            WriteOperation::BlockErase => self.block_erase_ms,
+            WriteOperation::ChipErase => self.chip_erase_ms,
+        };
+        Duration::from_millis(ms as u64)
+    }
+}
+
+macro_rules! impl_timeouts {
+    ($t:ident) => {
+        impl<X: Transport> $t<X> {
+            /// Timeouts of program and erase operations, the chip's datasheet values unless
+            /// changed with `set_timeouts`.
+            pub fn timeouts(&self) -> Timeouts {
+                self.timeouts
+            }
+
+            /// Change the timeouts of program and erase operations.
+            pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+                self.timeouts = timeouts;
+            }
+        }
+    };
+}
+
+impl_timeouts!(SpiFlashMemory);
+impl_timeouts!(OpiFlashMemory);
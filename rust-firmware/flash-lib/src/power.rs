@@ -0,0 +1,78 @@
+//! Deep power-down.
+//!
+//! A driver is put to sleep with `power_down`, which consumes it and returns a
+//! [`PoweredDown`] handle. The handle offers no commands, so nothing can be sent to the
+//! sleeping chip, and `wake` turns it back into the active driver.
+//!
+//! Memory mapped mode is left before powering down, as any access to the mapped region would
+//! stall while the chip sleeps, and restored after waking up.
+//!
+//! Both return what they consumed together with the error if they fail, so the driver isn't
+//! lost: `power_down` the driver, unmapped, and `wake` the [`PoweredDown`] handle, which can be
+//! woken again.
+
+use embassy_time::{Duration, block_for};
+
+use crate::{FlashError, HandleError, OpiFlashMemory, SpiFlashMemory, Transport};
+
+/// A flash driver whose chip is in deep power-down.
+pub struct PoweredDown<F> {
+    flash: F,
+    memory_mapped: bool,
+}
+
+impl<F> PoweredDown<F> {
+    /// Whether the driver was memory mapped before powering down, which is restored by `wake`.
+    pub fn was_memory_mapped(&self) -> bool {
+        self.memory_mapped
+    }
+}
+
+macro_rules! impl_power {
+    ($t:ident) => {
+        impl<X: Transport> $t<X> {
+            /// Put the chip into deep power-down, leaving memory mapped mode if needed.
+            // The error holds the driver, there is no allocator to box it.
+            #[allow(clippy::result_large_err)]
+            pub fn power_down(mut self) -> Result<PoweredDown<Self>, HandleError<Self>> {
+                let power_down = self.chip.power_down;
+                let memory_mapped = self.memory_mapped;
+                if memory_mapped {
+                    self.unmap();
+                }
+                if let Err(error) = self.exec_command(power_down.enter) {
+                    return Err(HandleError::new(error, self));
+                }
+                block_for(Duration::from_micros(power_down.enter_time_us as u64));
+                Ok(PoweredDown {
+                    flash: self,
+                    memory_mapped,
+                })
+            }
+        }
+
+        impl<X: Transport> PoweredDown<$t<X>> {
+            /// Wake the chip up and restore memory mapped mode if it was enabled before.
+            #[allow(clippy::result_large_err)]
+            pub fn wake(mut self) -> Result<$t<X>, HandleError<Self>> {
+                match self.release() {
+                    Ok(()) => Ok(self.flash),
+                    Err(error) => Err(HandleError::new(error, self)),
+                }
+            }
+
+            fn release(&mut self) -> Result<(), FlashError> {
+                let power_down = self.flash.chip.power_down;
+                self.flash.exec_command(power_down.release)?;
+                block_for(Duration::from_micros(power_down.release_time_us as u64));
+                if self.memory_mapped {
+                    self.flash.map()?;
+                }
+                Ok(())
+            }
+        }
+    };
+}
+
+impl_power!(SpiFlashMemory);
+impl_power!(OpiFlashMemory);
@@ -0,0 +1,470 @@
+//! Block and sector protection.
+//!
+//! The chip supports two protection schemes, selected by the one-time programmable WPSEL bit:
+//!
+//! - BP mode (default): the BP bits of the status register protect a number of 64KB blocks at
+//!   the top (or, with the TB bit of the configuration register, bottom) of the array.
+//! - Advanced sector protection: every protection unit has a volatile dynamic protection bit
+//!   (DPB) and a non-volatile solid protection bit (SPB), the unit is protected if either is
+//!   set. In password mode the SPBs can only be changed after unlocking them with a password.
+//!
+//! Protection units are the 4KB sectors of the first and last 64KB block and the 64KB blocks
+//! in between. Ranges are given as [`SectorRange`]s and must be made of whole units.
+
+use core::cmp::min;
+
+use crate::chip::Protection;
+use crate::polling::WriteOperation;
+use crate::registers::StatusRegister;
+use crate::transport::{AddressSize, DummyCycles, TransferConfig, XspiWidth};
+use crate::{
+    BLOCK_64K_SIZE, FlashError, OpiFlashMemory, SECTOR_SIZE, SpiFlashMemory, Transport,
+    dummy_cycles,
+};
+
+/// Value of a DPB or SPB read back or written for a protected unit.
+const PROTECTED: u8 = 0xFF;
+
+/// A range of whole 4KB sectors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SectorRange {
+    first: u32,
+    count: u32,
+}
+
+impl SectorRange {
+    /// `count` sectors starting with sector number `first`.
+    pub const fn new(first: u32, count: u32) -> Self {
+        Self { first, count }
+    }
+
+    /// The sectors from address `start` up to (excluding) `end`, both must be sector aligned.
+    pub fn from_addresses(start: u32, end: u32) -> Result<Self, FlashError> {
+        let sector = SECTOR_SIZE as u32;
+        if start > end {
+            return Err(FlashError::OutOfBounds);
+        }
+        if !start.is_multiple_of(sector) || !end.is_multiple_of(sector) {
+            return Err(FlashError::NotAligned);
+        }
+        Ok(Self::new(start / sector, (end - start) / sector))
+    }
+
+    /// Number of the first sector.
+    pub const fn first(&self) -> u32 {
+        self.first
+    }
+
+    /// Number of sectors.
+    pub const fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Address of the first byte.
+    pub const fn start_address(&self) -> u32 {
+        self.first * SECTOR_SIZE as u32
+    }
+
+    /// Address after the last byte.
+    pub const fn end_address(&self) -> u32 {
+        (self.first + self.count) * SECTOR_SIZE as u32
+    }
+
+    /// Whether `addr` lies within the range.
+    pub const fn contains(&self, addr: u32) -> bool {
+        addr >= self.start_address() && addr < self.end_address()
+    }
+}
+
+/// Calls `f` with the address of every protection unit in `range` of a chip of `size` bytes.
+///
+/// The whole range is checked before `f` is called for the first time, so nothing is changed
+/// if the range is out of bounds or doesn't consist of whole units.
+fn for_each_unit(
+    range: SectorRange,
+    size: usize,
+    mut f: impl FnMut(u32) -> Result<(), FlashError>,
+) -> Result<(), FlashError> {
+    let (start, end) = (range.start_address() as usize, range.end_address() as usize);
+    if end > size {
+        return Err(FlashError::OutOfBounds);
+    }
+    let unit_size = |addr: usize| {
+        if addr < BLOCK_64K_SIZE || addr >= size - BLOCK_64K_SIZE {
+            SECTOR_SIZE
+        } else {
+            BLOCK_64K_SIZE
+        }
+    };
+
+    let mut addr = start;
+    while addr < end {
+        let unit = unit_size(addr);
+        if addr % unit != 0 || addr + unit > end {
+            return Err(FlashError::NotAligned);
+        }
+        addr += unit;
+    }
+
+    let mut addr = start;
+    while addr < end {
+        f(addr as u32)?;
+        addr += unit_size(addr);
+    }
+    Ok(())
+}
+
+/// The active protection scheme.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ProtectionMode {
+    /// Protection by the BP bits of the status register.
+    Block,
+    /// Advanced sector protection, SPBs can be changed freely.
+    Solid,
+    /// Advanced sector protection, SPBs must be unlocked with the password first.
+    Password,
+}
+
+/// Protection by the BP bits of the status register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BlockProtection {
+    /// Value of BP0-BP3. Level `n > 0` protects `2^(n-1)` 64KB blocks.
+    pub level: u8,
+    /// The protected blocks are at the bottom instead of the top of the array.
+    pub bottom: bool,
+}
+
+impl BlockProtection {
+    /// The sectors protected on a chip of `size` bytes.
+    pub fn protected_range(&self, size: usize) -> SectorRange {
+        let sectors = (size / SECTOR_SIZE) as u32;
+        let count = match self.level {
+            0 => 0,
+            level => {
+                let blocks = 1u32 << (level - 1).min(31);
+                (blocks * (BLOCK_64K_SIZE / SECTOR_SIZE) as u32).min(sectors)
+            }
+        };
+        if self.bottom {
+            SectorRange::new(0, count)
+        } else {
+            SectorRange::new(sectors - count, count)
+        }
+    }
+}
+
+impl<X: Transport> SpiFlashMemory<X> {
+    /// Read a protection or fast boot register. `array_dummy` selects the dummy cycles of an array read
+    /// instead of a register read.
+    pub(crate) fn asp_read(
+        &mut self,
+        cmd: u8,
+        address: Option<u32>,
+        array_dummy: bool,
+        buffer: &mut [u8],
+    ) -> Result<(), FlashError> {
+        let transaction = TransferConfig {
+            iwidth: XspiWidth::SING,
+            isize: AddressSize::_8bit,
+            adwidth: if address.is_some() {
+                XspiWidth::SING
+            } else {
+                XspiWidth::NONE
+            },
+            adsize: AddressSize::_32bit,
+            dwidth: XspiWidth::SING,
+            instruction: Some(cmd as u32),
+            address,
+            dummy: if array_dummy {
+                DummyCycles::_8
+            } else {
+                DummyCycles::_0
+            },
+            ..Default::default()
+        };
+        self.read(buffer, transaction)
+    }
+
+    /// Write a protection or fast boot register.
+    pub(crate) fn asp_write(
+        &mut self,
+        cmd: u8,
+        address: Option<u32>,
+        data: &[u8],
+    ) -> Result<(), FlashError> {
+        let transaction = TransferConfig {
+            iwidth: XspiWidth::SING,
+            isize: AddressSize::_8bit,
+            adwidth: if address.is_some() {
+                XspiWidth::SING
+            } else {
+                XspiWidth::NONE
+            },
+            adsize: AddressSize::_32bit,
+            dwidth: XspiWidth::SING,
+            instruction: Some(cmd as u32),
+            address,
+            dummy: DummyCycles::_0,
+            ..Default::default()
+        };
+        self.write(data, transaction)
+    }
+}
+
+impl<X: Transport> OpiFlashMemory<X> {
+    /// Read a protection or fast boot register. `array_dummy` selects the dummy cycles of an array read
+    /// instead of a register read. Commands without an address get a dummy address.
+    pub(crate) fn asp_read(
+        &mut self,
+        cmd: u8,
+        address: Option<u32>,
+        array_dummy: bool,
+        buffer: &mut [u8],
+    ) -> Result<(), FlashError> {
+        let (instruction, isize) = self.instruction(cmd);
+        let dummy = if array_dummy {
+            self.geometry.opi_read_dummy_cycles
+        } else {
+            self.geometry.opi_status_dummy_cycles
+        };
+        let transaction = TransferConfig {
+            iwidth: XspiWidth::OCTO,
+            isize,
+            idtr: self.dtr,
+            adwidth: XspiWidth::OCTO,
+            adsize: AddressSize::_32bit,
+            addtr: self.dtr,
+            dwidth: XspiWidth::OCTO,
+            ddtr: self.dtr,
+            instruction,
+            address: Some(address.unwrap_or(0x00000000)),
+            dummy: dummy_cycles(dummy),
+            ..Default::default()
+        };
+
+        // DTR transfers are always a whole 2-byte word, single byte registers are repeated.
+        if self.dtr && buffer.len() % 2 == 1 {
+            let mut word = [0; 10];
+            let len = min(buffer.len() + 1, word.len());
+            self.read(&mut word[..len], transaction)?;
+            let len = buffer.len();
+            buffer.copy_from_slice(&word[..len]);
+            return Ok(());
+        }
+        self.read(buffer, transaction)
+    }
+
+    /// Write a protection or fast boot register. Commands without an address get a dummy address.
+    pub(crate) fn asp_write(
+        &mut self,
+        cmd: u8,
+        address: Option<u32>,
+        data: &[u8],
+    ) -> Result<(), FlashError> {
+        let (instruction, isize) = self.instruction(cmd);
+        let transaction = TransferConfig {
+            iwidth: XspiWidth::OCTO,
+            isize,
+            idtr: self.dtr,
+            adwidth: XspiWidth::OCTO,
+            adsize: AddressSize::_32bit,
+            addtr: self.dtr,
+            dwidth: XspiWidth::OCTO,
+            ddtr: self.dtr,
+            instruction,
+            address: Some(address.unwrap_or(0x00000000)),
+            dummy: DummyCycles::_0,
+            ..Default::default()
+        };
+
+        // DTR transfers are always a whole 2-byte word, a single byte is sent twice.
+        if self.dtr && data.len() % 2 == 1 {
+            let mut word = [0; 10];
+            let len = min(data.len() + 1, word.len());
+            word[..data.len()].copy_from_slice(data);
+            word[data.len()] = data[data.len() - 1];
+            return self.write(&word[..len], transaction);
+        }
+        self.write(data, transaction)
+    }
+}
+
+macro_rules! impl_protection {
+    ($t:ident) => {
+        impl<X: Transport> $t<X> {
+            fn protection_commands(&self) -> Result<Protection, FlashError> {
+                self.chip.protection.ok_or(FlashError::Unsupported)
+            }
+
+            /// The active protection scheme.
+            pub fn protection_mode(&mut self) -> Result<ProtectionMode, FlashError> {
+                self.protection_commands()?;
+                if !self.read_security()?.advanced_protection() {
+                    return Ok(ProtectionMode::Block);
+                }
+                if self.read_lock()?.password_mode() {
+                    Ok(ProtectionMode::Password)
+                } else {
+                    Ok(ProtectionMode::Solid)
+                }
+            }
+
+            /// Read the BP bits. Only meaningful in [`ProtectionMode::Block`].
+            pub fn read_block_protection(&mut self) -> Result<BlockProtection, FlashError> {
+                self.protection_commands()?;
+                Ok(BlockProtection {
+                    level: self.read_status()?.block_protection(),
+                    bottom: self.read_configuration()?.bottom_protection(),
+                })
+            }
+
+            /// Set the BP bits to `level` (0-15), 0 unprotects the whole array.
+            ///
+            /// Fails with [`FlashError::WrongMode`] if advanced sector protection is selected.
+            pub fn set_block_protection(&mut self, level: u8) -> Result<(), FlashError> {
+                if level > StatusRegister::MAX_BP_LEVEL {
+                    return Err(FlashError::OutOfBounds);
+                }
+                if self.protection_mode()? != ProtectionMode::Block {
+                    return Err(FlashError::WrongMode);
+                }
+                self.modify_status_configuration(|sr, cr| (sr.with_block_protection(level), cr))
+            }
+
+            /// Permanently switch from BP mode to advanced sector protection.
+            ///
+            /// This sets a one-time programmable bit, BP mode can't be selected again.
+            pub fn enable_sector_protection(&mut self) -> Result<(), FlashError> {
+                let commands = self.protection_commands()?;
+                self.enable_write()?;
+                self.exec_command(commands.select_advanced)?;
+                self.finish_write(WriteOperation::Register)
+            }
+
+            /// Set or clear the dynamic protection bits of all units in `range`.
+            pub fn set_dynamic_protection(
+                &mut self,
+                range: SectorRange,
+                protect: bool,
+            ) -> Result<(), FlashError> {
+                let commands = self.protection_commands()?;
+                let value = if protect { PROTECTED } else { 0x00 };
+                for_each_unit(range, self.geometry.size, |addr| {
+                    self.enable_write()?;
+                    self.asp_write(commands.write_dpb, Some(addr), &[value])?;
+                    self.wait_write_finish(WriteOperation::Register)
+                })
+            }
+
+            /// Whether the dynamic protection bits of all units in `range` are set.
+            pub fn dynamic_protection(&mut self, range: SectorRange) -> Result<bool, FlashError> {
+                let commands = self.protection_commands()?;
+                let mut all = true;
+                for_each_unit(range, self.geometry.size, |addr| {
+                    let mut value = [0; 1];
+                    self.asp_read(commands.read_dpb, Some(addr), false, &mut value)?;
+                    all &= value[0] == PROTECTED;
+                    Ok(())
+                })?;
+                Ok(all)
+            }
+
+            /// Set the dynamic protection bits of the whole array.
+            pub fn lock_all(&mut self) -> Result<(), FlashError> {
+                let commands = self.protection_commands()?;
+                self.enable_write()?;
+                self.exec_command(commands.gang_lock)?;
+                self.wait_write_finish(WriteOperation::Register)
+            }
+
+            /// Clear the dynamic protection bits of the whole array.
+            pub fn unlock_all(&mut self) -> Result<(), FlashError> {
+                let commands = self.protection_commands()?;
+                self.enable_write()?;
+                self.exec_command(commands.gang_unlock)?;
+                self.wait_write_finish(WriteOperation::Register)
+            }
+
+            /// Program the solid protection bits of all units in `range`. SPBs can only be
+            /// cleared all at once with [`Self::erase_solid_protection`].
+            pub fn set_solid_protection(&mut self, range: SectorRange) -> Result<(), FlashError> {
+                let commands = self.protection_commands()?;
+                for_each_unit(range, self.geometry.size, |addr| {
+                    let transaction = self.erase_transaction(addr, commands.write_spb);
+                    self.enable_write()?;
+                    self.command(&transaction)?;
+                    self.finish_write(WriteOperation::Program)
+                })
+            }
+
+            /// Clear all solid protection bits.
+            pub fn erase_solid_protection(&mut self) -> Result<(), FlashError> {
+                let commands = self.protection_commands()?;
+                self.enable_write()?;
+                self.exec_command(commands.erase_spb)?;
+                self.finish_write(WriteOperation::SectorErase)
+            }
+
+            /// Whether the solid protection bits of all units in `range` are set.
+            pub fn solid_protection(&mut self, range: SectorRange) -> Result<bool, FlashError> {
+                let commands = self.protection_commands()?;
+                let mut all = true;
+                for_each_unit(range, self.geometry.size, |addr| {
+                    let mut value = [0; 1];
+                    self.asp_read(commands.read_spb, Some(addr), false, &mut value)?;
+                    all &= value[0] == PROTECTED;
+                    Ok(())
+                })?;
+                Ok(all)
+            }
+
+            /// Program the 64-bit password. It can only be read back until password mode is
+            /// enabled.
+            pub fn write_password(&mut self, password: &[u8; 8]) -> Result<(), FlashError> {
+                let commands = self.protection_commands()?;
+                self.enable_write()?;
+                self.asp_write(commands.write_password, None, password)?;
+                self.finish_write(WriteOperation::Program)
+            }
+
+            /// Read the 64-bit password.
+            pub fn read_password(&mut self) -> Result<[u8; 8], FlashError> {
+                let commands = self.protection_commands()?;
+                let mut password = [0; 8];
+                self.asp_read(commands.read_password, None, true, &mut password)?;
+                Ok(password)
+            }
+
+            /// Permanently switch to password mode, after which the solid protection bits are
+            /// locked on every power-up until unlocked with [`Self::unlock_password`].
+            ///
+            /// The password must have been written (and verified) beforehand, it can't be
+            /// changed or read afterwards.
+            pub fn enable_password_mode(&mut self) -> Result<(), FlashError> {
+                let commands = self.protection_commands()?;
+                let lock = self.read_lock()?.with_password_mode();
+                self.enable_write()?;
+                self.asp_write(commands.write_lock, None, &[lock.into()])?;
+                self.finish_write(WriteOperation::Register)
+            }
+
+            /// Unlock the solid protection bits in password mode.
+            ///
+            /// Fails with [`FlashError::WrongPassword`] if the password doesn't match.
+            pub fn unlock_password(&mut self, password: &[u8; 8]) -> Result<(), FlashError> {
+                let commands = self.protection_commands()?;
+                self.asp_write(commands.password_unlock, None, password)?;
+                match self.finish_write(WriteOperation::Program) {
+                    Err(FlashError::ProgramFailed) => Err(FlashError::WrongPassword),
+                    result => result,
+                }
+            }
+        }
+    };
+}
+
+impl_protection!(SpiFlashMemory);
+impl_protection!(OpiFlashMemory);
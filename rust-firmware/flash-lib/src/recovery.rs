@@ -0,0 +1,78 @@
+//! Recovery of the bus mode after a warm reset.
+//!
+//! The chip keeps its bus mode across an MCU reset: after the bootloader switched it to octal
+//! mode, a debugger or watchdog reset leaves it there, and a single-line SPI reset from a fresh
+//! driver never reaches it. [`SpiFlashMemory::recover`], which `new` and `with_transport` run
+//! instead of a plain SPI reset, reads the JEDEC ID in SPI mode and in the octal STR and DTR
+//! encoding of every chip in the [`chip`](crate::chip) database, and resets the chip in the
+//! encoding it answered in, which returns it to SPI mode. If it doesn't answer at all, e.g.
+//! because it is busy, the reset is sent in every encoding instead.
+//!
+//! The octal resets don't form a valid command for a chip in another mode and are ignored by
+//! it. A read ID in the wrong encoding returns garbage, which doesn't match the probed chip's ID.
+
+use crate::chip;
+use crate::polling::WriteOperation;
+use crate::registers::BusMode;
+use crate::{FlashError, OpiFlashMemory, SpiFlashMemory, Transport};
+
+/// The octal modes probed, in order.
+const OCTAL_MODES: [BusMode; 2] = [BusMode::OctalStr, BusMode::OctalDtr];
+
+impl<X: Transport> SpiFlashMemory<X> {
+    /// Reset the chip from whatever bus mode it is in, see the [module docs](self).
+    ///
+    /// Returns the driver in SPI mode and the mode the chip answered in, `None` if it didn't
+    /// answer in any mode and was reset in all of them.
+    pub fn recover(mut self) -> Result<(Self, Option<BusMode>), FlashError> {
+        if chip::lookup(self.read_id()?).is_ok() {
+            self.reset_memory()?;
+            return Ok((self, Some(BusMode::Spi)));
+        }
+
+        // Plain loops over the database rather than iterator adapters, whose code is `core`'s
+        // and not placed in RAM by `xip.x`.
+        let spi_chip = self.chip;
+        for chip in chip::CHIPS {
+            for mode in OCTAL_MODES {
+                if chip.opi_enable.value(mode).is_none() {
+                    continue;
+                }
+                let mut flash = self.octal_driver(mode == BusMode::OctalDtr);
+                flash.chip = *chip;
+                let answered = flash.read_id()? == chip.jedec_id;
+                if answered {
+                    flash.send_reset()?;
+                }
+                self = flash.spi_driver();
+                if answered {
+                    self.wait_write_finish(WriteOperation::Register)?;
+                    return Ok((self, Some(mode)));
+                }
+            }
+        }
+
+        for chip in chip::CHIPS {
+            for mode in OCTAL_MODES {
+                if chip.opi_enable.value(mode).is_none() {
+                    continue;
+                }
+                let mut flash = self.octal_driver(mode == BusMode::OctalDtr);
+                flash.chip = *chip;
+                flash.send_reset()?;
+                self = flash.spi_driver();
+            }
+        }
+        self.chip = spi_chip;
+        self.reset_memory()?;
+        Ok((self, None))
+    }
+}
+
+impl<X: Transport> OpiFlashMemory<X> {
+    /// Send the reset commands without waiting, as the chip is back in SPI mode afterwards.
+    fn send_reset(&mut self) -> Result<(), FlashError> {
+        self.exec_command(self.chip.commands.reset_enable)?;
+        self.exec_command(self.chip.commands.reset_memory)
+    }
+}
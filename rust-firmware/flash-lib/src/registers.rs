@@ -0,0 +1,488 @@
+//! Typed status, configuration, security, lock and CR2 registers.
+//!
+//! Every register is a newtype around its raw value with accessors for its fields, and converts
+//! from and to `u8`. The layouts are the ones of the Macronix chips (see
+//! [`Registers::macronix_layout`](crate::chip::Registers::macronix_layout)). On other chips only
+//! the WIP and WEL bits of the status register mean the same, and the drivers' typed accessors
+//! other than `read_status` fail with [`FlashError::Unsupported`].
+//!
+//! `modify_status_configuration` and `modify_cr2_register` read a register, apply a closure,
+//! write the result back and check that the chip took it. The bus mode and the octal read dummy
+//! cycles can't be changed that way, as the drivers keep track of them: they are changed with
+//! `into_octo`, `into_octo_dtr`, `into_spi` and `set_timing`.
+
+use crate::polling::WriteOperation;
+use crate::{FlashError, OpiFlashMemory, SpiFlashMemory, Transport};
+
+/// Bus mode of the chip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BusMode {
+    /// 1S-1S-1S.
+    Spi,
+    /// 8S-8S-8S.
+    OctalStr,
+    /// 8D-8D-8D.
+    OctalDtr,
+}
+
+/// Implements the conversions from and to the raw register value.
+macro_rules! impl_raw {
+    ($t:ident) => {
+        impl $t {
+            /// The raw register value.
+            pub const fn bits(self) -> u8 {
+                self.0
+            }
+        }
+
+        impl From<u8> for $t {
+            fn from(value: u8) -> Self {
+                Self(value)
+            }
+        }
+
+        impl From<$t> for u8 {
+            fn from(register: $t) -> Self {
+                register.0
+            }
+        }
+    };
+}
+
+/// Sets or clears `bit` in `value`.
+const fn with_bit(value: u8, bit: u8, set: bool) -> u8 {
+    if set { value | bit } else { value & !bit }
+}
+
+/// Status register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct StatusRegister(u8);
+
+impl_raw!(StatusRegister);
+
+impl StatusRegister {
+    /// Write in progress.
+    pub const WIP: u8 = 1 << 0;
+    /// Write enable latch.
+    pub const WEL: u8 = 1 << 1;
+    /// Block protection bits BP0-BP3.
+    pub const BP_MASK: u8 = 0b0011_1100;
+    const BP_SHIFT: u8 = 2;
+    /// Highest block protection level.
+    pub const MAX_BP_LEVEL: u8 = Self::BP_MASK >> Self::BP_SHIFT;
+    /// Bits changed by a status register write.
+    pub const WRITABLE: u8 = Self::BP_MASK;
+
+    /// A program, erase or register write is in progress.
+    pub const fn write_in_progress(self) -> bool {
+        self.0 & Self::WIP != 0
+    }
+
+    /// Program, erase and register writes are enabled.
+    pub const fn write_enabled(self) -> bool {
+        self.0 & Self::WEL != 0
+    }
+
+    /// Value of BP0-BP3. Level `n > 0` protects `2^(n-1)` 64KB blocks.
+    pub const fn block_protection(self) -> u8 {
+        (self.0 & Self::BP_MASK) >> Self::BP_SHIFT
+    }
+
+    /// With BP0-BP3 set to `level`, which is truncated to [`Self::MAX_BP_LEVEL`].
+    pub const fn with_block_protection(self, level: u8) -> Self {
+        Self((self.0 & !Self::BP_MASK) | ((level << Self::BP_SHIFT) & Self::BP_MASK))
+    }
+}
+
+/// Configuration register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ConfigurationRegister(u8);
+
+impl_raw!(ConfigurationRegister);
+
+impl ConfigurationRegister {
+    /// Output driver strength ODS0-ODS2.
+    pub const ODS_MASK: u8 = 0b0000_0111;
+    /// The BP bits protect the bottom instead of the top of the array, one-time programmable.
+    pub const TB: u8 = 1 << 3;
+    /// Preamble bit pattern before the data of reads.
+    pub const PBE: u8 = 1 << 4;
+
+    /// Output driver strength, 0b111 (the default) is the weakest.
+    pub const fn output_driver_strength(self) -> u8 {
+        self.0 & Self::ODS_MASK
+    }
+
+    /// With the output driver strength set to `ods`, which is truncated to 3 bits.
+    pub const fn with_output_driver_strength(self, ods: u8) -> Self {
+        Self((self.0 & !Self::ODS_MASK) | (ods & Self::ODS_MASK))
+    }
+
+    /// The BP bits protect the bottom instead of the top of the array.
+    pub const fn bottom_protection(self) -> bool {
+        self.0 & Self::TB != 0
+    }
+
+    /// With the TB bit set or cleared. Once set it can't be cleared anymore.
+    pub const fn with_bottom_protection(self, bottom: bool) -> Self {
+        Self(with_bit(self.0, Self::TB, bottom))
+    }
+
+    /// Reads output a preamble bit pattern before the data.
+    pub const fn preamble(self) -> bool {
+        self.0 & Self::PBE != 0
+    }
+
+    pub const fn with_preamble(self, enabled: bool) -> Self {
+        Self(with_bit(self.0, Self::PBE, enabled))
+    }
+}
+
+/// Security register. It is read-only, the bits are changed by the commands they report on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SecurityRegister(u8);
+
+impl_raw!(SecurityRegister);
+
+impl SecurityRegister {
+    /// The OTP region was locked in the factory.
+    pub const SOTP: u8 = 1 << 0;
+    /// The OTP region was locked down.
+    pub const LDSO: u8 = 1 << 1;
+    /// A program operation is suspended.
+    pub const PSB: u8 = 1 << 2;
+    /// An erase operation is suspended.
+    pub const ESB: u8 = 1 << 3;
+    /// The last program operation failed.
+    pub const P_FAIL: u8 = 1 << 5;
+    /// The last erase operation failed.
+    pub const E_FAIL: u8 = 1 << 6;
+    /// Advanced sector protection instead of BP mode, one-time programmable.
+    pub const WPSEL: u8 = 1 << 7;
+
+    pub const fn factory_otp_locked(self) -> bool {
+        self.0 & Self::SOTP != 0
+    }
+
+    pub const fn otp_locked(self) -> bool {
+        self.0 & Self::LDSO != 0
+    }
+
+    pub const fn program_suspended(self) -> bool {
+        self.0 & Self::PSB != 0
+    }
+
+    pub const fn erase_suspended(self) -> bool {
+        self.0 & Self::ESB != 0
+    }
+
+    pub const fn program_failed(self) -> bool {
+        self.0 & Self::P_FAIL != 0
+    }
+
+    pub const fn erase_failed(self) -> bool {
+        self.0 & Self::E_FAIL != 0
+    }
+
+    /// Advanced sector protection is selected instead of BP mode.
+    pub const fn advanced_protection(self) -> bool {
+        self.0 & Self::WPSEL != 0
+    }
+}
+
+/// Lock register of the advanced sector protection. Its bits are one-time programmable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LockRegister(u8);
+
+impl_raw!(LockRegister);
+
+impl LockRegister {
+    /// Solid protection mode lock bit, cleared in solid protection mode.
+    pub const SPMLB: u8 = 1 << 1;
+    /// Password protection mode lock bit, cleared in password mode.
+    pub const PWDMLB: u8 = 1 << 2;
+
+    /// Solid protection mode has been selected permanently.
+    pub const fn solid_mode(self) -> bool {
+        self.0 & Self::SPMLB == 0
+    }
+
+    /// Password mode has been selected permanently.
+    pub const fn password_mode(self) -> bool {
+        self.0 & Self::PWDMLB == 0
+    }
+
+    /// With password mode selected.
+    pub const fn with_password_mode(self) -> Self {
+        Self(self.0 & !Self::PWDMLB)
+    }
+}
+
+/// A register in the address space of CR2.
+pub trait Cr2Register: From<u8> + Into<u8> + Copy + PartialEq {
+    /// Address of the register.
+    const ADDRESS: u32;
+}
+
+/// A CR2 register that isn't tracked by the drivers, and can be changed with
+/// `modify_cr2_register`.
+pub trait WritableCr2Register: Cr2Register {}
+
+/// CR2 register selecting the bus mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Cr2Mode(u8);
+
+impl_raw!(Cr2Mode);
+
+impl Cr2Register for Cr2Mode {
+    const ADDRESS: u32 = 0x0000_0000;
+}
+
+impl Cr2Mode {
+    /// Octal STR mode.
+    pub const SOPI: u8 = 1 << 0;
+    /// Octal DTR mode.
+    pub const DOPI: u8 = 1 << 1;
+    pub const MODE_MASK: u8 = Self::SOPI | Self::DOPI;
+
+    /// The selected mode, `None` for the invalid combination of both bits.
+    pub const fn bus_mode(self) -> Option<BusMode> {
+        match (self.0 & Self::SOPI != 0, self.0 & Self::DOPI != 0) {
+            (false, false) => Some(BusMode::Spi),
+            (true, false) => Some(BusMode::OctalStr),
+            (false, true) => Some(BusMode::OctalDtr),
+            (true, true) => None,
+        }
+    }
+
+    pub const fn with_bus_mode(self, mode: BusMode) -> Self {
+        let bits = match mode {
+            BusMode::Spi => 0,
+            BusMode::OctalStr => Self::SOPI,
+            BusMode::OctalDtr => Self::DOPI,
+        };
+        Self((self.0 & !Self::MODE_MASK) | bits)
+    }
+}
+
+/// CR2 register configuring the DQS output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Cr2Dqs(u8);
+
+impl_raw!(Cr2Dqs);
+
+impl Cr2Register for Cr2Dqs {
+    const ADDRESS: u32 = 0x0000_0200;
+}
+
+impl WritableCr2Register for Cr2Dqs {}
+
+impl Cr2Dqs {
+    /// DQS is driven low for one cycle before the data in DTR mode.
+    pub const DQSPRC: u8 = 1 << 0;
+    /// DQS is also driven in octal STR mode.
+    pub const DOS: u8 = 1 << 1;
+
+    pub const fn pre_cycle(self) -> bool {
+        self.0 & Self::DQSPRC != 0
+    }
+
+    pub const fn with_pre_cycle(self, enabled: bool) -> Self {
+        Self(with_bit(self.0, Self::DQSPRC, enabled))
+    }
+
+    pub const fn in_str_mode(self) -> bool {
+        self.0 & Self::DOS != 0
+    }
+
+    pub const fn with_in_str_mode(self, enabled: bool) -> Self {
+        Self(with_bit(self.0, Self::DOS, enabled))
+    }
+}
+
+/// CR2 register selecting the dummy cycles of octal reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Cr2DummyCycles(u8);
+
+impl_raw!(Cr2DummyCycles);
+
+impl Cr2Register for Cr2DummyCycles {
+    const ADDRESS: u32 = 0x0000_0300;
+}
+
+impl Cr2DummyCycles {
+    /// Dummy cycle bits DC0-DC2.
+    pub const MASK: u8 = 0b0000_0111;
+    /// Dummy cycles selected by each value of the DC bits, 20 cycles (the 200 MHz default)
+    /// down to 6.
+    pub const TABLE: [u8; 8] = [20, 18, 16, 14, 12, 10, 8, 6];
+
+    /// Number of dummy cycles.
+    pub const fn cycles(self) -> u8 {
+        Self::TABLE[(self.0 & Self::MASK) as usize]
+    }
+}
+
+/// Result of the on-die ECC for the last read chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EccResult {
+    NoError,
+    /// A single bit error was corrected.
+    Corrected,
+    /// A multi bit error was detected but couldn't be corrected.
+    Uncorrectable,
+    /// The chunk was programmed more than once, which disables its ECC.
+    DoubleProgrammed,
+}
+
+/// CR2 register reporting the on-die ECC status, on parts with on-die ECC. Read-only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Cr2EccStatus(u8);
+
+impl_raw!(Cr2EccStatus);
+
+impl Cr2Register for Cr2EccStatus {
+    const ADDRESS: u32 = 0x0000_0800;
+}
+
+impl Cr2EccStatus {
+    /// ECC result of the last read chunk.
+    pub const RESULT_MASK: u8 = 0b0000_0011;
+    /// Number of chunks with corrected errors since the counter was cleared.
+    pub const COUNT_MASK: u8 = 0b1111_0000;
+    const COUNT_SHIFT: u8 = 4;
+
+    pub const fn result(self) -> EccResult {
+        match self.0 & Self::RESULT_MASK {
+            0b00 => EccResult::NoError,
+            0b01 => EccResult::Corrected,
+            0b10 => EccResult::Uncorrectable,
+            _ => EccResult::DoubleProgrammed,
+        }
+    }
+
+    pub const fn corrected_count(self) -> u8 {
+        (self.0 & Self::COUNT_MASK) >> Self::COUNT_SHIFT
+    }
+}
+
+macro_rules! impl_registers {
+    ($t:ident) => {
+        impl<X: Transport> $t<X> {
+            fn check_macronix_layout(&self) -> Result<(), FlashError> {
+                if self.chip.registers.macronix_layout {
+                    Ok(())
+                } else {
+                    Err(FlashError::Unsupported)
+                }
+            }
+
+            pub fn read_status(&mut self) -> Result<StatusRegister, FlashError> {
+                Ok(self.read_sr()?.into())
+            }
+
+            pub fn read_configuration(&mut self) -> Result<ConfigurationRegister, FlashError> {
+                self.check_macronix_layout()?;
+                Ok(self.read_cr()?.into())
+            }
+
+            pub fn read_security(&mut self) -> Result<SecurityRegister, FlashError> {
+                self.check_macronix_layout()?;
+                Ok(self.read_scur()?.into())
+            }
+
+            pub fn read_lock(&mut self) -> Result<LockRegister, FlashError> {
+                self.check_macronix_layout()?;
+                let commands = self.chip.protection.ok_or(FlashError::Unsupported)?;
+                let mut lock = [0; 1];
+                self.asp_read(commands.read_lock, None, false, &mut lock)?;
+                Ok(lock[0].into())
+            }
+
+            /// Read the CR2 register `R`.
+            pub fn read_cr2_register<R: Cr2Register>(&mut self) -> Result<R, FlashError> {
+                self.check_macronix_layout()?;
+                Ok(self.read_cr2(R::ADDRESS)?.into())
+            }
+
+            /// The bus mode the chip reports. Fails with [`FlashError::WrongMode`] if the
+            /// register holds no valid mode, e.g. because the chip isn't in the mode the driver
+            /// talks to it in.
+            pub fn read_bus_mode(&mut self) -> Result<BusMode, FlashError> {
+                let opi = self.chip.opi_enable;
+                let value = self.read_cr2(opi.address)?;
+                opi.decode(value).ok_or(FlashError::WrongMode)
+            }
+
+            /// CR2 value selecting `mode`, keeping the register's other bits.
+            pub(crate) fn bus_mode_value(&mut self, mode: BusMode) -> Result<u8, FlashError> {
+                let opi = self.chip.opi_enable;
+                let bits = opi.value(mode).ok_or(FlashError::Unsupported)?;
+                if opi.mask == 0xFF {
+                    Ok(bits)
+                } else {
+                    Ok((self.read_cr2(opi.address)? & !opi.mask) | bits)
+                }
+            }
+
+            /// Wait for a mode switch to finish and check that the chip is in `mode`.
+            pub(crate) fn check_bus_mode(&mut self, mode: BusMode) -> Result<(), FlashError> {
+                self.wait_write_finish(WriteOperation::Register)?;
+                if self.read_bus_mode()? != mode {
+                    return Err(FlashError::WrongMode);
+                }
+                Ok(())
+            }
+
+            /// Apply `f` to the status and configuration registers and write them back.
+            ///
+            /// Fails with [`FlashError::VerifyFailed`] if the chip doesn't take the new values,
+            /// e.g. when trying to clear the TB bit.
+            pub fn modify_status_configuration(
+                &mut self,
+                f: impl FnOnce(
+                    StatusRegister,
+                    ConfigurationRegister,
+                ) -> (StatusRegister, ConfigurationRegister),
+            ) -> Result<(), FlashError> {
+                let (sr, cr) = f(self.read_status()?, self.read_configuration()?);
+                self.write_sr_cr(sr.into(), cr.into())?;
+                let written = self.read_status()?;
+                if (written.bits() ^ sr.bits()) & StatusRegister::WRITABLE != 0
+                    || self.read_configuration()? != cr
+                {
+                    return Err(FlashError::VerifyFailed);
+                }
+                Ok(())
+            }
+
+            /// Apply `f` to the CR2 register `R` and write it back.
+            ///
+            /// Fails with [`FlashError::VerifyFailed`] if the chip doesn't take the new value.
+            pub fn modify_cr2_register<R: WritableCr2Register>(
+                &mut self,
+                f: impl FnOnce(R) -> R,
+            ) -> Result<(), FlashError> {
+                let value = f(self.read_cr2_register()?);
+                self.write_cr2(R::ADDRESS, value.into())?;
+                if self.read_cr2_register::<R>()? != value {
+                    return Err(FlashError::VerifyFailed);
+                }
+                Ok(())
+            }
+        }
+    };
+}
+
+impl_registers!(SpiFlashMemory);
+impl_registers!(OpiFlashMemory);
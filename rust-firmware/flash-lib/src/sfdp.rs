@@ -0,0 +1,280 @@
+//! Serial Flash Discoverable Parameters (JESD216) parsing.
+//!
+//! Reads the basic flash parameter table, the sector map table and the xSPI profile 1.0 table
+//! and turns them into a [`FlashGeometry`] that the drivers use instead of hardcoded values.
+//!
+//! The erase commands come from the [`chip`](crate::chip) database, so the sector and block
+//! sizes are taken from the erase types with those commands. Tables without them, or with a
+//! sector map that doesn't allow them in the whole array, are rejected.
+
+use crate::chip::Commands;
+use crate::{BLOCK_64K_SIZE, FlashError, MEMORY_PAGE_SIZE, MEMORY_SIZE, SECTOR_SIZE};
+
+/// "SFDP" in little endian.
+const SFDP_SIGNATURE: u32 = 0x5044_4653;
+
+/// Parameter IDs of the tables we know how to parse.
+const BFPT_ID: u16 = 0xFF00;
+const SECTOR_MAP_ID: u16 = 0xFF81;
+const XSPI_PROFILE_1_ID: u16 = 0xFF05;
+
+/// Max number of parameter headers that are looked at.
+const MAX_PARAMETER_HEADERS: usize = 16;
+/// Max number of DWORDs read from a single parameter table.
+const MAX_TABLE_DWORDS: usize = 64;
+
+/// Sizes and timings of the flash chip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FlashGeometry {
+    /// Total size of the flash in bytes.
+    pub size: usize,
+    /// Max number of bytes programmed by a single page program.
+    pub page_size: usize,
+    /// Size of the sector erased by `erase_sector`, always [`SECTOR_SIZE`] as the
+    /// `embedded-storage` implementations erase in those units.
+    pub sector_size: usize,
+    /// Size of the block erased by `erase_block_64k`.
+    pub block_size: usize,
+    /// Dummy cycles of an octal read. The chip's default configuration, until the timing
+    /// profile's dummy cycles are programmed when switching to octal mode.
+    pub opi_read_dummy_cycles: u8,
+    /// Dummy cycles of an octal status register read.
+    pub opi_status_dummy_cycles: u8,
+}
+
+impl FlashGeometry {
+    /// Geometry of the MX25UW25645G on the Nucleo board, used until SFDP has been read.
+    pub const MX25UW25645G: Self = Self {
+        size: MEMORY_SIZE,
+        page_size: MEMORY_PAGE_SIZE,
+        sector_size: SECTOR_SIZE,
+        block_size: BLOCK_64K_SIZE,
+        opi_read_dummy_cycles: 20,
+        opi_status_dummy_cycles: 4,
+    };
+
+    /// Checks that `len` bytes starting at `addr` lie within the flash memory.
+    pub fn check_bounds(&self, addr: u32, len: usize) -> Result<(), FlashError> {
+        match (addr as usize).checked_add(len) {
+            Some(end) if end <= self.size => Ok(()),
+            _ => Err(FlashError::OutOfBounds),
+        }
+    }
+}
+
+impl Default for FlashGeometry {
+    fn default() -> Self {
+        Self::MX25UW25645G
+    }
+}
+
+struct ParameterHeader {
+    id: u16,
+    dwords: usize,
+    pointer: u32,
+}
+
+/// Read the SFDP tables using `read` and build the flash geometry from them, for a chip erased
+/// with `commands`.
+///
+/// `read(addr, buffer)` must issue the chip's Read SFDP command for the given SFDP address.
+pub fn read_geometry<F>(commands: &Commands, mut read: F) -> Result<FlashGeometry, FlashError>
+where
+    F: FnMut(u32, &mut [u8]) -> Result<(), FlashError>,
+{
+    let mut header = [0u8; 8];
+    read(0, &mut header)?;
+    if u32::from_le_bytes([header[0], header[1], header[2], header[3]]) != SFDP_SIGNATURE {
+        return Err(FlashError::InvalidSfdp);
+    }
+    let header_count = (header[6] as usize + 1).min(MAX_PARAMETER_HEADERS);
+
+    let mut bfpt = None;
+    let mut sector_map = None;
+    let mut profile = None;
+    for i in 0..header_count {
+        let mut raw = [0u8; 8];
+        read(8 + 8 * i as u32, &mut raw)?;
+        let param = ParameterHeader {
+            id: u16::from_le_bytes([raw[0], raw[7]]),
+            dwords: raw[3] as usize,
+            pointer: u32::from_le_bytes([raw[4], raw[5], raw[6], 0]),
+        };
+        match param.id {
+            BFPT_ID if bfpt.is_none() => bfpt = Some(param),
+            SECTOR_MAP_ID => sector_map = Some(param),
+            XSPI_PROFILE_1_ID => profile = Some(param),
+            _ => {}
+        }
+    }
+
+    let mut table = [0u32; MAX_TABLE_DWORDS];
+
+    let bfpt = bfpt.ok_or(FlashError::InvalidSfdp)?;
+    let dwords = read_table(&mut read, &bfpt, &mut table)?;
+    let erase_types = erase_types(&table[..dwords]);
+    let sector = find_erase_type(&erase_types, commands.sector_erase)?;
+    let block = find_erase_type(&erase_types, commands.block_erase)?;
+    let mut geometry = parse_bfpt(&table[..dwords])?;
+    if erase_types[sector].size != SECTOR_SIZE {
+        return Err(FlashError::InvalidSfdp);
+    }
+    geometry.sector_size = erase_types[sector].size;
+    geometry.block_size = erase_types[block].size;
+
+    if let Some(sector_map) = sector_map {
+        let dwords = read_table(&mut read, &sector_map, &mut table)?;
+        check_sector_map(&table[..dwords], &[sector, block])?;
+    }
+
+    if let Some(profile) = profile {
+        let dwords = read_table(&mut read, &profile, &mut table)?;
+        apply_xspi_profile(&mut geometry, &table[..dwords]);
+    }
+
+    Ok(geometry)
+}
+
+fn read_table<F>(
+    read: &mut F,
+    param: &ParameterHeader,
+    table: &mut [u32; MAX_TABLE_DWORDS],
+) -> Result<usize, FlashError>
+where
+    F: FnMut(u32, &mut [u8]) -> Result<(), FlashError>,
+{
+    let dwords = param.dwords.min(MAX_TABLE_DWORDS);
+    for (i, dword) in table.iter_mut().take(dwords).enumerate() {
+        let mut raw = [0u8; 4];
+        read(param.pointer + 4 * i as u32, &mut raw)?;
+        *dword = u32::from_le_bytes(raw);
+    }
+    Ok(dwords)
+}
+
+/// An erase type of the basic flash parameter table.
+#[derive(Clone, Copy, Default)]
+struct EraseType {
+    /// Erase size in bytes, 0 if the erase type is unsupported.
+    size: usize,
+    opcode: u8,
+}
+
+/// The four erase types in the basic flash parameter table.
+fn erase_types(bfpt: &[u32]) -> [EraseType; 4] {
+    let mut types = [EraseType::default(); 4];
+    for (i, erase_type) in types.iter_mut().enumerate() {
+        // Erase types 1 and 2 are in DWORD 8, types 3 and 4 in DWORD 9.
+        let Some(dword) = bfpt.get(7 + i / 2) else {
+            continue;
+        };
+        let field = dword >> (16 * (i % 2));
+        let exponent = field & 0xFF;
+        if exponent != 0 {
+            *erase_type = EraseType {
+                size: 1 << exponent,
+                opcode: (field >> 8) as u8,
+            };
+        }
+    }
+    types
+}
+
+/// Index of the erase type performed by the 4-byte address erase command `opcode`. Most chips
+/// list the 3-byte address variant of the command.
+fn find_erase_type(types: &[EraseType; 4], opcode: u8) -> Result<usize, FlashError> {
+    let three_byte = match opcode {
+        0x21 => 0x20,
+        0x5C => 0x52,
+        0xDC => 0xD8,
+        other => other,
+    };
+    types
+        .iter()
+        .position(|erase_type| {
+            erase_type.size != 0 && (erase_type.opcode == opcode || erase_type.opcode == three_byte)
+        })
+        .ok_or(FlashError::InvalidSfdp)
+}
+
+fn parse_bfpt(bfpt: &[u32]) -> Result<FlashGeometry, FlashError> {
+    // JESD216 requires at least 9 DWORDs, the page size is in DWORD 11 (JESD216A+).
+    if bfpt.len() < 9 {
+        return Err(FlashError::InvalidSfdp);
+    }
+
+    let density = bfpt[1];
+    let size_bits: u64 = if density & (1 << 31) == 0 {
+        density as u64 + 1
+    } else {
+        1 << (density & 0x7FFF_FFFF).min(63)
+    };
+    let size = (size_bits / 8) as usize;
+
+    let page_size = match bfpt.get(10) {
+        Some(dword) => 1 << ((dword >> 4) & 0xF),
+        None => MEMORY_PAGE_SIZE,
+    };
+
+    Ok(FlashGeometry {
+        size,
+        page_size,
+        ..FlashGeometry::MX25UW25645G
+    })
+}
+
+/// Check that the erase types with the indices `used` are available in every region of the
+/// sector map. Only the first map is considered, configuration detection commands are skipped.
+fn check_sector_map(map: &[u32], used: &[usize]) -> Result<(), FlashError> {
+    let mut i = 0;
+    while i < map.len() {
+        let descriptor = map[i];
+        if descriptor & (1 << 1) == 0 {
+            // Configuration detection command descriptor, always 2 DWORDs.
+            if descriptor & 1 != 0 {
+                return Ok(());
+            }
+            i += 2;
+            continue;
+        }
+
+        let regions = ((descriptor >> 16) & 0xFF) as usize + 1;
+        let Some(region_dwords) = map.get(i + 1..i + 1 + regions) else {
+            return Err(FlashError::InvalidSfdp);
+        };
+        // Bit n of a region DWORD is set if erase type n + 1 can be used in that region.
+        let common = region_dwords
+            .iter()
+            .fold(0xF, |acc, region| acc & region & 0xF);
+        if used
+            .iter()
+            .any(|erase_type| common & (1 << erase_type) == 0)
+        {
+            return Err(FlashError::InvalidSfdp);
+        }
+        return Ok(());
+    }
+    Ok(())
+}
+
+/// Read dummy cycles from the xSPI profile 1.0 table, for the fastest supported frequency
+/// which is also the chip's power-on default.
+fn apply_xspi_profile(geometry: &mut FlashGeometry, profile: &[u32]) {
+    if let Some(dword1) = profile.first() {
+        geometry.opi_status_dummy_cycles = if dword1 & (1 << 28) != 0 { 8 } else { 4 };
+    }
+
+    let dummy_200mhz = profile.get(3).map(|dword| (dword >> 7) & 0x1F);
+    let dummy_166mhz = profile.get(4).map(|dword| (dword >> 27) & 0x1F);
+    let dummy_133mhz = profile.get(4).map(|dword| (dword >> 17) & 0x1F);
+    let dummy_100mhz = profile.get(4).map(|dword| (dword >> 7) & 0x1F);
+    let dummy = [dummy_200mhz, dummy_166mhz, dummy_133mhz, dummy_100mhz]
+        .into_iter()
+        .flatten()
+        .find(|&cycles| cycles != 0);
+    if let Some(cycles) = dummy {
+        // Round up to an even count, odd counts aren't supported in DTR mode.
+        geometry.opi_read_dummy_cycles = (cycles + (cycles & 1)) as u8;
+    }
+}
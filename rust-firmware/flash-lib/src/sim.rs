@@ -0,0 +1,750 @@
+//! Software model of the MX25UW25645G for host tests.
+//!
+//! [`SimulatedFlash`] is a [`Transport`] that decodes every transaction like the chip would and
+//! keeps the array, registers and bus mode in memory, so the drivers can be exercised with
+//! `cargo test` on a host. It models:
+//!
+//! - NOR semantics: programming only clears bits and wraps around within the page, erases set
+//!   whole 4KB sectors, 64KB blocks or the whole array back to 0xFF.
+//! - The write enable latch, which program, erase and register writes require and clear, and
+//!   the write in progress bit, which stays set for a number of status reads after every write
+//!   (see [`SimulatedFlash::set_busy_reads`]).
+//! - SPI, octal STR and octal DTR mode, switched with CR2 at address 0, the DQS bits of CR2 at
+//!   address 0x200 and the octal read dummy cycles of CR2 at address 0x300. Transactions in the wrong encoding (widths, command
+//!   extension, DTR, address size, dummy cycles) are ignored by the chip and counted, see
+//!   [`SimulatedFlash::ignored`].
+//! - BP block protection with the TB bit. Programs and erases of protected blocks set
+//!   P_FAIL/E_FAIL in the security register instead of changing the array.
+//! - Reset, deep power-down and the SFDP tables.
+//!
+//! Advanced sector protection, OTP, suspend, write-to-buffer and fast boot commands aren't
+//! modelled and are ignored like unknown opcodes.
+
+use core::cell::RefCell;
+
+use embassy_time::Duration;
+use std::rc::Rc;
+use std::vec;
+use std::vec::Vec;
+
+use crate::FlashError;
+use crate::transport::{AddressSize, MemoryType, TransferConfig, Transport, XspiWidth};
+
+pub use crate::registers::BusMode;
+
+/// Size of the array in bytes.
+const SIZE: usize = 32 * 1024 * 1024;
+const PAGE_SIZE: usize = 256;
+const SECTOR_SIZE: usize = 4 * 1024;
+const BLOCK_SIZE: usize = 64 * 1024;
+
+const JEDEC_ID: [u8; 3] = [0xC2, 0x81, 0x39];
+
+const READ4B: u8 = 0x13;
+const FAST_READ4B: u8 = 0x0C;
+const OCTA_READ: u8 = 0xEC;
+const OCTA_DTR_READ: u8 = 0xEE;
+const PP4B: u8 = 0x12;
+const SE4B: u8 = 0x21;
+const BE4B: u8 = 0xDC;
+const CE: u8 = 0x60;
+const CE_ALT: u8 = 0xC7;
+const WREN: u8 = 0x06;
+const WRDI: u8 = 0x04;
+const RSTEN: u8 = 0x66;
+const RST: u8 = 0x99;
+const DP: u8 = 0xB9;
+const RDP: u8 = 0xAB;
+const RDID: u8 = 0x9F;
+const RDSFDP: u8 = 0x5A;
+const RDSR: u8 = 0x05;
+const RDCR: u8 = 0x15;
+const WRSR: u8 = 0x01;
+const RDCR2: u8 = 0x71;
+const WRCR2: u8 = 0x72;
+const RDSCUR: u8 = 0x2B;
+
+const SR_WIP: u8 = 1 << 0;
+const SR_WEL: u8 = 1 << 1;
+const SR_BP_MASK: u8 = 0b0011_1100;
+const SR_BP_SHIFT: u8 = 2;
+const CR_TB: u8 = 1 << 3;
+const SCUR_P_FAIL: u8 = 1 << 5;
+const SCUR_E_FAIL: u8 = 1 << 6;
+
+/// CR2 address of the SOPI/DOPI bits.
+const CR2_MODE: u32 = 0x0000_0000;
+const CR2_SOPI: u8 = 1 << 0;
+const CR2_DOPI: u8 = 1 << 1;
+/// CR2 address of the DQS pre-cycle and STR output bits.
+const CR2_DQS: u32 = 0x0000_0200;
+const CR2_DQS_MASK: u8 = 0x03;
+/// CR2 address of the octal read dummy cycle bits.
+const CR2_DUMMY: u32 = 0x0000_0300;
+const CR2_DUMMY_MASK: u8 = 0x07;
+/// Dummy cycles selected by the CR2 dummy cycle bits.
+const DUMMY_CYCLES: [u8; 8] = [20, 18, 16, 14, 12, 10, 8, 6];
+
+/// Dummy cycles of fast reads and SFDP reads in SPI mode.
+const SPI_READ_DUMMY_CYCLES: u8 = 8;
+/// Dummy cycles of register reads in octal mode.
+const OPI_REGISTER_DUMMY_CYCLES: u8 = 4;
+
+/// Number of automatic status reads after which polling gives up, standing in for the
+/// transport's timeout.
+const POLL_LIMIT: u32 = 10_000;
+
+/// An array operation executed by the simulated chip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// Page program of `len` bytes at `address`.
+    Program { address: u32, len: usize },
+    /// Erase of the `size` bytes at `address` (sector, block or the whole chip).
+    Erase { address: u32, size: usize },
+}
+
+/// The simulated chip, see the [module docs](self).
+///
+/// Clones are handles to the same chip, so a test can keep one to inspect the chip while the
+/// driver owns the other, or hand it to a new driver to model an MCU reset.
+#[derive(Clone)]
+pub struct SimulatedFlash {
+    chip: Rc<RefCell<Chip>>,
+}
+
+impl SimulatedFlash {
+    /// An erased chip in SPI mode, as after power-up.
+    pub fn new() -> Self {
+        Self {
+            chip: Rc::new(RefCell::new(Chip::new())),
+        }
+    }
+
+    /// The bus mode the chip is in.
+    pub fn mode(&self) -> BusMode {
+        self.chip.borrow().mode()
+    }
+
+    /// `len` bytes of the array starting at `addr`.
+    pub fn contents(&self, addr: u32, len: usize) -> Vec<u8> {
+        let start = addr as usize;
+        self.chip.borrow().array[start..start + len].to_vec()
+    }
+
+    /// Overwrite the array at `addr` with `data`, bypassing NOR semantics.
+    pub fn load(&self, addr: u32, data: &[u8]) {
+        let start = addr as usize;
+        self.chip.borrow_mut().array[start..start + data.len()].copy_from_slice(data);
+    }
+
+    /// Overwrite the SFDP tables at `addr` with `data`, e.g. to describe another chip.
+    pub fn patch_sfdp(&self, addr: u32, data: &[u8]) {
+        let start = addr as usize;
+        self.chip.borrow_mut().sfdp[start..start + data.len()].copy_from_slice(data);
+    }
+
+    /// Value of CR2 at `address`.
+    pub fn cr2(&self, address: u32) -> u8 {
+        self.chip.borrow().cr2(address)
+    }
+
+    /// The array operations executed since the last call.
+    pub fn take_operations(&self) -> Vec<Operation> {
+        core::mem::take(&mut self.chip.borrow_mut().operations)
+    }
+
+    /// Number of transactions the chip ignored because they weren't encoded as it expects in
+    /// its current mode.
+    pub fn ignored(&self) -> usize {
+        self.chip.borrow().ignored
+    }
+
+    /// Number of status reads that report the write in progress after every program, erase
+    /// and register write (1 by default). `u32::MAX` makes waiting for the chip time out.
+    pub fn set_busy_reads(&self, reads: u32) {
+        self.chip.borrow_mut().busy_setting = reads;
+    }
+
+    /// The clock prescaler last set by the driver.
+    pub fn prescaler(&self) -> u8 {
+        self.chip.borrow().prescaler
+    }
+
+    /// Power the chip off and on again, which clears all volatile state.
+    pub fn power_cycle(&self) {
+        let mut chip = self.chip.borrow_mut();
+        chip.reset();
+        chip.powered_down = false;
+    }
+}
+
+impl Default for SimulatedFlash {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transport for SimulatedFlash {
+    fn command(&mut self, transaction: &TransferConfig) -> Result<(), FlashError> {
+        self.chip.borrow_mut().execute(transaction, Data::None);
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut [u8], transaction: TransferConfig) -> Result<(), FlashError> {
+        self.chip
+            .borrow_mut()
+            .execute(&transaction, Data::Read(buffer));
+        Ok(())
+    }
+
+    fn write(&mut self, buffer: &[u8], transaction: TransferConfig) -> Result<(), FlashError> {
+        self.chip
+            .borrow_mut()
+            .execute(&transaction, Data::Write(buffer));
+        Ok(())
+    }
+
+    fn poll_until_clear(
+        &mut self,
+        mask: u8,
+        addressed: bool,
+        _timeout: Duration,
+    ) -> Result<(), FlashError> {
+        let mut chip = self.chip.borrow_mut();
+        let Some(transaction) = chip.last_read else {
+            return Err(FlashError::Timeout);
+        };
+        // Rewriting the wrong register doesn't start the polling.
+        if transaction.address.is_some() != addressed {
+            return Err(FlashError::Timeout);
+        }
+        for _ in 0..POLL_LIMIT {
+            let mut value = [0; 2];
+            let len = if transaction.ddtr { 2 } else { 1 };
+            chip.execute(&transaction, Data::Read(&mut value[..len]));
+            if value[0] & mask == 0 {
+                return Ok(());
+            }
+        }
+        Err(FlashError::Timeout)
+    }
+
+    fn set_memory_type(&mut self, memory_type: MemoryType) {
+        self.chip.borrow_mut().memory_type = memory_type;
+    }
+
+    fn set_device_size(&mut self, _size: usize) {}
+
+    fn set_prescaler(&mut self, prescaler: u8) {
+        self.chip.borrow_mut().prescaler = prescaler;
+    }
+
+    fn set_dqs(&mut self, enabled: bool) {
+        self.chip.borrow_mut().dqs = enabled;
+    }
+
+    fn enable_memory_mapped(
+        &mut self,
+        read: TransferConfig,
+        _write: TransferConfig,
+    ) -> Result<(), FlashError> {
+        let mut chip = self.chip.borrow_mut();
+        // Mapped accesses take the address from the bus.
+        let read = TransferConfig {
+            address: Some(0),
+            ..read
+        };
+        match chip.decode(&read, true) {
+            Some(command) if chip.is_array_read(&command) => {}
+            _ => chip.ignored += 1,
+        }
+        Ok(())
+    }
+
+    fn disable_memory_mapped(&mut self) {}
+
+    fn mapped_ptr(&self) -> *const u8 {
+        // The array is never reallocated, so the pointer stays valid as long as the chip lives.
+        self.chip.borrow().array.as_ptr()
+    }
+}
+
+/// Data phase of a transaction.
+enum Data<'a> {
+    None,
+    Read(&'a mut [u8]),
+    Write(&'a [u8]),
+}
+
+/// A decoded transaction.
+struct Command {
+    opcode: u8,
+    address: Option<u32>,
+    address_bytes: usize,
+    dummy: u8,
+}
+
+struct Chip {
+    array: Vec<u8>,
+    sfdp: Vec<u8>,
+    status: u8,
+    config: u8,
+    security: u8,
+    cr2_mode: u8,
+    cr2_dqs: u8,
+    cr2_dummy: u8,
+    reset_enabled: bool,
+    powered_down: bool,
+    /// Status reads left that report the write in progress.
+    busy_reads: u32,
+    busy_setting: u32,
+    last_read: Option<TransferConfig>,
+    memory_type: MemoryType,
+    prescaler: u8,
+    dqs: bool,
+    operations: Vec<Operation>,
+    ignored: usize,
+}
+
+impl Chip {
+    fn new() -> Self {
+        Self {
+            array: vec![0xFF; SIZE],
+            sfdp: sfdp_tables(),
+            status: 0,
+            config: 0,
+            security: 0,
+            cr2_mode: 0,
+            cr2_dqs: 0,
+            cr2_dummy: 0,
+            reset_enabled: false,
+            powered_down: false,
+            busy_reads: 0,
+            busy_setting: 1,
+            last_read: None,
+            memory_type: MemoryType::Macronix,
+            prescaler: 0,
+            dqs: false,
+            operations: Vec::new(),
+            ignored: 0,
+        }
+    }
+
+    fn mode(&self) -> BusMode {
+        if self.cr2_mode & CR2_DOPI != 0 {
+            BusMode::OctalDtr
+        } else if self.cr2_mode & CR2_SOPI != 0 {
+            BusMode::OctalStr
+        } else {
+            BusMode::Spi
+        }
+    }
+
+    fn dtr(&self) -> bool {
+        self.mode() == BusMode::OctalDtr
+    }
+
+    fn cr2(&self, address: u32) -> u8 {
+        match address {
+            CR2_MODE => self.cr2_mode,
+            CR2_DQS => self.cr2_dqs,
+            CR2_DUMMY => self.cr2_dummy,
+            _ => 0x00,
+        }
+    }
+
+    /// Reset the volatile state, the non-volatile BP and TB bits are kept.
+    fn reset(&mut self) {
+        self.status &= SR_BP_MASK;
+        self.security = 0;
+        self.cr2_mode = 0;
+        self.cr2_dqs = 0;
+        self.cr2_dummy = 0;
+        self.reset_enabled = false;
+        self.busy_reads = 0;
+    }
+
+    /// Decode the instruction, address and dummy phase of `transaction` in the current mode.
+    ///
+    /// Returns `None` if the chip wouldn't recognize the transaction.
+    fn decode(&self, transaction: &TransferConfig, has_data: bool) -> Option<Command> {
+        let instruction = transaction.instruction?;
+        let (width, isize, dtr) = match self.mode() {
+            BusMode::Spi => (XspiWidth::SING, AddressSize::_8bit, false),
+            BusMode::OctalStr => (XspiWidth::OCTO, AddressSize::_16bit, false),
+            BusMode::OctalDtr => (XspiWidth::OCTO, AddressSize::_16bit, true),
+        };
+        if transaction.iwidth != width || transaction.isize != isize || transaction.idtr != dtr {
+            return None;
+        }
+        let opcode = if isize == AddressSize::_16bit {
+            // Octal commands are the opcode followed by its inverse.
+            let (opcode, extension) = ((instruction >> 8) as u8, instruction as u8);
+            if extension != !opcode {
+                return None;
+            }
+            opcode
+        } else {
+            instruction as u8
+        };
+
+        let address = match transaction.adwidth {
+            XspiWidth::NONE => None,
+            adwidth if adwidth == width && transaction.addtr == dtr => transaction.address,
+            _ => return None,
+        };
+        if has_data && (transaction.dwidth != width || transaction.ddtr != dtr) {
+            return None;
+        }
+        Some(Command {
+            opcode,
+            address,
+            address_bytes: transaction.adsize.bytes(),
+            dummy: transaction.dummy.cycles(),
+        })
+    }
+
+    /// Whether `command` is the array read of the current mode with the right dummy cycles.
+    fn is_array_read(&self, command: &Command) -> bool {
+        match self.mode() {
+            BusMode::Spi => {
+                command.opcode == FAST_READ4B
+                    && self.expect(command, Some(4), SPI_READ_DUMMY_CYCLES)
+            }
+            BusMode::OctalStr => {
+                command.opcode == OCTA_READ && self.expect(command, Some(4), self.read_dummy())
+            }
+            BusMode::OctalDtr => {
+                command.opcode == OCTA_DTR_READ && self.expect(command, Some(4), self.read_dummy())
+            }
+        }
+    }
+
+    /// Dummy cycles of octal array reads.
+    fn read_dummy(&self) -> u8 {
+        DUMMY_CYCLES[(self.cr2_dummy & CR2_DUMMY_MASK) as usize]
+    }
+
+    /// Whether `command` has an address of `address_bytes` (or none) and `dummy` cycles.
+    fn expect(&self, command: &Command, address_bytes: Option<usize>, dummy: u8) -> bool {
+        let address_ok = match address_bytes {
+            None => command.address.is_none(),
+            Some(bytes) => command.address.is_some() && command.address_bytes == bytes,
+        };
+        address_ok && command.dummy == dummy
+    }
+
+    /// Address and dummy cycles of register reads in the current mode.
+    fn register_read(&self) -> (Option<usize>, u8) {
+        match self.mode() {
+            BusMode::Spi => (None, 0),
+            _ => (Some(4), OPI_REGISTER_DUMMY_CYCLES),
+        }
+    }
+
+    fn execute(&mut self, transaction: &TransferConfig, data: Data) {
+        let has_data = !matches!(data, Data::None);
+        let command = match self.decode(transaction, has_data) {
+            Some(command) if !self.powered_down || command.opcode == RDP => command,
+            _ => return self.ignore(data),
+        };
+        let reset_enabled = core::mem::take(&mut self.reset_enabled);
+
+        let handled = match data {
+            Data::None => self.execute_command(&command, reset_enabled),
+            Data::Read(buffer) => {
+                let handled = self.execute_read(&command, buffer);
+                if handled {
+                    self.last_read = Some(*transaction);
+                }
+                handled
+            }
+            Data::Write(buffer) => self.execute_write(&command, buffer),
+        };
+        if !handled {
+            self.ignored += 1;
+        }
+    }
+
+    /// A transaction the chip doesn't respond to: reads see the pulled up bus.
+    fn ignore(&mut self, data: Data) {
+        if let Data::Read(buffer) = data {
+            buffer.fill(0xFF);
+        }
+        self.ignored += 1;
+    }
+
+    fn execute_command(&mut self, command: &Command, reset_enabled: bool) -> bool {
+        let no_address = self.expect(command, None, 0);
+        match command.opcode {
+            WREN if no_address => self.status |= SR_WEL,
+            WRDI if no_address => self.status &= !SR_WEL,
+            RSTEN if no_address => self.reset_enabled = true,
+            RST if no_address => {
+                if reset_enabled {
+                    self.reset();
+                }
+            }
+            DP if no_address => self.powered_down = true,
+            RDP if no_address => self.powered_down = false,
+            CE | CE_ALT if no_address => {
+                if self.start_write() {
+                    if self.status & SR_BP_MASK != 0 {
+                        self.security |= SCUR_E_FAIL;
+                    } else {
+                        self.array.fill(0xFF);
+                        self.operations.push(Operation::Erase {
+                            address: 0,
+                            size: SIZE,
+                        });
+                    }
+                }
+            }
+            SE4B | BE4B if self.expect(command, Some(4), 0) => {
+                let size = if command.opcode == SE4B {
+                    SECTOR_SIZE
+                } else {
+                    BLOCK_SIZE
+                };
+                self.erase(command.address.unwrap_or(0), size);
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn execute_read(&mut self, command: &Command, buffer: &mut [u8]) -> bool {
+        let (register_address, register_dummy) = self.register_read();
+        let is_register_read = self.expect(command, register_address, register_dummy);
+        let spi = self.mode() == BusMode::Spi;
+        match command.opcode {
+            RDID if is_register_read => self.output_register(&JEDEC_ID, buffer),
+            RDSR if is_register_read => {
+                let mut status = self.status;
+                if self.busy_reads > 0 {
+                    self.busy_reads -= 1;
+                    status |= SR_WIP;
+                }
+                self.output_register(&[status], buffer);
+            }
+            RDCR if is_register_read => self.output_register(&[self.config], buffer),
+            RDSCUR if is_register_read => self.output_register(&[self.security], buffer),
+            RDCR2 if self.expect(command, Some(4), register_dummy) => {
+                let value = self.cr2(command.address.unwrap_or(0));
+                self.output_register(&[value], buffer);
+            }
+            RDSFDP
+                if (spi && self.expect(command, Some(3), SPI_READ_DUMMY_CYCLES))
+                    || (!spi && self.expect(command, Some(4), self.read_dummy())) =>
+            {
+                let start = command.address.unwrap_or(0) as usize;
+                for (i, byte) in buffer.iter_mut().enumerate() {
+                    *byte = self.sfdp.get(start + i).copied().unwrap_or(0xFF);
+                }
+            }
+            READ4B if spi && self.expect(command, Some(4), 0) => {
+                self.output_array(command, buffer);
+            }
+            _ if self.is_array_read(command) => {
+                // DTR reads start at even addresses and need the DQS strobe.
+                let address = command.address.unwrap_or(0);
+                if self.dtr() && (address % 2 == 1 || !self.dqs) {
+                    buffer.fill(0xFF);
+                    return false;
+                }
+                self.output_array(command, buffer);
+            }
+            _ => {
+                buffer.fill(0xFF);
+                return false;
+            }
+        }
+        true
+    }
+
+    fn execute_write(&mut self, command: &Command, data: &[u8]) -> bool {
+        let (register_address, _) = self.register_read();
+        let Some(&first) = data.first() else {
+            return false;
+        };
+        match command.opcode {
+            WRSR if self.expect(command, register_address, 0) => {
+                if !self.start_write() {
+                    return true;
+                }
+                let (sr, cr) = match (self.mode(), command.address) {
+                    (BusMode::Spi, _) => (Some(first), data.get(1).copied()),
+                    // In octal mode the address selects the register.
+                    (_, Some(1)) => (None, Some(first)),
+                    _ => (Some(first), None),
+                };
+                if let Some(sr) = sr {
+                    self.status = (self.status & !SR_BP_MASK) | (sr & SR_BP_MASK);
+                }
+                if let Some(cr) = cr {
+                    // TB is one-time programmable.
+                    self.config = (cr & !CR_TB) | ((self.config | cr) & CR_TB);
+                }
+            }
+            WRCR2 if self.expect(command, Some(4), 0) => {
+                if !self.start_write() {
+                    return true;
+                }
+                match command.address.unwrap_or(0) {
+                    CR2_MODE => self.cr2_mode = first & (CR2_SOPI | CR2_DOPI),
+                    CR2_DQS => self.cr2_dqs = first & CR2_DQS_MASK,
+                    CR2_DUMMY => self.cr2_dummy = first & CR2_DUMMY_MASK,
+                    _ => {}
+                }
+            }
+            PP4B if self.expect(command, Some(4), 0) => {
+                let address = command.address.unwrap_or(0);
+                if self.dtr() && (address % 2 == 1 || data.len() % 2 == 1) {
+                    return false;
+                }
+                self.program(address, data);
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Output a register value, in DTR mode every byte is repeated.
+    fn output_register(&self, value: &[u8], buffer: &mut [u8]) {
+        let repeat = if self.dtr() { 2 } else { 1 };
+        for (i, byte) in buffer.iter_mut().enumerate() {
+            *byte = value.get(i / repeat).copied().unwrap_or(0xFF);
+        }
+    }
+
+    fn output_array(&self, command: &Command, buffer: &mut [u8]) {
+        let start = command.address.unwrap_or(0) as usize;
+        for (i, byte) in buffer.iter_mut().enumerate() {
+            *byte = self.array[(start + i) % SIZE];
+        }
+        self.swap_dtr_bytes(buffer);
+    }
+
+    /// Models the XSPI's byte order in DTR mode, which only matches the chip's for the
+    /// Macronix memory type.
+    fn swap_dtr_bytes(&self, data: &mut [u8]) {
+        if self.dtr() && self.memory_type != MemoryType::Macronix {
+            for pair in data.chunks_exact_mut(2) {
+                pair.swap(0, 1);
+            }
+        }
+    }
+
+    /// Start a program, erase or register write. Returns whether the write enable latch was
+    /// set, the latch is cleared either way.
+    fn start_write(&mut self) -> bool {
+        let enabled = self.status & SR_WEL != 0;
+        self.status &= !SR_WEL;
+        if enabled {
+            self.security &= !(SCUR_P_FAIL | SCUR_E_FAIL);
+            self.busy_reads = self.busy_setting;
+        }
+        enabled
+    }
+
+    /// Whether the BP bits protect `addr`.
+    fn protected(&self, addr: u32) -> bool {
+        let level = (self.status & SR_BP_MASK) >> SR_BP_SHIFT;
+        if level == 0 {
+            return false;
+        }
+        let size = (BLOCK_SIZE << (level - 1)).min(SIZE);
+        let addr = addr as usize;
+        if self.config & CR_TB != 0 {
+            addr < size
+        } else {
+            addr >= SIZE - size
+        }
+    }
+
+    fn program(&mut self, address: u32, data: &[u8]) {
+        if !self.start_write() {
+            return;
+        }
+        if self.protected(address) {
+            self.security |= SCUR_P_FAIL;
+            return;
+        }
+        let mut data = data.to_vec();
+        self.swap_dtr_bytes(&mut data);
+        let address = address as usize % SIZE;
+        let page = address - address % PAGE_SIZE;
+        // Only the last page worth of data is programmed, wrapping around within the page.
+        let skip = data.len().saturating_sub(PAGE_SIZE);
+        for (i, byte) in data.iter().enumerate().skip(skip) {
+            let offset = (address - page + i) % PAGE_SIZE;
+            self.array[page + offset] &= byte;
+        }
+        self.operations.push(Operation::Program {
+            address: address as u32,
+            len: data.len(),
+        });
+    }
+
+    fn erase(&mut self, address: u32, size: usize) {
+        if !self.start_write() {
+            return;
+        }
+        let start = address as usize % SIZE / size * size;
+        if self.protected(start as u32) {
+            self.security |= SCUR_E_FAIL;
+            return;
+        }
+        self.array[start..start + size].fill(0xFF);
+        self.operations.push(Operation::Erase {
+            address: start as u32,
+            size,
+        });
+    }
+}
+
+/// SFDP tables of the chip: the basic flash parameter table and the xSPI profile 1.0 table.
+fn sfdp_tables() -> Vec<u8> {
+    const BFPT_POINTER: usize = 0x30;
+    const PROFILE_POINTER: usize = 0x80;
+
+    let mut bfpt = [0u32; 16];
+    // 4KB erase with 0x20, 4-byte addresses only.
+    bfpt[0] = 0x0004_20E5;
+    // 256 Mbit.
+    bfpt[1] = (SIZE * 8 - 1) as u32;
+    // Erase type 1: 4KB with 0x20, erase type 2: 64KB with 0xD8.
+    bfpt[7] = 0xD810_200C;
+    // 256 byte pages.
+    bfpt[10] = 8 << 4;
+
+    let mut profile = [0u32; 5];
+    // 20 dummy cycles at 200 MHz, register reads use 4 dummy cycles.
+    profile[3] = 20 << 7;
+
+    let mut sfdp = vec![0xFF; PROFILE_POINTER + 4 * profile.len()];
+    // Signature, revision 1.6, two parameter headers, unused.
+    sfdp[..8].copy_from_slice(&[b'S', b'F', b'D', b'P', 0x06, 0x01, 0x01, 0xFF]);
+    let headers = [
+        (0xFF00u16, bfpt.len(), BFPT_POINTER),
+        (0xFF05u16, profile.len(), PROFILE_POINTER),
+    ];
+    for (i, (id, dwords, pointer)) in headers.into_iter().enumerate() {
+        let header = &mut sfdp[8 + 8 * i..16 + 8 * i];
+        header[0] = id as u8;
+        header[1] = 0x00;
+        header[2] = 0x01;
+        header[3] = dwords as u8;
+        header[4..7].copy_from_slice(&(pointer as u32).to_le_bytes()[..3]);
+        header[7] = (id >> 8) as u8;
+    }
+    for (i, dword) in bfpt.iter().enumerate() {
+        let at = BFPT_POINTER + 4 * i;
+        sfdp[at..at + 4].copy_from_slice(&dword.to_le_bytes());
+    }
+    for (i, dword) in profile.iter().enumerate() {
+        let at = PROFILE_POINTER + 4 * i;
+        sfdp[at..at + 4].copy_from_slice(&dword.to_le_bytes());
+    }
+    sfdp
+}
@@ -0,0 +1,145 @@
+//! Program/erase suspend and resume.
+//!
+//! A long erase can be started with `start_erase_sector`/`start_erase_block_64k`, suspended to
+//! serve latency sensitive reads and resumed afterwards. While an operation is suspended the
+//! flash is only reachable through the [`Suspended`] guard, which only offers the commands the
+//! chip accepts in that state.
+
+use crate::polling::WriteOperation;
+use crate::{FlashError, FlashMemory, OpiFlashMemory, SpiFlashMemory, Transport};
+
+/// The kind of operation that was suspended, as reported by the chip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SuspendedOperation {
+    /// A page program (PSB set).
+    Program,
+    /// A sector, block or chip erase (ESB set).
+    Erase,
+}
+
+mod sealed {
+    use crate::FlashError;
+
+    pub trait Resume {
+        fn resume_operation(&mut self) -> Result<(), FlashError>;
+    }
+}
+
+/// Exclusive access to the flash while a program or erase operation is suspended.
+///
+/// Dropping the guard resumes the operation, [`Suspended::resume`] does the same but reports
+/// errors. After resuming, the operation must be waited for with `finish_erase` before issuing
+/// other commands. The chip needs some time to make progress after a resume, suspending again
+/// right away may keep the operation from ever finishing.
+pub struct Suspended<'a, F: sealed::Resume> {
+    flash: &'a mut F,
+    operation: SuspendedOperation,
+    resumed: bool,
+}
+
+impl<F: FlashMemory + sealed::Resume> Suspended<'_, F> {
+    /// The operation that is suspended.
+    pub fn operation(&self) -> SuspendedOperation {
+        self.operation
+    }
+
+    /// Read `buffer.len()` bytes starting at `addr`. Data read from the sector or block being
+    /// programmed or erased is undefined.
+    pub fn read_memory(&mut self, addr: u32, buffer: &mut [u8]) -> Result<(), FlashError> {
+        self.flash.read_memory(addr, buffer)
+    }
+
+    /// Read the Status Register.
+    pub fn read_sr(&mut self) -> Result<u8, FlashError> {
+        self.flash.read_sr()
+    }
+
+    /// Program `buffer` starting at `addr`. Only possible while an erase is suspended, and only
+    /// outside of the block being erased.
+    pub fn write_memory(&mut self, addr: u32, buffer: &[u8]) -> Result<(), FlashError> {
+        if self.operation != SuspendedOperation::Erase {
+            return Err(FlashError::WrongMode);
+        }
+        self.flash.write_memory(addr, buffer)
+    }
+
+    /// Resume the suspended operation.
+    pub fn resume(mut self) -> Result<(), FlashError> {
+        self.resumed = true;
+        self.flash.resume_operation()
+    }
+}
+
+impl<F: sealed::Resume> Drop for Suspended<'_, F> {
+    fn drop(&mut self) {
+        if !self.resumed {
+            let _ = self.flash.resume_operation();
+        }
+    }
+}
+
+macro_rules! impl_suspend {
+    ($t:ident) => {
+        impl<X: Transport> sealed::Resume for $t<X> {
+            fn resume_operation(&mut self) -> Result<(), FlashError> {
+                let suspend = self.chip.suspend.ok_or(FlashError::Unsupported)?;
+                self.exec_command(suspend.resume)
+            }
+        }
+
+        impl<X: Transport> $t<X> {
+            /// Start erasing the 4KB sector containing `addr` without waiting for the erase to
+            /// finish.
+            pub fn start_erase_sector(&mut self, addr: u32) -> Result<(), FlashError> {
+                self.start_erase(addr, self.chip.commands.sector_erase)
+            }
+
+            /// Start erasing the 64KB block containing `addr` without waiting for the erase to
+            /// finish.
+            pub fn start_erase_block_64k(&mut self, addr: u32) -> Result<(), FlashError> {
+                self.start_erase(addr, self.chip.commands.block_erase)
+            }
+
+            /// Whether a program or erase operation is in progress.
+            pub fn is_busy(&mut self) -> Result<bool, FlashError> {
+                Ok(self.read_status()?.write_in_progress())
+            }
+
+            /// Wait for a started (and possibly resumed) erase to finish and check that it
+            /// succeeded.
+            pub fn finish_erase(&mut self) -> Result<(), FlashError> {
+                self.finish_write(WriteOperation::BlockErase)
+            }
+
+            /// Suspend the program or erase operation in progress.
+            ///
+            /// Returns `None` if no operation was in progress, e.g. because it finished before
+            /// the suspend command arrived. Fails with [`FlashError::Unsupported`] if the chip
+            /// can't suspend operations.
+            pub fn suspend(&mut self) -> Result<Option<Suspended<'_, Self>>, FlashError> {
+                let suspend = self.chip.suspend.ok_or(FlashError::Unsupported)?;
+                self.exec_command(suspend.suspend)?;
+                // The chip clears WIP once the operation is suspended (tSUS).
+                self.wait_write_finish(WriteOperation::Register)?;
+
+                let status = self.read_fail_status()?;
+                let operation = if status & suspend.erase_suspended != 0 {
+                    SuspendedOperation::Erase
+                } else if status & suspend.program_suspended != 0 {
+                    SuspendedOperation::Program
+                } else {
+                    return Ok(None);
+                };
+                Ok(Some(Suspended {
+                    flash: self,
+                    operation,
+                    resumed: false,
+                }))
+            }
+        }
+    };
+}
+
+impl_suspend!(SpiFlashMemory);
+impl_suspend!(OpiFlashMemory);
@@ -0,0 +1,147 @@
+//! XSPI clock and dummy cycle timing profiles.
+//!
+//! A [`TimingProfile`] selects the XSPI kernel clock, the prescalers used in SPI and octal
+//! mode and the dummy cycles of octal reads. The kernel clock is configured with
+//! `TimingProfile::configure_rcc` before embassy is initialized (e.g. by
+//! `nucleo_h7s3l8::init_with_timing`), the rest by the driver: the prescaler is changed
+//! whenever the chip switches between SPI and octal mode, and the dummy cycles are programmed
+//! into the chip before switching to octal mode so that the chip and all read transfers
+//! (indirect and memory mapped) agree on them.
+//!
+//! Clock frequencies and the RCC setup are only available with the `stm32` feature.
+
+#[cfg(feature = "stm32")]
+use embassy_stm32::{
+    rcc::{self, Pll, PllDivSt, PllMul, PllPreDiv, PllSource, mux::Xspisel},
+    time::Hertz,
+};
+
+#[cfg(feature = "stm32")]
+use crate::XspiInstance;
+
+/// Frequency of the AHB clock set up by [`init`](crate::nucleo_h7s3l8::init). The PLL2
+/// settings also assume the board's 24 MHz HSE.
+#[cfg(feature = "stm32")]
+const HCLK_FREQUENCY: u32 = 300_000_000;
+
+/// Frequency of the PLL2 VCO: 24 MHz HSE / 3 * 100.
+#[cfg(feature = "stm32")]
+const PLL2_VCO_FREQUENCY: u32 = 800_000_000;
+
+/// Source of the XSPI kernel clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum KernelClock {
+    /// The AHB clock, 300 MHz.
+    Hclk,
+    /// The S output of PLL2, 800 MHz divided by `divider` (1 to 8).
+    Pll2S { divider: u8 },
+}
+
+#[cfg(feature = "stm32")]
+impl KernelClock {
+    /// Frequency of the kernel clock.
+    pub const fn frequency(&self) -> Hertz {
+        match self {
+            KernelClock::Hclk => Hertz(HCLK_FREQUENCY),
+            KernelClock::Pll2S { divider } => Hertz(PLL2_VCO_FREQUENCY / *divider as u32),
+        }
+    }
+}
+
+/// Clock and dummy cycle settings, see the [module docs](self).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TimingProfile {
+    pub kernel_clock: KernelClock,
+    /// Prescaler in SPI mode, the kernel clock is divided by `spi_prescaler + 1`. The chip
+    /// supports up to 133 MHz in SPI mode.
+    pub spi_prescaler: u8,
+    /// Prescaler in octal mode. The chip supports up to 200 MHz in octal mode.
+    pub opi_prescaler: u8,
+    /// Dummy cycles of octal reads. Rounded up to a count the chip supports.
+    pub opi_read_dummy_cycles: u8,
+}
+
+impl TimingProfile {
+    /// 75 MHz from the AHB clock in both modes. Doesn't need any extra clock setup.
+    pub const HCLK_75MHZ: Self = Self {
+        kernel_clock: KernelClock::Hclk,
+        spi_prescaler: 3,
+        opi_prescaler: 3,
+        opi_read_dummy_cycles: 10,
+    };
+
+    /// 114 MHz from PLL2 in both modes, the fastest PLL2 divider within the chip's 133 MHz SPI
+    /// limit. The dummy cycles are the chip's for 133 MHz.
+    pub const PLL2_114MHZ: Self = Self {
+        kernel_clock: KernelClock::Pll2S { divider: 7 },
+        spi_prescaler: 0,
+        opi_prescaler: 0,
+        opi_read_dummy_cycles: 14,
+    };
+
+    /// 200 MHz from PLL2 in octal mode, 100 MHz in SPI mode.
+    pub const PLL2_200MHZ: Self = Self {
+        kernel_clock: KernelClock::Pll2S { divider: 4 },
+        spi_prescaler: 1,
+        opi_prescaler: 0,
+        opi_read_dummy_cycles: 20,
+    };
+
+    /// Profile used by [`init`](crate::nucleo_h7s3l8::init) and the drivers' constructors.
+    pub const DEFAULT: Self = Self::HCLK_75MHZ;
+
+    /// Bus clock in SPI mode.
+    #[cfg(feature = "stm32")]
+    pub const fn spi_frequency(&self) -> Hertz {
+        Hertz(self.kernel_clock.frequency().0 / (self.spi_prescaler as u32 + 1))
+    }
+
+    /// Bus clock in octal mode.
+    #[cfg(feature = "stm32")]
+    pub const fn opi_frequency(&self) -> Hertz {
+        Hertz(self.kernel_clock.frequency().0 / (self.opi_prescaler as u32 + 1))
+    }
+
+    /// Select the kernel clock of the XSPI peripheral `T`, setting up PLL2 if it is used.
+    #[cfg(feature = "stm32")]
+    pub fn configure_rcc<T: XspiInstance>(&self, rcc: &mut rcc::Config) {
+        match self.kernel_clock {
+            KernelClock::Hclk => T::select_kernel_clock(&mut rcc.mux, Xspisel::HCLK5),
+            KernelClock::Pll2S { divider } => {
+                rcc.pll2 = Some(Pll {
+                    source: PllSource::HSE,
+                    prediv: PllPreDiv::DIV3,
+                    mul: PllMul::MUL100,
+                    divp: None,
+                    divq: None,
+                    divr: None,
+                    divs: Some(pll_div_st(divider)),
+                    divt: None,
+                });
+                T::select_kernel_clock(&mut rcc.mux, Xspisel::PLL2_S);
+            }
+        }
+    }
+}
+
+impl Default for TimingProfile {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+#[cfg(feature = "stm32")]
+fn pll_div_st(divider: u8) -> PllDivSt {
+    match divider {
+        ..=1 => PllDivSt::DIV1,
+        2 => PllDivSt::DIV2,
+        3 => PllDivSt::DIV3,
+        4 => PllDivSt::DIV4,
+        5 => PllDivSt::DIV5,
+        6 => PllDivSt::DIV6,
+        7 => PllDivSt::DIV7,
+        _ => PllDivSt::DIV8,
+    }
+}
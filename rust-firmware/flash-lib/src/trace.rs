@@ -0,0 +1,338 @@
+//! Transaction trace for bring-up, enabled with the `trace` feature.
+//!
+//! [`Traced`] wraps a [`Transport`] and records every transaction it executes in a [`Trace`]
+//! ring buffer holding the last [`TRACE_LEN`] entries: the [`TransferConfig`] (opcode, widths,
+//! address, dummy cycles), the data length and the first [`TRACE_DATA_LEN`] data bytes, how
+//! long it took and whether it failed. A driver on a traced transport, e.g. from
+//! [`new_flash_traced`](crate::nucleo_h7s3l8::new_flash_traced), exposes the trace with `trace`,
+//! and [`Trace::dump`] logs it over defmt when something goes wrong.
+//!
+//! On the host a trace, recorded in a test or rebuilt from a dump with [`Trace::push`], can be
+//! replayed against the simulated chip with [`Trace::replay`] to find the first transaction the
+//! chip doesn't accept.
+//!
+//! Only the blocking drivers can be traced.
+
+use core::cmp::min;
+
+use embassy_time::{Duration, Instant};
+
+use crate::transport::{AddressSize, MemoryType, TransferConfig, Transport};
+use crate::{FlashError, OpiFlashMemory, SpiFlashMemory};
+
+/// Number of transactions kept in a [`Trace`].
+pub const TRACE_LEN: usize = 64;
+
+/// Number of data bytes kept per transaction.
+pub const TRACE_DATA_LEN: usize = 8;
+
+/// What the transport did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TraceKind {
+    /// Transaction without a data phase.
+    Command,
+    /// Transaction reading data.
+    Read,
+    /// Transaction writing data.
+    Write,
+    /// Automatic polling of the last read until the bits of `mask` are clear.
+    Poll { mask: u8, addressed: bool },
+    /// Memory mapped mode entered with the transaction as read configuration.
+    Map,
+    /// Memory mapped mode left.
+    Unmap,
+}
+
+/// A recorded transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub kind: TraceKind,
+    pub transaction: TransferConfig,
+    /// Length of the data phase in bytes.
+    pub len: usize,
+    /// The first bytes of the data phase, see [`TraceEntry::data`].
+    pub data: [u8; TRACE_DATA_LEN],
+    pub duration: Duration,
+    /// Whether the transport returned an error.
+    pub failed: bool,
+}
+
+impl TraceEntry {
+    /// The opcode of the instruction, without the octal command extension.
+    pub fn opcode(&self) -> Option<u8> {
+        let instruction = self.transaction.instruction?;
+        Some(match self.transaction.isize {
+            AddressSize::_16bit => (instruction >> 8) as u8,
+            _ => instruction as u8,
+        })
+    }
+
+    /// The recorded data bytes.
+    pub fn data(&self) -> &[u8] {
+        &self.data[..min(self.len, TRACE_DATA_LEN)]
+    }
+}
+
+/// Ring buffer of the last [`TRACE_LEN`] transactions.
+#[derive(Clone)]
+pub struct Trace {
+    entries: [Option<TraceEntry>; TRACE_LEN],
+    /// Index the next entry is stored at.
+    next: usize,
+    /// Number of entries overwritten since the trace was cleared.
+    dropped: usize,
+}
+
+impl Trace {
+    pub const fn new() -> Self {
+        Self {
+            entries: [None; TRACE_LEN],
+            next: 0,
+            dropped: 0,
+        }
+    }
+
+    /// The recorded transactions, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &TraceEntry> {
+        let (newer, older) = self.entries.split_at(self.next);
+        older.iter().chain(newer).flatten()
+    }
+
+    /// Number of transactions that no longer fit into the trace.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Append `entry`, overwriting the oldest one if the trace is full.
+    pub fn push(&mut self, entry: TraceEntry) {
+        if self.entries[self.next].replace(entry).is_some() {
+            self.dropped += 1;
+        }
+        self.next = (self.next + 1) % TRACE_LEN;
+    }
+
+    /// Log the recorded transactions, oldest first.
+    #[cfg(feature = "defmt")]
+    pub fn dump(&self) {
+        defmt::info!(
+            "XSPI trace: {} transactions, {} dropped",
+            self.iter().count(),
+            self.dropped
+        );
+        for entry in self.iter() {
+            let t = &entry.transaction;
+            defmt::info!(
+                "{} op={} i={}/{} a={}/{}={} d={}/{} dummy={} len={} data={=[u8]:02x} {} us{}",
+                entry.kind,
+                entry.opcode(),
+                t.iwidth,
+                if t.idtr { "dtr" } else { "str" },
+                t.adwidth,
+                t.adsize,
+                t.address,
+                t.dwidth,
+                if t.ddtr { "dtr" } else { "str" },
+                t.dummy.cycles(),
+                entry.len,
+                entry.data(),
+                entry.duration.as_micros(),
+                if entry.failed { " FAILED" } else { "" },
+            );
+        }
+    }
+
+    /// Replay the recorded transactions against the simulated `chip`, starting from its
+    /// current state.
+    ///
+    /// Returns the index (in [`Trace::iter`] order) of the first transaction the chip ignores,
+    /// because it isn't encoded as the chip expects in its mode at that point. Writes are
+    /// replayed with the recorded data bytes, padded with 0xFF. The result is only meaningful if
+    /// the chip's state matches the real chip's when the first entry was recorded, e.g. if
+    /// nothing was dropped since power-up.
+    #[cfg(not(feature = "stm32"))]
+    pub fn replay(&self, chip: &mut crate::sim::SimulatedFlash) -> Option<usize> {
+        use std::vec;
+
+        for (i, entry) in self.iter().enumerate() {
+            let ignored = chip.ignored();
+            let t = entry.transaction;
+            // The simulated chip never fails a transfer, only polling can time out.
+            let result = match entry.kind {
+                TraceKind::Command => chip.command(&t),
+                TraceKind::Read => chip.read(&mut vec![0; entry.len], t),
+                TraceKind::Write => {
+                    let mut data = vec![0xFF; entry.len];
+                    data[..entry.data().len()].copy_from_slice(entry.data());
+                    chip.write(&data, t)
+                }
+                TraceKind::Poll { mask, addressed } => {
+                    chip.poll_until_clear(mask, addressed, Duration::MAX)
+                }
+                TraceKind::Map => chip.enable_memory_mapped(t, TransferConfig::default()),
+                TraceKind::Unmap => {
+                    chip.disable_memory_mapped();
+                    Ok(())
+                }
+            };
+            if chip.ignored() != ignored || result.is_err() {
+                return Some(i);
+            }
+        }
+        None
+    }
+}
+
+impl Default for Trace {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A transport recording its transactions, see the [module docs](self).
+pub struct Traced<X> {
+    inner: X,
+    trace: Trace,
+    last_read: TransferConfig,
+}
+
+impl<X: Transport> Traced<X> {
+    pub fn new(inner: X) -> Self {
+        Self {
+            inner,
+            trace: Trace::new(),
+            last_read: TransferConfig::default(),
+        }
+    }
+
+    pub fn trace(&self) -> &Trace {
+        &self.trace
+    }
+
+    pub fn trace_mut(&mut self) -> &mut Trace {
+        &mut self.trace
+    }
+
+    /// The wrapped transport.
+    pub fn inner(&self) -> &X {
+        &self.inner
+    }
+
+    fn record<T>(
+        &mut self,
+        kind: TraceKind,
+        transaction: TransferConfig,
+        data: &[u8],
+        start: Instant,
+        result: &Result<T, FlashError>,
+    ) {
+        let mut recorded = [0; TRACE_DATA_LEN];
+        let len = min(data.len(), TRACE_DATA_LEN);
+        recorded[..len].copy_from_slice(&data[..len]);
+        self.trace.push(TraceEntry {
+            kind,
+            transaction,
+            len: data.len(),
+            data: recorded,
+            duration: start.elapsed(),
+            failed: result.is_err(),
+        });
+    }
+}
+
+impl<X: Transport> Transport for Traced<X> {
+    fn command(&mut self, transaction: &TransferConfig) -> Result<(), FlashError> {
+        let start = Instant::now();
+        let result = self.inner.command(transaction);
+        self.record(TraceKind::Command, *transaction, &[], start, &result);
+        result
+    }
+
+    fn read(&mut self, buffer: &mut [u8], transaction: TransferConfig) -> Result<(), FlashError> {
+        let start = Instant::now();
+        let result = self.inner.read(buffer, transaction);
+        self.record(TraceKind::Read, transaction, buffer, start, &result);
+        self.last_read = transaction;
+        result
+    }
+
+    fn write(&mut self, buffer: &[u8], transaction: TransferConfig) -> Result<(), FlashError> {
+        let start = Instant::now();
+        let result = self.inner.write(buffer, transaction);
+        self.record(TraceKind::Write, transaction, buffer, start, &result);
+        result
+    }
+
+    fn poll_until_clear(
+        &mut self,
+        mask: u8,
+        addressed: bool,
+        timeout: Duration,
+    ) -> Result<(), FlashError> {
+        let start = Instant::now();
+        let result = self.inner.poll_until_clear(mask, addressed, timeout);
+        let kind = TraceKind::Poll { mask, addressed };
+        self.record(kind, self.last_read, &[], start, &result);
+        result
+    }
+
+    fn set_memory_type(&mut self, memory_type: MemoryType) {
+        self.inner.set_memory_type(memory_type);
+    }
+
+    fn set_device_size(&mut self, size: usize) {
+        self.inner.set_device_size(size);
+    }
+
+    fn set_prescaler(&mut self, prescaler: u8) {
+        self.inner.set_prescaler(prescaler);
+    }
+
+    fn set_dqs(&mut self, enabled: bool) {
+        self.inner.set_dqs(enabled);
+    }
+
+    fn enable_memory_mapped(
+        &mut self,
+        read: TransferConfig,
+        write: TransferConfig,
+    ) -> Result<(), FlashError> {
+        let start = Instant::now();
+        let result = self.inner.enable_memory_mapped(read, write);
+        self.record(TraceKind::Map, read, &[], start, &result);
+        result
+    }
+
+    fn disable_memory_mapped(&mut self) {
+        let start = Instant::now();
+        self.inner.disable_memory_mapped();
+        let transaction = TransferConfig::default();
+        self.record(TraceKind::Unmap, transaction, &[], start, &Ok(()));
+    }
+
+    fn mapped_ptr(&self) -> *const u8 {
+        self.inner.mapped_ptr()
+    }
+}
+
+macro_rules! impl_trace {
+    ($t:ident) => {
+        impl<X: Transport> $t<Traced<X>> {
+            /// The transactions recorded by the driver's transport.
+            pub fn trace(&self) -> &Trace {
+                self.transport.trace()
+            }
+
+            pub fn clear_trace(&mut self) {
+                self.transport.trace_mut().clear();
+            }
+        }
+    };
+}
+
+impl_trace!(SpiFlashMemory);
+impl_trace!(OpiFlashMemory);
@@ -0,0 +1,208 @@
+//! Transport between the drivers and the flash chip.
+//!
+//! The drivers describe every bus transaction with a [`TransferConfig`] and hand it to a
+//! [`Transport`]. On the STM32 that's the XSPI peripheral (see [`instance`](crate::instance)),
+//! on a host the simulated chip of [`sim`](crate::sim), so the drivers can be tested with
+//! `cargo test`.
+//!
+//! The transaction types mirror the ones of embassy's XSPI driver, so the drivers don't
+//! depend on the HAL.
+
+use embassy_time::Duration;
+
+use crate::FlashError;
+
+/// Transport of the drivers' default type parameter: blocking XSPI2.
+#[cfg(feature = "stm32")]
+pub type DefaultTransport = embassy_stm32::xspi::Xspi<
+    'static,
+    embassy_stm32::peripherals::XSPI2,
+    embassy_stm32::mode::Blocking,
+>;
+
+/// Transport of the drivers' default type parameter: the simulated chip.
+#[cfg(not(feature = "stm32"))]
+pub type DefaultTransport = crate::sim::SimulatedFlash;
+
+/// Number of lines used by a phase of a transaction, `NONE` skips the phase.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum XspiWidth {
+    #[default]
+    NONE,
+    SING,
+    DUAL,
+    QUAD,
+    OCTO,
+}
+
+/// Size of the instruction or address phase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AddressSize {
+    #[default]
+    _8bit,
+    _16bit,
+    _24bit,
+    _32bit,
+}
+
+impl AddressSize {
+    /// Number of bytes.
+    pub const fn bytes(self) -> usize {
+        match self {
+            AddressSize::_8bit => 1,
+            AddressSize::_16bit => 2,
+            AddressSize::_24bit => 3,
+            AddressSize::_32bit => 4,
+        }
+    }
+}
+
+/// Number of dummy cycles between the address and the data phase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum DummyCycles {
+    #[default]
+    _0,
+    _1,
+    _2,
+    _3,
+    _4,
+    _5,
+    _6,
+    _7,
+    _8,
+    _9,
+    _10,
+    _11,
+    _12,
+    _13,
+    _14,
+    _15,
+    _16,
+    _17,
+    _18,
+    _19,
+    _20,
+    _21,
+    _22,
+    _23,
+    _24,
+    _25,
+    _26,
+    _27,
+    _28,
+    _29,
+    _30,
+    _31,
+}
+
+impl DummyCycles {
+    /// Number of cycles.
+    pub const fn cycles(self) -> u8 {
+        self as u8
+    }
+}
+
+/// Memory type of the XSPI peripheral, which selects the byte order in DTR mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MemoryType {
+    Micron,
+    Macronix,
+    Standard,
+}
+
+/// A single bus transaction: instruction, address, alternate bytes, dummy cycles and data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TransferConfig {
+    pub iwidth: XspiWidth,
+    pub instruction: Option<u32>,
+    pub isize: AddressSize,
+    pub idtr: bool,
+    pub adwidth: XspiWidth,
+    pub address: Option<u32>,
+    pub adsize: AddressSize,
+    pub addtr: bool,
+    pub abwidth: XspiWidth,
+    pub alternate_bytes: Option<u32>,
+    pub absize: AddressSize,
+    pub abdtr: bool,
+    pub dwidth: XspiWidth,
+    pub ddtr: bool,
+    pub dummy: DummyCycles,
+}
+
+impl Default for TransferConfig {
+    fn default() -> Self {
+        Self {
+            iwidth: XspiWidth::NONE,
+            instruction: None,
+            isize: AddressSize::_8bit,
+            idtr: false,
+            adwidth: XspiWidth::NONE,
+            address: None,
+            adsize: AddressSize::_8bit,
+            addtr: false,
+            abwidth: XspiWidth::NONE,
+            alternate_bytes: None,
+            absize: AddressSize::_8bit,
+            abdtr: false,
+            dwidth: XspiWidth::NONE,
+            ddtr: false,
+            dummy: DummyCycles::_0,
+        }
+    }
+}
+
+/// Executes the drivers' transactions, see the [module docs](self).
+pub trait Transport {
+    /// Transaction without a data phase.
+    fn command(&mut self, transaction: &TransferConfig) -> Result<(), FlashError>;
+
+    /// Transaction reading `buffer.len()` bytes.
+    fn read(&mut self, buffer: &mut [u8], transaction: TransferConfig) -> Result<(), FlashError>;
+
+    /// Transaction writing `buffer`.
+    fn write(&mut self, buffer: &[u8], transaction: TransferConfig) -> Result<(), FlashError>;
+
+    /// Repeat the last read until the bits of `mask` in its first byte are clear.
+    ///
+    /// `addressed` tells whether the read has an address phase. Fails with
+    /// [`FlashError::Timeout`] if the bits don't clear within `timeout`.
+    fn poll_until_clear(
+        &mut self,
+        mask: u8,
+        addressed: bool,
+        timeout: Duration,
+    ) -> Result<(), FlashError>;
+
+    /// Select the byte order of DTR transfers.
+    fn set_memory_type(&mut self, memory_type: MemoryType);
+
+    /// Set the size of the flash in bytes, which bounds memory mapped accesses.
+    fn set_device_size(&mut self, size: usize);
+
+    /// Set the prescaler dividing the kernel clock down to the bus clock.
+    fn set_prescaler(&mut self, prescaler: u8);
+
+    /// Enable or disable sampling read data with the chip's DQS strobe.
+    fn set_dqs(&mut self, enabled: bool);
+
+    /// Map the flash, using `read` and `write` for accesses to the mapped region.
+    fn enable_memory_mapped(
+        &mut self,
+        read: TransferConfig,
+        write: TransferConfig,
+    ) -> Result<(), FlashError>;
+
+    /// Return to indirect transactions.
+    fn disable_memory_mapped(&mut self);
+
+    /// Start of the mapped flash.
+    fn mapped_ptr(&self) -> *const u8;
+}
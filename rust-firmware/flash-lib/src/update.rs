@@ -0,0 +1,73 @@
+//! Read-modify-write updates.
+//!
+//! `update` rewrites bytes anywhere in the flash while preserving the rest of the affected
+//! sectors: each sector is read into a caller supplied scratch buffer, the new bytes are
+//! merged in, and the sector is erased and programmed again. Sectors are erased even if only
+//! bits need to be cleared, as programming a page twice breaks the ECC of chips like the
+//! IS25WX256. Unchanged sectors aren't touched at all. Every rewritten sector is read back and
+//! compared afterwards.
+
+use core::cmp::min;
+
+use crate::{FlashError, OpiFlashMemory, SpiFlashMemory, Transport};
+
+/// Number of bytes read at once when verifying a sector.
+const VERIFY_CHUNK: usize = 256;
+
+macro_rules! impl_update {
+    ($t:ident) => {
+        impl<X: Transport> $t<X> {
+            /// Write `data` starting at `addr`, preserving the other bytes of the affected
+            /// sectors, see the [module docs](crate::update).
+            ///
+            /// `scratch` must hold at least one sector.
+            pub fn update(
+                &mut self,
+                addr: u32,
+                data: &[u8],
+                scratch: &mut [u8],
+            ) -> Result<(), FlashError> {
+                let sector_size = self.geometry.sector_size;
+                self.geometry.check_bounds(addr, data.len())?;
+                let sector = scratch
+                    .get_mut(..sector_size)
+                    .ok_or(FlashError::BufferTooSmall)?;
+
+                let mut written = 0;
+                while written < data.len() {
+                    let target = addr + written as u32;
+                    let sector_start = target - target % sector_size as u32;
+                    let offset = (target - sector_start) as usize;
+                    let len = min(sector_size - offset, data.len() - written);
+                    let new = &data[written..written + len];
+
+                    self.read_memory(sector_start, sector)?;
+                    if sector[offset..offset + len] != *new {
+                        sector[offset..offset + len].copy_from_slice(new);
+                        self.erase_sector(sector_start)?;
+                        self.write_memory(sector_start, sector)?;
+                        self.verify(sector_start, sector)?;
+                    }
+                    written += len;
+                }
+                Ok(())
+            }
+
+            /// Check that the flash at `addr` contains `expected`.
+            fn verify(&mut self, addr: u32, expected: &[u8]) -> Result<(), FlashError> {
+                let mut buffer = [0; VERIFY_CHUNK];
+                for (i, chunk) in expected.chunks(VERIFY_CHUNK).enumerate() {
+                    let buffer = &mut buffer[..chunk.len()];
+                    self.read_memory(addr + (i * VERIFY_CHUNK) as u32, buffer)?;
+                    if buffer != chunk {
+                        return Err(FlashError::VerifyFailed);
+                    }
+                }
+                Ok(())
+            }
+        }
+    };
+}
+
+impl_update!(SpiFlashMemory);
+impl_update!(OpiFlashMemory);
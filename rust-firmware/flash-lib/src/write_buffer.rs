@@ -0,0 +1,150 @@
+//! Interruptible write-to-buffer programming (WRBI/WRCT/WRCF).
+//!
+//! Instead of a single page program, the data of one page is collected in the chip's page
+//! buffer over several commands and only programmed to the array once the write is committed.
+//! Until then the write can be aborted with WRDI, which leaves the array untouched. This allows
+//! building up a page from packets that arrive one at a time.
+
+use crate::chip::WriteBuffer;
+use crate::polling::WriteOperation;
+use crate::{FlashError, OpiFlashMemory, SpiFlashMemory, Transport};
+
+mod sealed {
+    use crate::FlashError;
+
+    pub trait BufferCommands {
+        fn buffer_command(&mut self, cmd: u8, addr: u32, data: &[u8]) -> Result<(), FlashError>;
+        fn confirm_buffer(&mut self, cmd: u8) -> Result<(), FlashError>;
+        fn abort_buffer(&mut self) -> Result<(), FlashError>;
+    }
+}
+
+/// A page write collected in the chip's page buffer, see the [module docs](self).
+///
+/// All data must lie within the page the write was started in. Dropping the guard without
+/// calling [`BufferedWrite::commit`] aborts the write.
+pub struct BufferedWrite<'a, F: sealed::BufferCommands> {
+    flash: &'a mut F,
+    commands: WriteBuffer,
+    page: u32,
+    page_size: usize,
+    done: bool,
+}
+
+impl<F: sealed::BufferCommands> BufferedWrite<'_, F> {
+    /// Start address of the page being written.
+    pub fn page(&self) -> u32 {
+        self.page
+    }
+
+    /// Add `data` at `addr` to the page buffer (WRCT).
+    pub fn write(&mut self, addr: u32, data: &[u8]) -> Result<(), FlashError> {
+        check_in_page(self.page, self.page_size, addr, data.len())?;
+        self.flash.buffer_command(self.commands.append, addr, data)
+    }
+
+    /// Program the page buffer to the array (WRCF) and wait for the program to finish.
+    pub fn commit(mut self) -> Result<(), FlashError> {
+        self.done = true;
+        self.flash.confirm_buffer(self.commands.confirm)
+    }
+
+    /// Discard the page buffer without programming it (WRDI).
+    pub fn abort(mut self) -> Result<(), FlashError> {
+        self.done = true;
+        self.flash.abort_buffer()
+    }
+}
+
+impl<F: sealed::BufferCommands> Drop for BufferedWrite<'_, F> {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = self.flash.abort_buffer();
+        }
+    }
+}
+
+/// Checks that `len` bytes at `addr` lie within the page starting at `page`.
+fn check_in_page(page: u32, page_size: usize, addr: u32, len: usize) -> Result<(), FlashError> {
+    if addr < page || (addr - page) as usize + len > page_size {
+        return Err(FlashError::OutOfBounds);
+    }
+    Ok(())
+}
+
+impl<X: Transport> sealed::BufferCommands for SpiFlashMemory<X> {
+    fn buffer_command(&mut self, cmd: u8, addr: u32, data: &[u8]) -> Result<(), FlashError> {
+        let transaction = self.write_data_transaction(cmd, addr);
+        self.write(data, transaction)
+    }
+
+    fn confirm_buffer(&mut self, cmd: u8) -> Result<(), FlashError> {
+        self.exec_command(cmd)?;
+        self.finish_write(WriteOperation::Program)
+    }
+
+    fn abort_buffer(&mut self) -> Result<(), FlashError> {
+        self.exec_command(self.chip.commands.write_disable)
+    }
+}
+
+impl<X: Transport> sealed::BufferCommands for OpiFlashMemory<X> {
+    fn buffer_command(&mut self, cmd: u8, addr: u32, data: &[u8]) -> Result<(), FlashError> {
+        // Like page programs, DTR buffer writes are made of whole 2-byte words.
+        if self.dtr && (addr % 2 == 1 || data.len() % 2 == 1) {
+            return Err(FlashError::NotAligned);
+        }
+        let transaction = self.write_data_transaction(cmd, addr);
+        self.write(data, transaction)
+    }
+
+    fn confirm_buffer(&mut self, cmd: u8) -> Result<(), FlashError> {
+        self.exec_command(cmd)?;
+        self.finish_write(WriteOperation::Program)
+    }
+
+    fn abort_buffer(&mut self) -> Result<(), FlashError> {
+        self.exec_command(self.chip.commands.write_disable)
+    }
+}
+
+macro_rules! impl_write_buffer {
+    ($t:ident) => {
+        impl<X: Transport> $t<X> {
+            /// Start a buffered write of the page containing `addr`, with `data` at `addr` as
+            /// the initial buffer contents (WRBI).
+            ///
+            /// Fails with [`FlashError::Unsupported`] if the chip has no write-to-buffer
+            /// commands.
+            pub fn begin_buffered_write(
+                &mut self,
+                addr: u32,
+                data: &[u8],
+            ) -> Result<BufferedWrite<'_, Self>, FlashError> {
+                let commands = self.chip.write_buffer.ok_or(FlashError::Unsupported)?;
+                let page_size = self.geometry.page_size;
+                let page = addr - addr % page_size as u32;
+                self.geometry.check_bounds(addr, data.len())?;
+                check_in_page(page, page_size, addr, data.len())?;
+
+                self.enable_write()?;
+                let result =
+                    sealed::BufferCommands::buffer_command(self, commands.initial, addr, data);
+                if let Err(e) = result {
+                    let _ = sealed::BufferCommands::abort_buffer(self);
+                    return Err(e);
+                }
+                Ok(BufferedWrite {
+                    flash: self,
+                    commands,
+                    page,
+                    page_size,
+                    done: false,
+                })
+            }
+        }
+    };
+}
+
+impl_write_buffer!(SpiFlashMemory);
+impl_write_buffer!(OpiFlashMemory);
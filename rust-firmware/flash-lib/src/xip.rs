@@ -0,0 +1,266 @@
+//! Flash writes from an application executing in place.
+//!
+//! An application running from the memory mapped flash, like the firmware the bootloader starts
+//! at [`MEMORY_MAPPED_FLASH_ADDRESS`](crate::MEMORY_MAPPED_FLASH_ADDRESS), can't use the
+//! drivers' indirect commands: leaving memory mapped mode pulls the code out from under the CPU.
+//! [`XipFlash`] owns an octal driver whose flash stays mapped, and runs every erase and program
+//! in a critical section, so no interrupt handler executes from the flash meanwhile. The flash
+//! is unmapped for the operation and mapped again before the critical section ends, also if the
+//! operation failed, and the caches are invalidated for the changed range, so code and data
+//! fetched afterwards see the new contents.
+//!
+//! Everything executed or read while the flash is unmapped must be in RAM. The linker script
+//! fragment `xip.x` in the crate root places the code and statics of this crate, the parts of
+//! embassy it calls and the compiler intrinsics in the ITCM, and the drivers keep their own copy
+//! of the [`Chip`](crate::Chip) entry. Most code is matched by its mangled name, which needs
+//! legacy symbol mangling. An application executing in place includes `xip.x` from its
+//! `memory.x` after defining the `ITCM` region, builds with `-C symbol-mangling-version=legacy`,
+//! and calls [`load_ram_code`] first thing in `main`, before any of that code runs. The time
+//! driver isn't needed: it doesn't advance with interrupts masked, so the drivers' timeouts count
+//! core cycles instead, and a `Traced` transport, which reads it, can't be used here.
+//!
+//! `check_xip.py` in the crate root follows the calls of the code running while the flash is
+//! unmapped in a linked image, and fails for any code or constant it reaches outside the ITCM,
+//! which then has to be added to `xip.x`. It is part of the cargo runner of `flash_test`, which
+//! in turn makes the internal flash it runs from inaccessible while the XSPI flash is unmapped,
+//! so anything the check misses faults there.
+//!
+//! Data to be written must not be in the mapped flash itself, e.g. a constant, as it can't be
+//! read while the flash is unmapped. Such writes fail with [`FlashError::WrongMode`], the data
+//! has to be copied to RAM first.
+
+use core::slice;
+
+use crate::{DefaultTransport, FlashError, FlashGeometry, OpiFlashMemory, Transport};
+
+/// A flash the application executes from, see the [module docs](self).
+pub struct XipFlash<X: Transport = DefaultTransport> {
+    flash: OpiFlashMemory<X>,
+}
+
+impl<X: Transport> XipFlash<X> {
+    /// Map `flash` for good and take it over.
+    pub fn new(mut flash: OpiFlashMemory<X>) -> Result<Self, FlashError> {
+        // SAFETY: the slice is dropped right away, the service hands out its own borrowing it.
+        unsafe { flash.enable_mm()?.leak() };
+        Ok(Self { flash })
+    }
+
+    /// Create the service from the driver returned by `init`, which runs in a critical section
+    /// like the service's operations, so it can set up the XSPI peripheral the application
+    /// executes from again.
+    ///
+    /// `init` must only run code in the ITCM, like the one of
+    /// [`new_xip_flash`](crate::nucleo_h7s3l8::new_xip_flash). The application can't continue if
+    /// it fails, as the flash is left unmapped.
+    #[inline(never)]
+    pub fn take_over(
+        init: impl FnOnce() -> Result<OpiFlashMemory<X>, FlashError>,
+    ) -> Result<Self, FlashError> {
+        critical_section::with(|_| Self::take_over_in_ram(init))
+    }
+
+    /// The part of [`take_over`](Self::take_over) running while the flash may be unmapped.
+    #[inline(never)]
+    #[cfg_attr(
+        feature = "stm32",
+        unsafe(link_section = ".itcm_text.flash_lib_xip_take_over")
+    )]
+    fn take_over_in_ram(
+        init: impl FnOnce() -> Result<OpiFlashMemory<X>, FlashError>,
+    ) -> Result<Self, FlashError> {
+        Self::new(init()?)
+    }
+
+    /// Sizes and timings of the flash.
+    pub fn geometry(&self) -> FlashGeometry {
+        self.flash.geometry
+    }
+
+    /// The whole flash.
+    pub fn as_slice(&self) -> &[u8] {
+        // SAFETY: the flash is mapped whenever the service isn't borrowed mutably, and only
+        // changes while it is.
+        unsafe {
+            slice::from_raw_parts(self.flash.transport.mapped_ptr(), self.flash.geometry.size)
+        }
+    }
+
+    /// `len` bytes of the flash starting at `addr`.
+    pub fn read(&self, addr: u32, len: usize) -> Result<&[u8], FlashError> {
+        let start = addr as usize;
+        match start.checked_add(len) {
+            Some(end) if end <= self.flash.geometry.size => Ok(&self.as_slice()[start..end]),
+            _ => Err(FlashError::OutOfBounds),
+        }
+    }
+
+    /// Erase the sector containing `addr`.
+    #[inline(never)]
+    pub fn erase_sector(&mut self, addr: u32) -> Result<(), FlashError> {
+        let sector_size = self.flash.geometry.sector_size;
+        let start = addr - addr % sector_size as u32;
+        self.unmapped(start, sector_size, |flash| flash.erase_sector(addr))
+    }
+
+    /// Erase `len` bytes starting at `start`, both must be sector aligned.
+    #[inline(never)]
+    pub fn erase_range(&mut self, start: u32, len: usize) -> Result<(), FlashError> {
+        self.unmapped(start, len, |flash| flash.erase_range(start, len))
+    }
+
+    /// Program `data` starting at `addr`, which must have been erased.
+    #[inline(never)]
+    pub fn write_memory(&mut self, addr: u32, data: &[u8]) -> Result<(), FlashError> {
+        self.check_outside(data)?;
+        self.unmapped(addr, data.len(), |flash| flash.write_memory(addr, data))
+    }
+
+    /// Write `data` starting at `addr`, preserving the other bytes of the affected sectors, see
+    /// [`update`](crate::update). `scratch` must hold at least one sector.
+    #[inline(never)]
+    pub fn update(&mut self, addr: u32, data: &[u8], scratch: &mut [u8]) -> Result<(), FlashError> {
+        self.check_outside(data)?;
+        self.unmapped(addr, data.len(), |flash| flash.update(addr, data, scratch))
+    }
+
+    /// Fail if `data` is in the mapped flash.
+    fn check_outside(&self, data: &[u8]) -> Result<(), FlashError> {
+        let flash = self.as_slice().as_ptr_range();
+        let data = data.as_ptr_range();
+        if data.start < flash.end && flash.start < data.end {
+            return Err(FlashError::WrongMode);
+        }
+        Ok(())
+    }
+
+    /// Run `f` on the unmapped flash and invalidate the caches for the `len` bytes at `addr`
+    /// afterwards.
+    #[inline(never)]
+    fn unmapped<R>(
+        &mut self,
+        addr: u32,
+        len: usize,
+        f: impl FnOnce(&mut OpiFlashMemory<X>) -> Result<R, FlashError>,
+    ) -> Result<R, FlashError> {
+        critical_section::with(|_| self.unmapped_in_ram(addr, len, f))
+    }
+
+    /// The part of [`unmapped`](Self::unmapped) running from the ITCM, the root of everything
+    /// `check_xip.py` checks.
+    #[inline(never)]
+    #[cfg_attr(
+        feature = "stm32",
+        unsafe(link_section = ".itcm_text.flash_lib_xip_unmapped")
+    )]
+    fn unmapped_in_ram<R>(
+        &mut self,
+        addr: u32,
+        len: usize,
+        f: impl FnOnce(&mut OpiFlashMemory<X>) -> Result<R, FlashError>,
+    ) -> Result<R, FlashError> {
+        self.flash.unmap();
+        #[cfg(feature = "flash-test")]
+        guard_internal_flash(true);
+        let result = f(&mut self.flash);
+        #[cfg(feature = "flash-test")]
+        guard_internal_flash(false);
+        // The application continues from the flash after the critical section, whether `f`
+        // succeeded or not.
+        let mapped = self.flash.map();
+        invalidate_caches(
+            self.flash.transport.mapped_ptr() as usize + addr as usize,
+            len,
+        );
+        mapped.and(result)
+    }
+}
+
+/// Drop cached copies of the `len` bytes at `address`, and the whole instruction cache.
+#[cfg(feature = "stm32")]
+fn invalidate_caches(address: usize, len: usize) {
+    use cortex_m::peripheral::CBP;
+
+    /// Size of a cache line of the Cortex-M7.
+    const LINE_SIZE: usize = 32;
+
+    // SAFETY: the cache maintenance registers are write-only and have no side effects besides
+    // invalidating. The mapped flash is never written through the data cache, so no dirty lines
+    // are dropped. The barriers are inline assembly like in `guard_internal_flash`.
+    unsafe {
+        let cbp = &*CBP::PTR;
+        core::arch::asm!("dsb");
+        let mut line = address & !(LINE_SIZE - 1);
+        while line < address + len {
+            cbp.dcimvac.write(line as u32);
+            line += LINE_SIZE;
+        }
+        cbp.iciallu.write(0);
+        core::arch::asm!("dsb", "isb");
+    }
+}
+
+/// The host has no caches to maintain.
+#[cfg(not(feature = "stm32"))]
+fn invalidate_caches(_address: usize, _len: usize) {}
+
+/// Make the internal flash holding `flash_test` inaccessible while `enable`d, like the unmapped
+/// XSPI flash is for an application executing in place, see the [module docs](self).
+#[cfg(feature = "flash-test")]
+#[inline(always)]
+fn guard_internal_flash(enable: bool) {
+    use cortex_m::peripheral::MPU;
+
+    /// The 64KB internal flash of the STM32H7S3.
+    const INTERNAL_FLASH: u32 = 0x0800_0000;
+    /// Region attributes: execute never, no access, 64KB, enabled.
+    const RASR: u32 = 1 << 28 | 15 << 1 | 1;
+    /// Default memory map for the rest, MPU enabled.
+    const CTRL: u32 = 1 << 2 | 1;
+
+    // SAFETY: the test program doesn't use the MPU otherwise. The barriers are inline assembly,
+    // as a call to `cortex_m::asm` could end up in the internal flash.
+    unsafe {
+        let mpu = &*MPU::PTR;
+        core::arch::asm!("dsb");
+        if enable {
+            mpu.rnr.write(0);
+            mpu.rbar.write(INTERNAL_FLASH);
+            mpu.rasr.write(RASR);
+            mpu.ctrl.write(CTRL);
+        } else {
+            mpu.ctrl.write(0);
+        }
+        core::arch::asm!("dsb", "isb");
+    }
+}
+
+/// Copy the code `xip.x` places in the ITCM there, see the [module docs](self).
+///
+/// # Safety
+///
+/// Must be called before any of the code in the ITCM runs, which includes the critical section
+/// implementation, and not while it does.
+#[cfg(feature = "stm32")]
+#[inline(never)]
+#[unsafe(link_section = ".text.flash_lib_load_ram_code")]
+pub unsafe fn load_ram_code() {
+    unsafe extern "C" {
+        static mut __sitcm_text: u32;
+        static mut __eitcm_text: u32;
+        static __siitcm_text: u32;
+    }
+
+    // Copied word by word, as the memory routines are part of the code being copied.
+    let mut dst = &raw mut __sitcm_text;
+    let end = &raw mut __eitcm_text;
+    let mut src = &raw const __siitcm_text;
+    while dst < end {
+        // SAFETY: `xip.x` defines the section bounds and its load address, both word aligned.
+        unsafe {
+            dst.write_volatile(src.read_volatile());
+            dst = dst.add(1);
+            src = src.add(1);
+        }
+    }
+}
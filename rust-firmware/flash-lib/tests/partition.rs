@@ -0,0 +1,65 @@
+//! Accesses through the partition table, run with `cargo test-host`.
+
+#![cfg(not(feature = "stm32"))]
+
+use flash_lib::partition::{DATA, FIRMWARE, LOGS, TABLE};
+use flash_lib::sim::SimulatedFlash;
+use flash_lib::xip::XipFlash;
+use flash_lib::{FlashError, SECTOR_SIZE, SpiFlashMemory};
+
+#[test]
+fn table_covers_the_flash_in_order() {
+    assert_eq!(TABLE.first(), Some(&FIRMWARE));
+    assert_eq!(TABLE.last().unwrap().end() as usize, flash_lib::MEMORY_SIZE);
+    assert!(TABLE.windows(2).all(|pair| pair[0].end() <= pair[1].offset));
+}
+
+#[test]
+fn offsets_are_relative_to_the_partition() {
+    let chip = SimulatedFlash::new();
+    let mut flash = SpiFlashMemory::with_transport(chip.clone()).unwrap();
+    DATA.erase(&mut flash, 0, SECTOR_SIZE).unwrap();
+    DATA.write(&mut flash, 4, b"data").unwrap();
+
+    let mut stored = [0; 8];
+    DATA.read(&mut flash, 0, &mut stored).unwrap();
+    assert_eq!(&stored, b"\xFF\xFF\xFF\xFFdata");
+    assert_eq!(chip.contents(DATA.offset + 4, 4), b"data");
+}
+
+#[test]
+fn accesses_beyond_the_partition_are_rejected() {
+    let chip = SimulatedFlash::new();
+    let mut flash = SpiFlashMemory::with_transport(chip.clone()).unwrap();
+    chip.take_operations();
+
+    let last = (DATA.size - SECTOR_SIZE) as u32;
+    assert_eq!(
+        DATA.erase(&mut flash, last, 2 * SECTOR_SIZE),
+        Err(FlashError::OutOfBounds)
+    );
+    assert_eq!(
+        DATA.write(&mut flash, last + SECTOR_SIZE as u32 - 2, b"abc"),
+        Err(FlashError::OutOfBounds)
+    );
+    assert_eq!(
+        DATA.read(&mut flash, u32::MAX, &mut [0; 2]),
+        Err(FlashError::OutOfBounds)
+    );
+    assert!(chip.take_operations().is_empty());
+
+    // The last bytes are still in bounds.
+    DATA.write(&mut flash, last + SECTOR_SIZE as u32 - 2, b"ab")
+        .unwrap();
+}
+
+#[test]
+fn partitions_are_written_through_the_xip_service() {
+    let mut flash = XipFlash::take_over(|| {
+        Ok(SpiFlashMemory::with_transport(SimulatedFlash::new())?.into_octo()?)
+    })
+    .unwrap();
+    LOGS.erase(&mut flash, 0, SECTOR_SIZE).unwrap();
+    LOGS.write(&mut flash, 0, b"log").unwrap();
+    assert_eq!(flash.read(LOGS.offset, 3).unwrap(), b"log");
+}
@@ -0,0 +1,79 @@
+//! Writes checked against the partition table, run with `cargo test-host`.
+
+#![cfg(not(feature = "stm32"))]
+
+use embedded_storage::nor_flash::NorFlash;
+use flash_lib::partition::{BOOTLOADER_CONFIG, DATA, FIRMWARE, LOGS, MODEL};
+use flash_lib::policy::{BootloaderAccess, ChipErase, ProtectedFlash};
+use flash_lib::sim::SimulatedFlash;
+use flash_lib::{FlashError, SECTOR_SIZE, SpiFlashMemory};
+
+fn protected() -> (ProtectedFlash<SpiFlashMemory>, SimulatedFlash) {
+    let chip = SimulatedFlash::new();
+    let flash = SpiFlashMemory::with_transport(chip.clone()).unwrap();
+    chip.take_operations();
+    (ProtectedFlash::new(flash), chip)
+}
+
+#[test]
+fn writable_partitions_are_written() {
+    let (mut flash, chip) = protected();
+    DATA.erase(&mut flash, 0, SECTOR_SIZE).unwrap();
+    DATA.write(&mut flash, 0, b"data").unwrap();
+    // A range may span neighbouring writable partitions.
+    flash.write(LOGS.offset - 2, b"span").unwrap();
+    assert_eq!(chip.contents(LOGS.offset - 2, 4), b"span");
+}
+
+#[test]
+fn violations_are_rejected_before_reaching_the_chip() {
+    let (mut flash, chip) = protected();
+    assert_eq!(
+        FIRMWARE.erase(&mut flash, 0, SECTOR_SIZE),
+        Err(FlashError::PolicyViolation)
+    );
+    assert_eq!(
+        MODEL.write(&mut flash, 0, b"model"),
+        Err(FlashError::PolicyViolation)
+    );
+    assert_eq!(
+        flash.write(DATA.offset - 1, b"ab"),
+        Err(FlashError::PolicyViolation)
+    );
+    assert!(chip.take_operations().is_empty());
+    assert_eq!(chip.ignored(), 0);
+}
+
+#[test]
+fn bootloader_partitions_need_the_token() {
+    let (mut flash, chip) = protected();
+    let token = BootloaderAccess::take().unwrap();
+    assert!(BootloaderAccess::take().is_none());
+    {
+        let mut boot = flash.bootloader_access(&token);
+        BOOTLOADER_CONFIG.write(&mut boot, 0, b"slot").unwrap();
+        FIRMWARE.write(&mut boot, 0, b"app").unwrap();
+        // The model stays read-only.
+        assert_eq!(
+            MODEL.write(&mut boot, 0, b"model"),
+            Err(FlashError::PolicyViolation)
+        );
+    }
+    assert_eq!(chip.contents(BOOTLOADER_CONFIG.offset, 4), b"slot");
+
+    // Ends with the borrow.
+    assert_eq!(
+        BOOTLOADER_CONFIG.write(&mut flash, 4, b"slot"),
+        Err(FlashError::PolicyViolation)
+    );
+}
+
+#[test]
+fn chip_erase_needs_the_token() {
+    let (mut flash, chip) = protected();
+    chip.load(FIRMWARE.offset, b"app");
+    let token = ChipErase::take().unwrap();
+    assert!(ChipErase::take().is_none());
+    flash.erase_chip(&token).unwrap();
+    assert_eq!(chip.contents(FIRMWARE.offset, 3), [0xFF; 3]);
+}
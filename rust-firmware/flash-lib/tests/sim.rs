@@ -0,0 +1,361 @@
+//! Driver tests against the simulated chip, run on the host with `cargo test-host`.
+
+#![cfg(not(feature = "stm32"))]
+
+use flash_lib::chip::Timeouts;
+use flash_lib::registers::{Cr2Dqs, Cr2DummyCycles};
+use flash_lib::sim::{BusMode, Operation, SimulatedFlash};
+use flash_lib::{FlashError, FlashGeometry, OpiFlashMemory, SpiFlashMemory, TimingProfile};
+
+fn spi() -> (SpiFlashMemory, SimulatedFlash) {
+    let chip = SimulatedFlash::new();
+    let flash = SpiFlashMemory::with_transport(chip.clone()).unwrap();
+    chip.take_operations();
+    (flash, chip)
+}
+
+fn octo(dtr: bool) -> (OpiFlashMemory, SimulatedFlash) {
+    let (flash, chip) = spi();
+    let flash = if dtr {
+        flash.into_octo_dtr().unwrap()
+    } else {
+        flash.into_octo().unwrap()
+    };
+    (flash, chip)
+}
+
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 + 3) as u8).collect()
+}
+
+#[test]
+fn identifies_chip_and_geometry() {
+    let (mut flash, chip) = spi();
+    assert_eq!(flash.read_id().unwrap(), [0xC2, 0x81, 0x39]);
+    assert_eq!(flash.chip().name, "MX25UW25645G");
+    assert_eq!(flash.geometry(), FlashGeometry::MX25UW25645G);
+    assert_eq!(chip.ignored(), 0);
+}
+
+#[test]
+fn rejects_sfdp_erase_types_without_the_chip_commands() {
+    // Erase type 1 at DWORD 8 of the basic flash parameter table.
+    const ERASE_TYPES: u32 = 0x30 + 4 * 7;
+    let chip = SimulatedFlash::new();
+    // 4KB with 0x20 and 32KB with 0x52, but no 64KB block erase.
+    chip.patch_sfdp(ERASE_TYPES, &0x520F_200Cu32.to_le_bytes());
+    assert!(matches!(
+        SpiFlashMemory::with_transport(chip.clone()),
+        Err(FlashError::InvalidSfdp)
+    ));
+
+    // The sector erase command erasing 64KB instead of 4KB.
+    chip.patch_sfdp(ERASE_TYPES, &0xD810_2010u32.to_le_bytes());
+    assert!(matches!(
+        SpiFlashMemory::with_transport(chip),
+        Err(FlashError::InvalidSfdp)
+    ));
+}
+
+#[test]
+fn write_splits_at_page_boundaries() {
+    let (mut flash, chip) = spi();
+    let data = pattern(600);
+    flash.write_memory(0x1F0, &data).unwrap();
+
+    assert_eq!(
+        chip.take_operations(),
+        [
+            Operation::Program {
+                address: 0x1F0,
+                len: 16
+            },
+            Operation::Program {
+                address: 0x200,
+                len: 256
+            },
+            Operation::Program {
+                address: 0x300,
+                len: 256
+            },
+            Operation::Program {
+                address: 0x400,
+                len: 72
+            },
+        ]
+    );
+    let mut read = vec![0; data.len()];
+    flash.read_memory(0x1F0, &mut read).unwrap();
+    assert_eq!(read, data);
+    assert_eq!(chip.ignored(), 0);
+}
+
+#[test]
+fn programming_only_clears_bits() {
+    let (mut flash, chip) = spi();
+    flash.write_memory(0x1000, &[0b1010_1010]).unwrap();
+    flash.write_memory(0x1000, &[0b0110_0110]).unwrap();
+    assert_eq!(chip.contents(0x1000, 1), [0b0010_0010]);
+
+    flash.erase_sector(0x1000).unwrap();
+    assert_eq!(chip.contents(0x1000, 1), [0xFF]);
+}
+
+#[test]
+fn erase_range_mixes_sectors_and_blocks() {
+    let (mut flash, chip) = spi();
+    chip.load(0x0E000, &[0; 0x24000]);
+    flash.erase_range(0x0F000, 0x22000).unwrap();
+
+    assert_eq!(
+        chip.take_operations(),
+        [
+            Operation::Erase {
+                address: 0x0F000,
+                size: 0x1000
+            },
+            Operation::Erase {
+                address: 0x10000,
+                size: 0x10000
+            },
+            Operation::Erase {
+                address: 0x20000,
+                size: 0x10000
+            },
+            Operation::Erase {
+                address: 0x30000,
+                size: 0x1000
+            },
+        ]
+    );
+    assert!(chip.contents(0x0F000, 0x22000).iter().all(|&b| b == 0xFF));
+    assert_eq!(chip.contents(0x0E000, 0x1000), [0; 0x1000]);
+    assert_eq!(chip.contents(0x31000, 0x1000), [0; 0x1000]);
+}
+
+#[test]
+fn block_protection_fails_writes() {
+    let (mut flash, chip) = spi();
+    // Level 1 protects the top 64KB block.
+    flash.set_block_protection(1).unwrap();
+    let top = 32 * 1024 * 1024 - 0x10000;
+
+    assert_eq!(flash.erase_sector(top), Err(FlashError::EraseFailed));
+    assert_eq!(
+        flash.write_memory(top, &[0]),
+        Err(FlashError::ProgramFailed)
+    );
+    assert_eq!(flash.erase_chip(), Err(FlashError::EraseFailed));
+    assert!(chip.take_operations().is_empty());
+
+    flash.write_memory(top - 1, &[0]).unwrap();
+    assert_eq!(chip.contents(top - 1, 2), [0x00, 0xFF]);
+}
+
+#[test]
+fn octal_str_round_trip() {
+    let (mut flash, chip) = octo(false);
+    assert_eq!(chip.mode(), BusMode::OctalStr);
+    assert_eq!(flash.read_id().unwrap(), [0xC2, 0x81, 0x39]);
+
+    let data = pattern(300);
+    flash.erase_sector(0x2000).unwrap();
+    flash.write_memory(0x2081, &data).unwrap();
+    let mut read = vec![0; data.len()];
+    flash.read_memory(0x2081, &mut read).unwrap();
+    assert_eq!(read, data);
+
+    let mut flash = flash.into_spi().unwrap();
+    assert_eq!(chip.mode(), BusMode::Spi);
+    flash.read_memory(0x2081, &mut read).unwrap();
+    assert_eq!(read, data);
+    assert_eq!(chip.ignored(), 0);
+}
+
+#[test]
+fn octal_dtr_handles_odd_addresses_and_lengths() {
+    let (mut flash, chip) = octo(true);
+    assert_eq!(chip.mode(), BusMode::OctalDtr);
+    assert!(flash.is_dtr());
+    assert_eq!(flash.read_id().unwrap(), [0xC2, 0x81, 0x39]);
+
+    let data = pattern(255);
+    flash.write_memory(0x3001, &data).unwrap();
+    assert_eq!(chip.contents(0x3001, data.len()), data);
+    assert_eq!(chip.contents(0x3000, 1), [0xFF]);
+
+    let mut read = vec![0; 101];
+    flash.read_memory(0x3003, &mut read).unwrap();
+    assert_eq!(read, data[2..103]);
+
+    flash.into_spi().unwrap();
+    assert_eq!(chip.mode(), BusMode::Spi);
+    assert_eq!(chip.ignored(), 0);
+}
+
+#[test]
+fn failed_mode_switch_returns_the_driver() {
+    let (mut flash, chip) = octo(true);
+    // SAFETY: the slice is dropped right away.
+    unsafe { flash.enable_mm().unwrap().leak() };
+
+    let error = flash.into_spi().err().unwrap();
+    assert_eq!(error.error, FlashError::WrongMode);
+    assert!(error.flash.is_dtr());
+    assert_eq!(chip.mode(), BusMode::OctalDtr);
+    assert_eq!(chip.ignored(), 0);
+}
+
+#[test]
+fn timing_sets_prescaler_and_dummy_cycles() {
+    let (mut flash, chip) = spi();
+    flash.set_timing(TimingProfile::PLL2_114MHZ).unwrap();
+    assert_eq!(chip.prescaler(), 0);
+
+    let mut flash = flash.into_octo().unwrap();
+    // 14 dummy cycles are encoded as 3.
+    assert_eq!(chip.cr2(0x300), 3);
+    assert_eq!(flash.geometry().opi_read_dummy_cycles, 14);
+
+    let mut read = [0; 4];
+    flash.read_memory(0, &mut read).unwrap();
+    assert_eq!(chip.ignored(), 0);
+}
+
+#[test]
+fn identify_keeps_custom_timeouts() {
+    let (mut flash, _chip) = spi();
+    let defaults = flash.timeouts();
+    flash.identify().unwrap();
+    assert_eq!(flash.timeouts(), defaults);
+
+    let custom = Timeouts {
+        page_program_ms: defaults.page_program_ms * 2,
+        ..defaults
+    };
+    flash.set_timeouts(custom);
+    flash.identify().unwrap();
+    assert_eq!(flash.timeouts(), custom);
+}
+
+#[test]
+fn update_rewrites_only_changed_sectors() {
+    let (mut flash, chip) = spi();
+    let mut scratch = vec![0; 4096];
+    flash.write_memory(0x5000, &pattern(16)).unwrap();
+    chip.take_operations();
+
+    // Even clearing bits erases and rewrites the sector, so no page is programmed twice.
+    flash.update(0x5000, &[0; 4], &mut scratch).unwrap();
+    assert_eq!(
+        chip.take_operations()[0],
+        Operation::Erase {
+            address: 0x5000,
+            size: 0x1000
+        }
+    );
+
+    // Unchanged bytes aren't written.
+    flash.update(0x5000, &[0; 4], &mut scratch).unwrap();
+    assert_eq!(chip.take_operations(), []);
+
+    flash.update(0x5008, &[0xFF; 4], &mut scratch).unwrap();
+    assert_eq!(
+        chip.take_operations()[0],
+        Operation::Erase {
+            address: 0x5000,
+            size: 0x1000
+        }
+    );
+    let mut expected = pattern(16);
+    expected[..4].fill(0);
+    expected[8..12].fill(0xFF);
+    assert_eq!(chip.contents(0x5000, 16), expected);
+}
+
+#[test]
+fn stuck_chip_times_out() {
+    let (mut flash, chip) = spi();
+    chip.set_busy_reads(u32::MAX);
+    assert_eq!(flash.erase_sector(0), Err(FlashError::Timeout));
+}
+
+#[test]
+fn mapped_reads_see_the_array() {
+    let (mut flash, chip) = octo(true);
+    chip.load(0x100, b"memory mapped");
+    {
+        let mapped = flash.enable_mm().unwrap();
+        assert_eq!(mapped.read(0x100, 13).unwrap(), b"memory mapped");
+    }
+    assert_eq!(chip.ignored(), 0);
+
+    let mut read = [0; 6];
+    flash.read_memory(0x100, &mut read).unwrap();
+    assert_eq!(&read, b"memory");
+}
+
+#[test]
+fn registers_follow_mode_changes() {
+    let (mut flash, chip) = spi();
+    assert_eq!(flash.read_bus_mode().unwrap(), BusMode::Spi);
+    flash.set_timing(TimingProfile::PLL2_114MHZ).unwrap();
+
+    let mut flash = flash.into_octo_dtr().unwrap();
+    assert_eq!(flash.read_bus_mode().unwrap(), BusMode::OctalDtr);
+    let dummy = flash.read_cr2_register::<Cr2DummyCycles>().unwrap();
+    assert_eq!(dummy.cycles(), flash.geometry().opi_read_dummy_cycles);
+
+    flash
+        .modify_cr2_register::<Cr2Dqs>(|dqs| dqs.with_pre_cycle(true))
+        .unwrap();
+    let dqs = flash.read_cr2_register::<Cr2Dqs>().unwrap();
+    assert!(dqs.pre_cycle());
+    assert_eq!(chip.cr2(0x200), Cr2Dqs::DQSPRC);
+    assert_eq!(chip.ignored(), 0);
+}
+
+#[test]
+fn status_configuration_writes_are_verified() {
+    let (mut flash, _chip) = spi();
+    flash
+        .modify_status_configuration(|sr, cr| {
+            (sr.with_block_protection(3), cr.with_bottom_protection(true))
+        })
+        .unwrap();
+    assert_eq!(flash.read_status().unwrap().block_protection(), 3);
+
+    // TB is one-time programmable, the chip keeps it set.
+    assert_eq!(
+        flash.modify_status_configuration(|sr, cr| (sr, cr.with_bottom_protection(false))),
+        Err(FlashError::VerifyFailed)
+    );
+    assert!(flash.read_configuration().unwrap().bottom_protection());
+}
+
+#[test]
+fn new_driver_recovers_chip_left_in_octal_mode() {
+    for dtr in [false, true] {
+        // A warm reset leaves the chip in octal mode while the MCU starts over.
+        let (_, chip) = octo(dtr);
+        let mut flash = SpiFlashMemory::with_transport(chip.clone()).unwrap();
+        assert_eq!(chip.mode(), BusMode::Spi);
+        assert_eq!(flash.read_id().unwrap(), [0xC2, 0x81, 0x39]);
+        assert_eq!(flash.geometry(), FlashGeometry::MX25UW25645G);
+    }
+}
+
+#[test]
+fn recover_reports_the_mode_the_chip_was_in() {
+    let (flash, chip) = spi();
+    let (flash, mode) = flash.recover().unwrap();
+    assert_eq!(mode, Some(BusMode::Spi));
+    assert_eq!(chip.ignored(), 0);
+
+    let stale = SpiFlashMemory::with_transport(chip.clone()).unwrap();
+    let _octal = flash.into_octo_dtr().unwrap();
+    let (mut stale, mode) = stale.recover().unwrap();
+    assert_eq!(mode, Some(BusMode::OctalDtr));
+    assert_eq!(chip.mode(), BusMode::Spi);
+    assert_eq!(stale.read_id().unwrap(), [0xC2, 0x81, 0x39]);
+}
@@ -0,0 +1,78 @@
+//! Trace recording and replay against the simulated chip, run with `cargo test-host`.
+
+#![cfg(all(feature = "trace", not(feature = "stm32")))]
+
+use flash_lib::SpiFlashMemory;
+use flash_lib::sim::SimulatedFlash;
+use flash_lib::trace::{TRACE_LEN, TraceKind, Traced};
+use flash_lib::transport::{AddressSize, XspiWidth};
+
+fn traced() -> SpiFlashMemory<Traced<SimulatedFlash>> {
+    let mut flash = SpiFlashMemory::with_transport(Traced::new(SimulatedFlash::new())).unwrap();
+    flash.clear_trace();
+    flash
+}
+
+#[test]
+fn records_transactions() {
+    let mut flash = traced();
+    flash.write_memory(0x1000, &[1, 2, 3]).unwrap();
+
+    let entries: Vec<_> = flash.trace().iter().collect();
+    // WREN, page program, status read, polling, security register read.
+    assert_eq!(entries.len(), 5);
+    assert_eq!(entries[0].kind, TraceKind::Command);
+    assert_eq!(entries[0].opcode(), Some(0x06));
+
+    let program = entries[1];
+    assert_eq!(program.kind, TraceKind::Write);
+    assert_eq!(program.opcode(), Some(0x12));
+    assert_eq!(program.transaction.adwidth, XspiWidth::SING);
+    assert_eq!(program.transaction.adsize, AddressSize::_32bit);
+    assert_eq!(program.transaction.address, Some(0x1000));
+    assert_eq!(program.data(), [1, 2, 3]);
+
+    assert!(matches!(entries[3].kind, TraceKind::Poll { mask: 1, .. }));
+    assert_eq!(entries[3].opcode(), Some(0x05));
+    assert!(entries.iter().all(|entry| !entry.failed));
+}
+
+#[test]
+fn keeps_the_latest_transactions() {
+    let mut flash = traced();
+    for _ in 0..TRACE_LEN {
+        flash.read_id().unwrap();
+    }
+    flash.read_sr().unwrap();
+
+    assert_eq!(flash.trace().iter().count(), TRACE_LEN);
+    assert_eq!(flash.trace().dropped(), 1);
+    assert_eq!(flash.trace().iter().last().unwrap().opcode(), Some(0x05));
+}
+
+#[test]
+fn replays_against_the_model() {
+    let chip = SimulatedFlash::new();
+    let flash = SpiFlashMemory::with_transport(Traced::new(chip.clone())).unwrap();
+    let mut flash = flash.into_octo_dtr().unwrap();
+    flash.write_memory(0x2000, &[0xA5; 8]).unwrap();
+    let trace = flash.trace().clone();
+    assert_eq!(trace.dropped(), 0);
+
+    let mut replayed = SimulatedFlash::new();
+    assert_eq!(trace.replay(&mut replayed), None);
+    assert_eq!(replayed.mode(), chip.mode());
+    assert_eq!(replayed.contents(0x2000, 8), [0xA5; 8]);
+
+    // The model starts in SPI mode and ignores the octal transactions.
+    let mut replayed = SimulatedFlash::new();
+    let mut octal = trace.clone();
+    octal.clear();
+    for entry in trace
+        .iter()
+        .skip_while(|entry| entry.transaction.iwidth != XspiWidth::OCTO)
+    {
+        octal.push(*entry);
+    }
+    assert_eq!(octal.replay(&mut replayed), Some(0));
+}
@@ -0,0 +1,51 @@
+//! Writes through the execute in place service, run with `cargo test-host`.
+
+#![cfg(not(feature = "stm32"))]
+
+use std::slice;
+
+use flash_lib::sim::SimulatedFlash;
+use flash_lib::xip::XipFlash;
+use flash_lib::{FlashError, SpiFlashMemory};
+
+fn xip() -> (XipFlash, SimulatedFlash) {
+    let chip = SimulatedFlash::new();
+    let flash =
+        XipFlash::take_over(|| Ok(SpiFlashMemory::with_transport(chip.clone())?.into_octo()?))
+            .unwrap();
+    (flash, chip)
+}
+
+#[test]
+fn writes_are_visible_in_the_mapped_flash() {
+    let (mut flash, chip) = xip();
+    flash.erase_sector(0x1000).unwrap();
+    flash.write_memory(0x1004, b"stored").unwrap();
+    assert_eq!(flash.read(0x1000, 10).unwrap(), b"\xFF\xFF\xFF\xFFstored");
+
+    let mut scratch = vec![0; flash.geometry().sector_size];
+    flash.update(0x1004, b"STORED", &mut scratch).unwrap();
+    assert_eq!(flash.read(0x1004, 6).unwrap(), b"STORED");
+    assert_eq!(chip.ignored(), 0);
+}
+
+#[test]
+fn failed_operations_leave_the_flash_mapped() {
+    let (mut flash, chip) = xip();
+    let top = (flash.geometry().size - 0x1000) as u32;
+    assert_eq!(flash.erase_range(top, 0x2000), Err(FlashError::OutOfBounds));
+
+    flash.write_memory(0x2000, &[0x5A]).unwrap();
+    assert_eq!(flash.read(0x2000, 1).unwrap(), [0x5A]);
+    assert_eq!(chip.ignored(), 0);
+}
+
+#[test]
+fn data_in_the_mapped_flash_is_rejected() {
+    let (mut flash, chip) = xip();
+    let mapped = flash.as_slice().as_ptr();
+    // SAFETY: the simulated array outlives the slice and isn't written while it's alive.
+    let data = unsafe { slice::from_raw_parts(mapped, 16) };
+    assert_eq!(flash.write_memory(0x3000, data), Err(FlashError::WrongMode));
+    assert!(chip.take_operations().is_empty());
+}
@@ -0,0 +1,58 @@
+/* Code and data used while the XSPI flash is unmapped, see `flash_lib::xip`.
+ *
+ * Include this from the memory.x of an application executing in place, after defining the ITCM
+ * region, and call `flash_lib::xip::load_ram_code` first thing in `main`. Like memory.x, it has
+ * to come before the SECTIONS of cortex-m-rt's `link.x`, whose `.text` would take the code below
+ * otherwise.
+ *
+ * The code running while the flash is unmapped and the statics it reads are placed here by
+ * their `.itcm_text`/`.itcm_data` section. The functions they call are matched by their mangled
+ * names, which requires legacy symbol mangling (`-C symbol-mangling-version=legacy`, see the
+ * crate's `.cargo/config.toml`): the length-prefixed path, or the `$LT$type$u20$as$u20$trait$GT$`
+ * of a trait impl. The Cortex-M7 reads data from the ITCM as well. Anonymous constants have no
+ * such name and can't be matched. `check_xip.py` fails for any call or data read outside the
+ * ITCM, and `flash_test` faults on one.
+ */
+SECTIONS
+{
+    .itcm_text : ALIGN(4)
+    {
+        __sitcm_text = .;
+        *(.itcm_text .itcm_text.*)
+        *(.itcm_data .itcm_data.*)
+        /* This crate, including the generic code instantiated by the application. */
+        *(.text._ZN9flash_lib*)
+        *(.text._ZN*_$LT$flash_lib..*)
+        *(.text._ZN*$u20$as$u20$flash_lib..*)
+        /* Embassy's XSPI driver, and the GPIO and RCC code it calls when it is created. */
+        *(.text._ZN13embassy_stm324xspi*)
+        *(.text._ZN*_$LT$embassy_stm32..xspi..*)
+        *(.text._ZN13embassy_stm324gpio*)
+        *(.text._ZN*$u20$as$u20$embassy_stm32..gpio..*)
+        *(.text._ZN13embassy_stm323rcc*)
+        *(.text._ZN*$u20$as$u20$embassy_stm32..rcc..*)
+        /* Register accessors of the PAC, in case they aren't inlined. */
+        *(.text._ZN13stm32_metapac*)
+        /* Critical sections, nested in the one the service runs in. */
+        *(.text._critical_section_1_0_acquire .text._critical_section_1_0_release)
+        *(.text.__cpsid .text.__cpsie .text.__primask_r)
+        /* Memory routines and other compiler intrinsics, from the precompiled `compiler_builtins`
+           with its v0 mangled names, or from a C library. */
+        *(.text._R*17compiler_builtins*)
+        *(.text.memcpy .text.memmove .text.memset .text.memcmp .text.bcmp)
+        *(.text.__aeabi_memcpy* .text.__aeabi_memmove* .text.__aeabi_memset* .text.__aeabi_memclr*)
+        /* Named statics read by the code above. */
+        *(.rodata._ZN9flash_lib*)
+        *(.rodata._ZN13embassy_stm324xspi*)
+        *(.rodata._ZN13embassy_stm324gpio*)
+        *(.rodata._ZN13embassy_stm323rcc*)
+        . = ALIGN(4);
+        __eitcm_text = .;
+    } > ITCM AT > FLASH
+    __siitcm_text = LOADADDR(.itcm_text);
+}
+INSERT BEFORE .text;
+
+/* The section is loaded from right behind the vector table, where cortex-m-rt would start
+   `.text`, so `.text` follows it. */
+_stext = __siitcm_text + SIZEOF(.itcm_text);